@@ -1,16 +1,31 @@
-use std::io;
+use std::{env, io};
 
-use system76_keyboard_configurator_backend::Benchmark;
+use system76_keyboard_configurator_backend::{Benchmark, BenchmarkConfig, Layout};
 
-fn benchmark() -> io::Result<()> {
-    let benchmark = Benchmark::new()?;
+fn benchmark(board: &str) -> io::Result<()> {
+    let usb_hub = Layout::from_board(board, "")
+        .ok()
+        .and_then(|layout| layout.meta.usb_hub)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no USB hub layout", board),
+            )
+        })?;
+    let benchmark = Benchmark::new(&usb_hub, &BenchmarkConfig::default())?;
     for (port_desc, port_result) in benchmark.port_results.iter() {
-        eprintln!("{}: {:.2?}", port_desc, port_result);
+        match port_result {
+            Ok(ok) => eprintln!("{}: {}", port_desc, ok),
+            Err(err) => eprintln!("{}: {}", port_desc, err),
+        }
     }
 
     Ok(())
 }
 
 fn main() {
-    benchmark().unwrap();
+    let board = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "system76/launch_2".to_string());
+    benchmark(&board).unwrap();
 }
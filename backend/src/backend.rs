@@ -3,26 +3,32 @@ use futures::{
     stream::{FusedStream, Stream},
 };
 use std::{
+    path::PathBuf,
     pin::Pin,
     process,
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
+    thread,
     time::Duration,
 };
 
 use crate::daemon::*;
-use crate::{Board, BoardEvent, Bootloaded};
+use crate::{
+    flash_firmware, fwupd_update, Board, BoardEvent, Bootloaded, FlashEvent, Fwupd, FwupdDevice,
+    FwupdEvent, Programmer,
+};
 
 #[derive(Clone, Debug)]
 pub enum Event {
     BoardLoading,
     BoardLoadingDone,
-    BoardNotUpdated,
     Board(BoardId, BoardEvent),
     BoardAdded(Board),
     BoardRemoved(BoardId),
     BootloadedAdded(Bootloaded),
     BootloadedRemoved,
+    Flash(FlashEvent),
+    Fwupd(FwupdEvent),
 }
 
 #[derive(Debug)]
@@ -50,6 +56,8 @@ impl Unpin for Events {}
 struct BackendInner {
     thread_client: Arc<ThreadClient>,
     executor: futures::executor::ThreadPool,
+    event_sender: async_mpsc::UnboundedSender<Event>,
+    matrix_get_rate: Mutex<Option<Duration>>,
 }
 
 #[derive(Clone, Debug)]
@@ -66,12 +74,14 @@ impl Backend {
             .create()
             .unwrap();
 
-        let thread_client = ThreadClient::new(Box::new(daemon), sender);
+        let thread_client = ThreadClient::new(Box::new(daemon), sender.clone());
 
         Ok((
             Self(Arc::new(BackendInner {
                 thread_client,
                 executor,
+                event_sender: sender,
+                matrix_get_rate: Mutex::new(None),
             })),
             Events(receiver),
         ))
@@ -116,7 +126,59 @@ impl Backend {
         });
     }
 
+    /// Flash the Intel HEX file at `path` to a keyboard in bootloader mode, in the background
+    ///
+    /// Progress and the result are sent as `Event::Flash`.
+    pub fn flash(&self, mut programmer: Box<dyn Programmer>, target: Bootloaded, path: PathBuf) {
+        let event_sender = self.0.event_sender.clone();
+        // Programmers block for several seconds, so don't tie up the executor
+        thread::spawn(move || {
+            let res = flash_firmware(&mut *programmer, target, &path, |event| {
+                let _ = event_sender.unbounded_send(Event::Flash(event));
+            });
+            if let Err(err) = res {
+                error!("Failed to flash '{}': {}", path.display(), err);
+            }
+        });
+    }
+
+    /// Look up installed firmware and available updates with fwupd, in the background
+    ///
+    /// The result is sent as `Event::Fwupd(FwupdEvent::Devices(_))`.
+    pub fn fwupd_check(&self, mut fwupd: Box<dyn Fwupd>) {
+        let event_sender = self.0.event_sender.clone();
+        // fwupd may check remotes, which can take a while
+        thread::spawn(move || {
+            let devices = fwupd.devices();
+            if let Err(err) = &devices {
+                error!("Failed to get fwupd devices: {}", err);
+            }
+            let _ = event_sender.unbounded_send(Event::Fwupd(FwupdEvent::Devices(devices)));
+        });
+    }
+
+    /// Install the firmware update for `device` with fwupd, in the background
+    ///
+    /// Progress and the result are sent as `Event::Fwupd`.
+    pub fn fwupd_update(&self, mut fwupd: Box<dyn Fwupd>, device: FwupdDevice) {
+        let event_sender = self.0.event_sender.clone();
+        thread::spawn(move || {
+            let res = fwupd_update(&mut *fwupd, &device, |event| {
+                let _ = event_sender.unbounded_send(Event::Fwupd(event));
+            });
+            if let Err(err) = res {
+                error!("Failed to update {}: {}", device.name, err);
+            }
+        });
+    }
+
+    /// Rate last set with `set_matrix_get_rate`
+    pub fn matrix_get_rate(&self) -> Option<Duration> {
+        *self.0.matrix_get_rate.lock().unwrap()
+    }
+
     pub fn set_matrix_get_rate(&self, rate: Option<Duration>) {
+        *self.0.matrix_get_rate.lock().unwrap() = rate;
         let self_ = self.clone();
         self.0.executor.spawn_ok(async move {
             let _ = self_.0.thread_client.set_matrix_get_rate(rate).await;
@@ -3,13 +3,101 @@ use std::os::unix::fs::OpenOptionsExt;
 use std::{
     alloc::{alloc, dealloc, handle_alloc_error, Layout},
     fs,
-    io::{self, Read},
+    io::{self, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     slice, time,
 };
 
+use super::{BenchmarkConfig, PortBenchmark, SpeedStats};
+
 const ALIGN: usize = 4096;
 const SIZE: usize = ALIGN * 1024;
+const SIZE_MB: f64 = (SIZE / (1024 * 1024)) as f64;
+
+/// Name of the temporary file used for write tests, in the root of a mounted filesystem
+const SCRATCH_NAME: &str = ".system76-keyboard-configurator-benchmark";
+
+/// Open `path` for direct IO, so transfers bypass the page cache and reach the device
+///
+/// If the filesystem doesn't support `O_DIRECT`, like some tmpfs, this fails unless
+/// `allow_buffered` is set, in which case it falls back to buffered IO. Returns if the file uses
+/// direct IO.
+fn open_direct(
+    open_options: &fs::OpenOptions,
+    path: &Path,
+    allow_buffered: bool,
+) -> io::Result<(fs::File, bool)> {
+    #[cfg(target_os = "linux")]
+    match open_options.clone().custom_flags(libc::O_DIRECT).open(path) {
+        Err(err) if err.raw_os_error() == Some(libc::EINVAL) && allow_buffered => {
+            warn!(
+                "{}: O_DIRECT not supported, using buffered IO",
+                path.display()
+            );
+        }
+        res => return res.map(|file| (file, true)),
+    }
+    #[cfg(not(target_os = "linux"))]
+    if !allow_buffered {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "direct IO not supported",
+        ));
+    }
+    Ok((open_options.open(path)?, false))
+}
+
+/// Buffer aligned for direct IO, as `O_DIRECT` requires
+struct AlignedBuf {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl AlignedBuf {
+    fn new() -> Self {
+        let layout = Layout::from_size_align(SIZE, ALIGN).unwrap();
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        Self { ptr, layout }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, SIZE) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// Offsets of `samples` reads spread evenly over a device of `len` bytes, aligned for direct IO
+fn read_offsets(len: u64, samples: usize) -> Vec<u64> {
+    let last = len.saturating_sub(SIZE as u64) / ALIGN as u64 * ALIGN as u64;
+    (0..samples)
+        .map(|i| {
+            if samples > 1 {
+                last * i as u64 / (samples - 1) as u64 / ALIGN as u64 * ALIGN as u64
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+/// Check if `source` from the mount table is `block` or one of its partitions
+fn is_same_disk(source: &str, block: &str) -> bool {
+    match source.strip_prefix(block) {
+        Some(rest) => rest
+            .trim_start_matches('p')
+            .chars()
+            .all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
 
 #[derive(Eq, Ord, PartialEq, PartialOrd)]
 pub struct BlockDev(PathBuf);
@@ -23,35 +111,151 @@ impl BlockDev {
         &self.0
     }
 
-    pub fn benchmark(&self) -> io::Result<f64> {
-        let mut open_options = fs::OpenOptions::new();
-        open_options.read(true);
-        #[cfg(target_os = "linux")]
-        open_options.custom_flags(libc::O_DIRECT);
+    pub fn benchmark(&self, config: &BenchmarkConfig) -> io::Result<PortBenchmark> {
+        let mut buf = AlignedBuf::new();
+        let (read, read_direct) = self.benchmark_read(config, &mut buf)?;
+        // A failed write test is reported with the read result, rather than discarding it
+        let (write, write_direct) = if config.write_test {
+            match self.benchmark_write(config, &mut buf) {
+                Ok((write, direct)) => (Some(Ok(write)), direct),
+                Err(err) => (Some(Err(err.to_string())), true),
+            }
+        } else {
+            (None, true)
+        };
+        Ok(PortBenchmark {
+            read,
+            write,
+            direct: read_direct && write_direct,
+        })
+    }
 
-        let mut file = open_options.open(self.path())?;
+    /// Time reads spread across the device, returning if they used direct IO
+    fn benchmark_read(
+        &self,
+        config: &BenchmarkConfig,
+        buf: &mut AlignedBuf,
+    ) -> io::Result<(SpeedStats, bool)> {
+        let (mut file, direct) = open_direct(
+            fs::OpenOptions::new().read(true),
+            self.path(),
+            config.allow_buffered,
+        )?;
+        let len = file.seek(SeekFrom::End(0))?;
 
-        // Buffer needs to be aligned for direct reads
-        let layout = Layout::from_size_align(SIZE, ALIGN).unwrap();
-        let ptr = unsafe { alloc(layout) };
-        if ptr.is_null() {
-            handle_alloc_error(layout);
+        // Warm-up reads wake the device from any power saving state, and are not counted
+        for _ in 0..config.warmup {
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(buf.as_mut_slice())?;
         }
 
-        let (res, elapsed) = {
-            let data = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, SIZE) };
-
+        let mut samples = Vec::with_capacity(config.samples);
+        for offset in read_offsets(len, config.samples) {
+            file.seek(SeekFrom::Start(offset))?;
             let start = time::Instant::now();
-            (file.read(data), start.elapsed())
-        };
-
-        unsafe {
-            dealloc(ptr, layout);
+            file.read_exact(buf.as_mut_slice())?;
+            samples.push(SIZE_MB / start.elapsed().as_secs_f64());
         }
 
-        // Do this after free to ensure no memory leaks
-        res?;
+        let stats = SpeedStats::new(samples)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no samples"))?;
+        Ok((stats, direct))
+    }
+
+    /// Find where a filesystem on this device is mounted, for the write test
+    fn mount_point(&self) -> io::Result<PathBuf> {
+        let block = self.path().to_string_lossy();
+        let mounts = fs::read_to_string("/proc/self/mounts")?;
+        mounts
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                Some((fields.next()?, fields.next()?))
+            })
+            .find(|(source, _)| is_same_disk(source, &block))
+            // Mount points escape spaces as octal
+            .map(|(_, target)| PathBuf::from(target.replace("\\040", " ")))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no mounted filesystem for write test",
+                )
+            })
+    }
+
+    /// Time writes to a scratch file on a mounted filesystem, leaving existing data untouched,
+    /// returning if they used direct IO
+    fn benchmark_write(
+        &self,
+        config: &BenchmarkConfig,
+        buf: &mut AlignedBuf,
+    ) -> io::Result<(SpeedStats, bool)> {
+        let path = self.mount_point()?.join(SCRATCH_NAME);
+        let res = write_samples(&path, config, buf);
+
+        // Remove the scratch file even if the test failed
+        let _ = fs::remove_file(&path);
+
+        let (samples, direct) = res?;
+        let stats = SpeedStats::new(samples)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no samples"))?;
+        Ok((stats, direct))
+    }
+}
+
+fn write_samples(
+    path: &Path,
+    config: &BenchmarkConfig,
+    buf: &mut AlignedBuf,
+) -> io::Result<(Vec<f64>, bool)> {
+    let (mut file, direct) = open_direct(
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true),
+        path,
+        config.allow_buffered,
+    )?;
+    buf.as_mut_slice().fill(0x76);
+
+    for _ in 0..config.warmup {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(buf.as_mut_slice())?;
+        file.sync_data()?;
+    }
+
+    let mut samples = Vec::with_capacity(config.samples);
+    for i in 0..config.samples {
+        file.seek(SeekFrom::Start((i * SIZE) as u64))?;
+        let start = time::Instant::now();
+        file.write_all(buf.as_mut_slice())?;
+        file.sync_data()?;
+        samples.push(SIZE_MB / start.elapsed().as_secs_f64());
+    }
+    Ok((samples, direct))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_dev_offsets() {
+        let len = 64 * SIZE as u64 + 1000;
+        let offsets = read_offsets(len, 5);
+        assert_eq!(offsets.len(), 5);
+        assert_eq!(offsets[0], 0);
+        assert!(offsets.iter().all(|x| x % ALIGN as u64 == 0));
+        assert!(offsets.windows(2).all(|x| x[0] < x[1]));
+        assert!(offsets[4] + SIZE as u64 <= len);
+
+        // Devices smaller than one read only have one offset to use
+        assert_eq!(read_offsets(1000, 3), vec![0, 0, 0]);
 
-        Ok(4.0 / elapsed.as_secs_f64())
+        assert!(is_same_disk("/dev/sdb", "/dev/sdb"));
+        assert!(is_same_disk("/dev/sdb1", "/dev/sdb"));
+        assert!(is_same_disk("/dev/nvme0n1p2", "/dev/nvme0n1"));
+        assert!(!is_same_disk("/dev/sdba1", "/dev/sdb"));
+        assert!(!is_same_disk("/dev/sda1", "/dev/sdb"));
     }
 }
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, path::Path};
+
+use super::{usb_hub::UsbHub, UsbHubLayout, USB_2_SPEED_NAME, USB_3_SPEED_NAME};
+
+/// Device plugged into a hub port
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UsbPortDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    /// Negotiated link speed in Mbps, as reported by sysfs
+    pub speed: Option<String>,
+}
+
+impl UsbPortDevice {
+    /// Name of the device, from its string descriptors or else its IDs
+    pub fn name(&self) -> String {
+        match (&self.manufacturer, &self.product) {
+            (Some(manufacturer), Some(product)) => format!("{} {}", manufacturer, product),
+            (None, Some(product)) => product.clone(),
+            _ => format!("{:04x}:{:04x}", self.vendor_id, self.product_id),
+        }
+    }
+
+    /// Name of the negotiated link speed, like "High Speed (480 Mbps)"
+    pub fn speed_desc(&self) -> Option<String> {
+        let speed = self.speed.as_ref()?;
+        let name = match speed.as_str() {
+            "1.5" => "Low Speed",
+            "12" => "Full Speed",
+            "480" => "High Speed",
+            "5000" => "SuperSpeed",
+            "10000" => "SuperSpeed+",
+            "20000" => "SuperSpeed+ 20Gbps",
+            _ => return Some(format!("{} Mbps", speed)),
+        };
+        Some(format!("{} ({} Mbps)", name, speed))
+    }
+}
+
+impl fmt::Display for UsbPortDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(speed) = self.speed_desc() {
+            write!(f, ", {}", speed)?;
+        }
+        Ok(())
+    }
+}
+
+/// External port of a hub, and what is plugged into it
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UsbPortStatus {
+    /// Description of the port, matching the keys of `Benchmark::port_results`
+    pub port_desc: String,
+    /// Hub port number
+    pub port_name: String,
+    /// Port is on a USB 3 hub, rather than a USB 2 hub
+    pub usb_3: bool,
+    pub device: Option<UsbPortDevice>,
+}
+
+/// Devices plugged into the external ports of a board's USB hub
+///
+/// Only reads sysfs attributes readable by any user, so this does not need the root daemon.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UsbHubStatus {
+    pub ports: Vec<UsbPortStatus>,
+}
+
+impl UsbHubStatus {
+    pub fn new(layout: &UsbHubLayout) -> io::Result<Self> {
+        Self::with_root(Path::new("/sys"), layout)
+    }
+
+    /// Read status from sysfs mounted at `sys_root`
+    pub fn with_root(sys_root: &Path, layout: &UsbHubLayout) -> io::Result<Self> {
+        let hubs = UsbHub::probe(sys_root)?;
+
+        let mut ports = Vec::new();
+        for (speed_name, usb_3) in [(USB_2_SPEED_NAME, false), (USB_3_SPEED_NAME, true)] {
+            let speed_hubs = hubs
+                .iter()
+                .filter(|hub| hub.is_usb_3() == usb_3)
+                .collect::<Vec<_>>();
+            for (hub_index, hub) in speed_hubs.iter().enumerate() {
+                let mut hub_ports = hub.ports()?;
+                for (port_name, port_desc) in layout.ports.iter() {
+                    let device = hub_ports
+                        .remove(port_name)
+                        .filter(|dev| dev.path().is_dir())
+                        .map(|dev| {
+                            Ok::<_, io::Error>(UsbPortDevice {
+                                vendor_id: dev.vendor_id()?,
+                                product_id: dev.product_id()?,
+                                manufacturer: dev.manufacturer(),
+                                product: dev.product(),
+                                speed: dev.speed().ok(),
+                            })
+                        })
+                        .transpose()?;
+                    ports.push(UsbPortStatus {
+                        port_desc: UsbHubLayout::port_desc(
+                            speed_name,
+                            hub_index,
+                            speed_hubs.len(),
+                            port_desc,
+                        ),
+                        port_name: port_name.clone(),
+                        usb_3,
+                        device,
+                    });
+                }
+            }
+        }
+
+        Ok(Self { ports })
+    }
+}
@@ -1,100 +1,587 @@
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, io};
+use std::{collections::BTreeMap, fmt, io, path::Path};
 
-use self::usb_hub::UsbHub;
+pub(crate) use self::{usb_dev::UsbDev, usb_hub::UsbHub};
 
 mod block_dev;
+mod hub_status;
 mod usb_dev;
 mod usb_hub;
 
-#[derive(Debug, Deserialize, Serialize)]
+pub use self::hub_status::*;
+
+const USB_2_SPEED_NAME: &str = "USB 2.0";
+const USB_3_SPEED_NAME: &str = "USB 3.2 Gen 2";
+
+/// Expected USB hub topology of a board, from the `usb_hub` section of its `meta.json`
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct UsbHubLayout {
+    /// Number of USB 2 hubs the board should enumerate
+    pub usb_2_hubs: usize,
+    /// Number of USB 3 hubs the board should enumerate
+    pub usb_3_hubs: usize,
+    /// Description of each external port, by hub port number. Other ports are internal.
+    pub ports: BTreeMap<String, String>,
+}
+
+impl UsbHubLayout {
+    fn port_desc(speed_name: &str, hub: usize, hubs: usize, port_desc: &str) -> String {
+        if hubs > 1 {
+            format!("{} (hub {}): {}", speed_name, hub + 1, port_desc)
+        } else {
+            format!("{}: {}", speed_name, port_desc)
+        }
+    }
+
+    /// Descriptions of every port benchmarked, matching the keys of `Benchmark::port_results`
+    pub fn port_descs(&self) -> Vec<String> {
+        let mut port_descs = Vec::new();
+        for (speed_name, hubs) in [
+            (USB_2_SPEED_NAME, self.usb_2_hubs),
+            (USB_3_SPEED_NAME, self.usb_3_hubs),
+        ] {
+            for hub in 0..hubs {
+                for port_desc in self.ports.values() {
+                    port_descs.push(Self::port_desc(speed_name, hub, hubs, port_desc));
+                }
+            }
+        }
+        port_descs.sort();
+        port_descs
+    }
+}
+
+/// Options for `Benchmark::new`
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct BenchmarkConfig {
+    /// Timed transfers per device, at offsets spread across the device
+    pub samples: usize,
+    /// Untimed transfers before sampling, to wake the device
+    pub warmup: usize,
+    /// Also time writes, to a scratch file on a mounted filesystem of the device
+    pub write_test: bool,
+    /// Fall back to buffered IO on filesystems without direct IO, like tmpfs, instead of failing.
+    /// Speeds then include the page cache, so results are marked as not `direct`.
+    #[serde(default)]
+    pub allow_buffered: bool,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            samples: 5,
+            warmup: 1,
+            write_test: false,
+            allow_buffered: false,
+        }
+    }
+}
+
+/// Statistics of speed samples, in MB/s
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SpeedStats {
+    pub min: f64,
+    pub median: f64,
+    pub max: f64,
+    pub samples: usize,
+}
+
+impl SpeedStats {
+    pub fn new(mut samples: Vec<f64>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(f64::total_cmp);
+        let len = samples.len();
+        let median = if len % 2 == 0 {
+            (samples[len / 2 - 1] + samples[len / 2]) / 2.0
+        } else {
+            samples[len / 2]
+        };
+        Some(Self {
+            min: samples[0],
+            median,
+            max: samples[len - 1],
+            samples: len,
+        })
+    }
+}
+
+impl fmt::Display for SpeedStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} MB/s (min {:.2}, max {:.2})",
+            self.median, self.min, self.max
+        )
+    }
+}
+
+/// Benchmark of a block device, or the fastest device connected to a port
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PortBenchmark {
+    pub read: SpeedStats,
+    /// Result of the write test, if it was run
+    pub write: Option<Result<SpeedStats, String>>,
+    /// Transfers bypassed the page cache. Buffered results may be faster than the device.
+    pub direct: bool,
+}
+
+impl fmt::Display for PortBenchmark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "read {}", self.read)?;
+        match &self.write {
+            Some(Ok(write)) => write!(f, ", write {}", write)?,
+            Some(Err(err)) => write!(f, ", write failed: {}", err)?,
+            None => {}
+        }
+        if !self.direct {
+            write!(f, " (buffered IO)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Benchmark the block devices on `dev`, requiring a median speed of at least `required_speed`
+fn benchmark_port(
+    dev: &UsbDev,
+    dev_root: &Path,
+    config: &BenchmarkConfig,
+    required_speed: f64,
+) -> Result<PortBenchmark, String> {
+    if !dev.path().is_dir() {
+        return Err("no devices".to_string());
+    }
+
+    let mut best: Option<PortBenchmark> = None;
+    let mut last_err = None;
+    for block_dev in dev.block_devs(dev_root).map_err(|err| err.to_string())? {
+        match block_dev.benchmark(config) {
+            Ok(benchmark) => {
+                if best
+                    .as_ref()
+                    .map_or(true, |x| benchmark.read.median > x.read.median)
+                {
+                    best = Some(benchmark);
+                }
+            }
+            Err(err) => {
+                error!("{}: {}", block_dev.path().display(), err);
+                last_err = Some(err);
+            }
+        }
+    }
+
+    let best = match (best, last_err) {
+        (Some(best), _) => best,
+        (None, Some(err)) => return Err(format!("no accessible disks: {}", err)),
+        (None, None) => return Err("no accessible disks".to_string()),
+    };
+
+    if let Some(Err(err)) = &best.write {
+        return Err(format!(
+            "read {}, but write test failed: {}",
+            best.read, err
+        ));
+    }
+
+    let write = best.write.clone().and_then(Result::ok);
+    for (kind, stats) in [("read", Some(best.read)), ("write", write)] {
+        if let Some(stats) = stats {
+            if stats.median < required_speed {
+                return Err(format!(
+                    "benchmarked {} speed of {} was less than required speed of {:.2} MB/s",
+                    kind, stats, required_speed
+                ));
+            }
+        }
+    }
+
+    Ok(best)
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct Benchmark {
-    pub port_results: BTreeMap<String, Result<f64, String>>,
+    pub port_results: BTreeMap<String, Result<PortBenchmark, String>>,
 }
 
 impl Benchmark {
-    pub fn new() -> io::Result<Self> {
-        let hubs = UsbHub::probe()?;
-
-        let mut port_descs = BTreeMap::new();
-        port_descs.insert("1", "USB-C Right");
-        port_descs.insert("2", "USB-A Right");
-        port_descs.insert("3", "USB-A Left");
-        port_descs.insert("4", "USB-C Left");
-
-        let mut usb_2_hubs = 0;
-        let mut usb_3_hubs = 0;
-        for hub in hubs.iter() {
-            match hub {
-                UsbHub::Usb2(_) => usb_2_hubs += 1,
-                UsbHub::Usb3(_) => usb_3_hubs += 1,
+    /// All ports benchmarked, and met the required speed
+    pub fn success(&self) -> bool {
+        !self.port_results.is_empty() && self.port_results.values().all(Result::is_ok)
+    }
+
+    /// Combine with the results of a later run, keeping the best result for each port. Errors
+    /// are replaced with newer results, and passing results with faster ones.
+    pub fn merge(&mut self, newer: Benchmark) {
+        for (port_desc, new) in newer.port_results {
+            let old = self
+                .port_results
+                .entry(port_desc)
+                .or_insert_with(|| new.clone());
+            match (&*old, &new) {
+                (Ok(old_ok), Ok(new_ok)) if new_ok.read.median <= old_ok.read.median => {}
+                (Ok(_), Err(_)) => {}
+                _ => *old = new,
             }
         }
+    }
 
-        if usb_2_hubs != 1 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Found {} USB 2 hubs instead of 1", usb_2_hubs),
-            ));
-        }
+    pub fn new(layout: &UsbHubLayout, config: &BenchmarkConfig) -> io::Result<Self> {
+        Self::with_roots(Path::new("/sys"), Path::new("/dev"), layout, config)
+    }
 
-        if usb_3_hubs != 1 {
+    /// Benchmark using sysfs mounted at `sys_root` and device nodes in `dev_root`, so a fixture
+    /// tree can stand in for real hardware
+    pub fn with_roots(
+        sys_root: &Path,
+        dev_root: &Path,
+        layout: &UsbHubLayout,
+        config: &BenchmarkConfig,
+    ) -> io::Result<Self> {
+        let hubs = UsbHub::probe(sys_root)?;
+
+        let hub_names = |usb_3: bool| {
+            hubs.iter()
+                .filter(|hub| hub.is_usb_3() == usb_3)
+                .filter_map(|hub| hub.path().file_name()?.to_str())
+                .collect::<Vec<_>>()
+        };
+        let usb_2_hubs = hub_names(false);
+        let usb_3_hubs = hub_names(true);
+
+        if usb_2_hubs.len() != layout.usb_2_hubs || usb_3_hubs.len() != layout.usb_3_hubs {
             return Err(io::Error::new(
                 io::ErrorKind::Other,
-                format!("Found {} USB 3 hubs instead of 1", usb_3_hubs),
+                format!(
+                    "Unexpected USB hub topology: expected {} USB 2 and {} USB 3 hubs, found {} USB 2 hubs [{}] and {} USB 3 hubs [{}]",
+                    layout.usb_2_hubs,
+                    layout.usb_3_hubs,
+                    usb_2_hubs.len(),
+                    usb_2_hubs.join(", "),
+                    usb_3_hubs.len(),
+                    usb_3_hubs.join(", "),
+                ),
             ));
         }
 
         let mut port_results = BTreeMap::new();
-        for hub in hubs.iter() {
-            let (required_speed, speed_name) = match hub {
-                UsbHub::Usb2(_) => (
-                    1.5, // USB 1.1 max speed is 12 Mbps or 1.5 MBps
-                    "USB 2.0",
-                ),
-                UsbHub::Usb3(_) => (
-                    60.0, // USB 2.0 max speed is 480 Mbps or 60 MBps
-                    "USB 3.2 Gen 2",
-                ),
-            };
-
-            for (port_name, dev) in hub.ports()?.iter() {
-                let port_desc = match port_descs.get(port_name.as_str()) {
-                    Some(some) => some,
-                    // Ports 5 and 6 connect to the Launch microcontroller (port 5)
-                    // and an integrated hub device (port 6)
-                    None => continue,
-                };
-
-                let port_result = if dev.path().is_dir() {
-                    let mut best_speed = -1.0;
-                    for block_dev in dev.block_devs()? {
-                        match block_dev.benchmark() {
-                            Ok(benchmark) => {
-                                if benchmark > best_speed {
-                                    best_speed = benchmark;
-                                }
-                            }
-                            Err(_err) => {
-                                //TODO: do something with error
-                            }
+        for (speed_name, required_speed, hub_count, usb_3) in [
+            (
+                USB_2_SPEED_NAME,
+                1.5, // USB 1.1 max speed is 12 Mbps or 1.5 MBps
+                layout.usb_2_hubs,
+                false,
+            ),
+            (
+                USB_3_SPEED_NAME,
+                60.0, // USB 2.0 max speed is 480 Mbps or 60 MBps
+                layout.usb_3_hubs,
+                true,
+            ),
+        ] {
+            let speed_hubs = hubs.iter().filter(|hub| hub.is_usb_3() == usb_3);
+            for (hub_index, hub) in speed_hubs.enumerate() {
+                // Ports not listed in the layout connect to internal devices, like the
+                // keyboard microcontroller, and are ignored
+                let mut hub_ports = hub.ports()?;
+                for (port_name, port_desc) in layout.ports.iter() {
+                    let port_desc =
+                        UsbHubLayout::port_desc(speed_name, hub_index, hub_count, port_desc);
+
+                    let dev = match hub_ports.remove(port_name) {
+                        Some(some) => some,
+                        None => {
+                            port_results.insert(
+                                port_desc,
+                                Err(format!(
+                                    "port {} not found on hub {}",
+                                    port_name,
+                                    hub.path().display()
+                                )),
+                            );
+                            continue;
                         }
-                    }
-                    if best_speed < 0.0 {
-                        Err("no accessible disks".to_string())
-                    } else if best_speed > required_speed {
-                        Ok(best_speed)
-                    } else {
-                        Err(format!("benchmarked speed of {:.2} MB/s was less than required speed of {:.2} MB/s", best_speed, required_speed))
-                    }
-                } else {
-                    Err("no devices".to_string())
-                };
-
-                port_results.insert(format!("{}: {}", speed_name, port_desc), port_result);
+                    };
+
+                    let port_result = benchmark_port(&dev, dev_root, config, required_speed);
+                    port_results.insert(port_desc, port_result);
+                }
             }
         }
 
         Ok(Self { port_results })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Layout;
+    use std::{env, fs, path::PathBuf, process};
+
+    /// Fake sysfs and /dev tree, removed on drop
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root =
+                env::temp_dir().join(format!("keyboard-benchmark-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("sys/bus/usb/devices")).unwrap();
+            fs::create_dir_all(root.join("dev")).unwrap();
+            Self(root)
+        }
+
+        fn sys(&self) -> PathBuf {
+            self.0.join("sys")
+        }
+
+        fn dev(&self) -> PathBuf {
+            self.0.join("dev")
+        }
+
+        fn hub(&self, name: &str, pid: &str) {
+            let path = self.sys().join("bus/usb/devices").join(name);
+            fs::create_dir_all(path.join(format!("{}:1.0", name))).unwrap();
+            fs::write(path.join("idVendor"), "3384\n").unwrap();
+            fs::write(path.join("idProduct"), format!("{}\n", pid)).unwrap();
+        }
+
+        fn port(&self, hub: &str, port: &str) -> PathBuf {
+            let path = self
+                .sys()
+                .join("bus/usb/devices")
+                .join(hub)
+                .join(format!("{}:1.0", hub))
+                .join(format!("{}-port{}", hub, port));
+            fs::create_dir_all(&path).unwrap();
+            path
+        }
+
+        /// Connect a mass storage device to a port, with a `len` byte device node if `len` is
+        /// not `None`
+        fn disk(&self, hub: &str, port: &str, block: &str, host: u32, len: Option<usize>) {
+            let path = self
+                .port(hub, port)
+                .join("device")
+                .join(format!("{}.{}:1.0", hub, port))
+                .join(format!("host{}", host))
+                .join(format!("target{}:0:0", host))
+                .join(format!("{}:0:0:0", host))
+                .join("block")
+                .join(block);
+            fs::create_dir_all(path).unwrap();
+            if let Some(len) = len {
+                fs::write(self.dev().join(block), vec![0; len]).unwrap();
+            }
+        }
+
+        /// Connect a device with string descriptors and link speed to a port
+        fn device(&self, hub: &str, port: &str, product: &str, speed: &str) {
+            let path = self.port(hub, port).join("device");
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("idVendor"), "0781\n").unwrap();
+            fs::write(path.join("idProduct"), "5581\n").unwrap();
+            fs::write(path.join("manufacturer"), "SanDisk\n").unwrap();
+            fs::write(path.join("product"), format!("{}\n", product)).unwrap();
+            fs::write(path.join("speed"), format!("{}\n", speed)).unwrap();
+        }
+
+        fn benchmark(&self, layout: &UsbHubLayout) -> io::Result<Benchmark> {
+            Benchmark::with_roots(
+                &self.sys(),
+                &self.dev(),
+                layout,
+                // The fixture's device nodes are files in the temporary directory
+                &BenchmarkConfig {
+                    allow_buffered: true,
+                    ..BenchmarkConfig::default()
+                },
+            )
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn hub_layout() -> UsbHubLayout {
+        Layout::from_board("system76/launch_2", "")
+            .unwrap()
+            .meta
+            .usb_hub
+            .unwrap()
+    }
+
+    #[test]
+    fn benchmark_unexpected_topology() {
+        let fixture = Fixture::new("topology");
+        fixture.hub("1-1", "0003");
+        fixture.hub("1-2", "4216");
+
+        let err = fixture.benchmark(&hub_layout()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unexpected USB hub topology: expected 1 USB 2 and 1 USB 3 hubs, found 2 USB 2 hubs [1-1, 1-2] and 0 USB 3 hubs []"
+        );
+    }
+
+    #[test]
+    fn benchmark_port_results() {
+        let fixture = Fixture::new("ports");
+        fixture.hub("1-1", "0003");
+        fixture.hub("2-1", "7216");
+
+        fixture.disk("1-1", "1", "sda", 0, Some(8 * 1024 * 1024));
+        fixture.port("1-1", "2");
+        fixture.port("1-1", "4");
+        fs::create_dir_all(fixture.port("1-1", "4").join("device")).unwrap();
+        // Internal port, not in the layout
+        fixture.disk("1-1", "5", "sdz", 9, Some(8 * 1024 * 1024));
+
+        fixture.disk("2-1", "1", "sdb", 1, None);
+        fixture.disk("2-1", "2", "sdc", 2, Some(1024));
+        fixture.port("2-1", "3");
+        fixture.port("2-1", "4");
+
+        let benchmark = fixture.benchmark(&hub_layout()).unwrap();
+        let results = &benchmark.port_results;
+        assert_eq!(
+            results.keys().collect::<Vec<_>>(),
+            hub_layout().port_descs().iter().collect::<Vec<_>>()
+        );
+
+        let read = results["USB 2.0: USB-C Right"].as_ref().unwrap().read;
+        assert_eq!(read.samples, 5);
+        assert!(read.min <= read.median && read.median <= read.max);
+
+        assert_eq!(
+            results["USB 2.0: USB-A Right"],
+            Err("no devices".to_string())
+        );
+        assert!(results["USB 2.0: USB-A Left"]
+            .as_ref()
+            .unwrap_err()
+            .starts_with("port 3 not found on hub"));
+        assert_eq!(
+            results["USB 2.0: USB-C Left"],
+            Err("no accessible disks".to_string())
+        );
+        assert!(results["USB 3.2 Gen 2: USB-C Right"]
+            .as_ref()
+            .unwrap_err()
+            .starts_with("no accessible disks: "));
+        assert!(results["USB 3.2 Gen 2: USB-A Right"]
+            .as_ref()
+            .unwrap_err()
+            .starts_with("no accessible disks: "));
+    }
+
+    #[test]
+    fn benchmark_write_failure() {
+        let fixture = Fixture::new("write");
+        fixture.hub("1-1", "0003");
+        fixture.hub("2-1", "7216");
+        fixture.disk("1-1", "1", "sda", 0, Some(8 * 1024 * 1024));
+
+        // The fixture's disk has no mounted filesystem to write to
+        let config = BenchmarkConfig {
+            write_test: true,
+            allow_buffered: true,
+            ..BenchmarkConfig::default()
+        };
+        let benchmark =
+            Benchmark::with_roots(&fixture.sys(), &fixture.dev(), &hub_layout(), &config).unwrap();
+        let err = benchmark.port_results["USB 2.0: USB-C Right"]
+            .as_ref()
+            .unwrap_err();
+        assert!(err.starts_with("read "), "{}", err);
+        assert!(
+            err.ends_with("but write test failed: no mounted filesystem for write test"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn speed_stats() {
+        assert_eq!(SpeedStats::new(Vec::new()), None);
+        let stats = SpeedStats::new(vec![30.0, 10.0, 20.0, 100.0]).unwrap();
+        assert_eq!(stats.min, 10.0);
+        assert_eq!(stats.median, 25.0);
+        assert_eq!(stats.max, 100.0);
+        assert_eq!(stats.samples, 4);
+        let port = PortBenchmark {
+            read: stats,
+            write: None,
+            direct: false,
+        };
+        assert_eq!(
+            port.to_string(),
+            "read 25.00 MB/s (min 10.00, max 100.00) (buffered IO)"
+        );
+        let port = PortBenchmark {
+            write: Some(Err("no mounted filesystem for write test".to_string())),
+            direct: true,
+            ..port
+        };
+        assert_eq!(
+            port.to_string(),
+            "read 25.00 MB/s (min 10.00, max 100.00), write failed: no mounted filesystem for write test"
+        );
+        assert_eq!(SpeedStats::new(vec![3.0, 1.0, 2.0]).unwrap().median, 2.0);
+    }
+
+    #[test]
+    fn launch_port_descs() {
+        assert_eq!(
+            hub_layout().port_descs(),
+            vec![
+                "USB 2.0: USB-A Left",
+                "USB 2.0: USB-A Right",
+                "USB 2.0: USB-C Left",
+                "USB 2.0: USB-C Right",
+                "USB 3.2 Gen 2: USB-A Left",
+                "USB 3.2 Gen 2: USB-A Right",
+                "USB 3.2 Gen 2: USB-C Left",
+                "USB 3.2 Gen 2: USB-C Right",
+            ]
+        );
+
+        let layout = Layout::from_board("system76/launch_lite_1", "").unwrap();
+        assert!(layout.meta.usb_hub.is_none());
+    }
+
+    #[test]
+    fn hub_status() {
+        let fixture = Fixture::new("status");
+        fixture.hub("1-1", "0003");
+        fixture.hub("2-1", "0004");
+        fixture.device("1-1", "2", "Ultra", "480");
+        fixture.device("2-1", "1", "Extreme", "5000");
+        fixture.port("2-1", "3");
+
+        let status = UsbHubStatus::with_root(&fixture.sys(), &hub_layout()).unwrap();
+        assert_eq!(status.ports.len(), 8);
+
+        let port = &status.ports[1];
+        assert_eq!(port.port_desc, "USB 2.0: USB-A Right");
+        assert!(!port.usb_3);
+        let device = port.device.as_ref().unwrap();
+        assert_eq!(device.to_string(), "SanDisk Ultra, High Speed (480 Mbps)");
+
+        let port = &status.ports[4];
+        assert_eq!(port.port_desc, "USB 3.2 Gen 2: USB-C Right");
+        assert!(port.usb_3);
+        assert_eq!(
+            port.device.as_ref().unwrap().speed_desc().unwrap(),
+            "SuperSpeed (5000 Mbps)"
+        );
+
+        assert!(status.ports[0].device.is_none());
+        assert!(status.ports[6].device.is_none());
+    }
+}
@@ -16,21 +16,42 @@ impl UsbDev {
         &self.0
     }
 
+    /// Read a sysfs attribute of the device, without surrounding whitespace
+    fn attr(&self, name: &str) -> io::Result<String> {
+        Ok(fs::read_to_string(self.path().join(name))?
+            .trim()
+            .to_string())
+    }
+
     pub fn vendor_id(&self) -> io::Result<u16> {
-        let vid_path = self.path().join("idVendor");
-        let vid_str = fs::read_to_string(&vid_path)?;
-        u16::from_str_radix(vid_str.trim(), 16)
+        let vid_str = self.attr("idVendor")?;
+        u16::from_str_radix(&vid_str, 16)
             .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 
     pub fn product_id(&self) -> io::Result<u16> {
-        let pid_path = self.path().join("idProduct");
-        let pid_str = fs::read_to_string(&pid_path)?;
-        u16::from_str_radix(pid_str.trim(), 16)
+        let pid_str = self.attr("idProduct")?;
+        u16::from_str_radix(&pid_str, 16)
             .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 
-    pub fn block_devs(&self) -> io::Result<Vec<BlockDev>> {
+    /// Manufacturer string descriptor, if the device has one
+    pub fn manufacturer(&self) -> Option<String> {
+        self.attr("manufacturer").ok()
+    }
+
+    /// Product string descriptor, if the device has one
+    pub fn product(&self) -> Option<String> {
+        self.attr("product").ok()
+    }
+
+    /// Negotiated link speed in Mbps, as reported by the kernel (e.g. `480` or `5000`)
+    pub fn speed(&self) -> io::Result<String> {
+        self.attr("speed")
+    }
+
+    /// Block devices of USB mass storage interfaces, with device nodes under `dev_root`
+    pub fn block_devs(&self, dev_root: &Path) -> io::Result<Vec<BlockDev>> {
         let mut ifaces = Vec::new();
         //TODO: support multiple ifaces
         let iface_suffix = ":1.0";
@@ -97,7 +118,7 @@ impl UsbDev {
 
         let mut block_devs = Vec::new();
         for (block_name, _block_path) in blocks.iter() {
-            block_devs.push(BlockDev::new(Path::new("/dev").join(block_name)));
+            block_devs.push(BlockDev::new(dev_root.join(block_name)));
         }
 
         block_devs.sort();
@@ -15,9 +15,11 @@ pub enum UsbHub {
 }
 
 impl UsbHub {
-    pub fn probe() -> io::Result<Vec<Self>> {
+    /// Find System76 hubs among the USB devices in the sysfs mounted at `sys_root`, sorted by
+    /// path so hubs of the same speed are numbered consistently
+    pub fn probe(sys_root: &Path) -> io::Result<Vec<Self>> {
         let mut hubs = Vec::new();
-        for entry_res in fs::read_dir("/sys/bus/usb/devices")? {
+        for entry_res in fs::read_dir(sys_root.join("bus/usb/devices"))? {
             let entry = entry_res?;
             let entry_path = entry.path();
             let vid_path = entry_path.join("idVendor");
@@ -35,9 +37,14 @@ impl UsbHub {
                 }
             }
         }
+        hubs.sort_by(|a, b| a.path().cmp(b.path()));
         Ok(hubs)
     }
 
+    pub fn is_usb_3(&self) -> bool {
+        matches!(self, UsbHub::Usb3(_))
+    }
+
     pub fn usb_dev(&self) -> &UsbDev {
         match self {
             UsbHub::Usb2(usb) => usb,
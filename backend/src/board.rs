@@ -4,24 +4,59 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
-    process::Command,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex, MutexGuard, Weak,
     },
+    time::Instant,
 };
 
 use crate::daemon::ThreadClient;
 use crate::{
-    Benchmark, BoardId, Daemon, Event, Key, KeyMap, KeyMapLayer, Layer, Layout, Matrix, Nelson,
-    NelsonKind,
+    Benchmark, BenchmarkConfig, BoardId, Capabilities, Capability, CapabilityProbes, Daemon, Event,
+    FirmwareVersion, Key, KeyMap, KeyMapLayer, KeyMapLint, Layer, Layout, Matrix, Nelson,
+    NelsonConfig, UsbHubLayout, UsbHubStatus,
 };
 
+/// Most layers the Configurator has pages for
+pub const MAX_LAYERS: u8 = 4;
+
 #[derive(Clone, Debug)]
 pub enum BoardEvent {
     KeymapChanged,
     LedsChanged,
     MatrixChanged,
+    /// A key was pressed or released, found by comparing matrix snapshots
+    Key(KeyEvent),
+}
+
+/// Key press or release, detected while matrix polling is enabled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// `true` for key down, `false` for key up
+    pub pressed: bool,
+    /// Logical name of the key, like `K01`
+    pub logical_name: String,
+    /// Physical name of the key (what is printed on the keycap)
+    pub physical_name: String,
+    /// Electrical mapping (output, input)
+    pub electrical: (u8, u8),
+    /// Time the change was seen by the matrix poll
+    pub time: Instant,
+}
+
+#[cfg(test)]
+impl KeyEvent {
+    /// Event for a key with its logical name as physical name, and no electrical mapping
+    pub(crate) fn mock(logical_name: &str, pressed: bool, time: Instant) -> Self {
+        Self {
+            pressed,
+            logical_name: logical_name.to_string(),
+            physical_name: logical_name.to_string(),
+            electrical: (0, 0),
+            time,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -30,18 +65,16 @@ struct BoardInner {
     board: BoardId,
     model: String,
     version: String,
+    firmware: FirmwareVersion,
     layout: Layout,
     keys: OnceCell<Vec<Key>>,
     layers: OnceCell<Vec<Layer>>,
     max_brightness: i32,
     leds_changed: AtomicBool,
-    has_led_save: bool,
     led_save_blocked: AtomicBool,
-    has_matrix: bool,
     is_fake: bool,
-    has_keymap: bool,
+    capabilities: Capabilities,
     matrix: Arc<Mutex<Matrix>>,
-    updated: bool,
     event_sender: async_mpsc::UnboundedSender<Event>,
 }
 
@@ -87,8 +120,12 @@ impl Board {
             error!("Error getting firmware version: {}", err);
             String::new()
         });
-        let layout = Layout::from_board(&model, &version)
-            .ok_or_else(|| format!("Failed to locate layout for '{}'", model))?;
+        let mut layout = Layout::from_board(&model, &version)
+            .map_err(|err| format!("Failed to load layout for '{}': {}", model, err))?;
+        // VIA keyboards report their number of layers, which VIA definitions don't include
+        if let Ok(layer_count) = daemon.layer_count(board) {
+            layout.meta.num_layers = layer_count.clamp(1, MAX_LAYERS);
+        }
 
         let max_brightness = daemon.max_brightness(board).unwrap_or_else(|err| {
             error!("Error getting max brightness: {}", err);
@@ -101,29 +138,38 @@ impl Board {
             1
         };
 
-        let has_led_save = daemon.led_save(board).is_ok();
-        let has_matrix = daemon.matrix_get(board).is_ok();
         let logical = layout.layout.values().next().unwrap();
-        let has_keymap = daemon.keymap_get(board, 0, logical.0, logical.1).is_ok();
+        let probes = CapabilityProbes {
+            keymap: daemon
+                .keymap_get(board, 0, logical.0, logical.1)
+                .map(|_| ()),
+            matrix: daemon.matrix_get(board).map(|_| ()),
+            led_save: daemon.led_save(board),
+        };
+        let firmware = FirmwareVersion::new(&version, layout.meta.firmware_kind());
+        let capabilities = Capabilities::new(&layout.meta, &firmware, probes);
+        for (capability, res) in capabilities.iter() {
+            if let Err(reason) = res {
+                debug!("{}: no {}: {}", model, capability, reason);
+            }
+        }
 
         let self_ = Board(Arc::new(BoardInner {
             thread_client,
             board,
             model,
             version,
+            firmware,
             layout,
             max_brightness,
-            has_led_save,
-            has_matrix,
             is_fake: daemon.is_fake(),
-            has_keymap,
+            capabilities,
             keys: OnceCell::new(),
             layers: OnceCell::new(),
             leds_changed: AtomicBool::new(false),
             led_save_blocked: AtomicBool::new(false),
             matrix,
             event_sender,
-            updated: is_launch_updated().unwrap_or(false),
         }));
 
         let keys = self_
@@ -171,27 +217,56 @@ impl Board {
         &self.0.version
     }
 
-    pub fn has_matrix(&self) -> bool {
-        self.0.has_matrix
+    /// Parsed firmware version, to check which features it supports
+    pub fn firmware_version(&self) -> &FirmwareVersion {
+        &self.0.firmware
+    }
+
+    /// What the board supports, and why anything else isn't
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.0.capabilities
     }
 
     pub fn max_brightness(&self) -> i32 {
         self.0.max_brightness
     }
 
-    pub async fn benchmark(&self) -> Result<Benchmark, String> {
-        self.thread_client().benchmark(self.board()).await
+    /// Topology of the board's integrated USB hub, if it has one
+    pub fn usb_hub(&self) -> Option<&UsbHubLayout> {
+        self.layout().meta.usb_hub.as_ref()
+    }
+
+    /// Devices plugged into the board's USB hub. Reads sysfs directly, so works without root.
+    pub fn usb_hub_status(&self) -> Result<UsbHubStatus, String> {
+        let usb_hub = self
+            .usb_hub()
+            .ok_or_else(|| format!("{} has no USB hub", self.model()))?;
+        UsbHubStatus::new(usb_hub).map_err(|err| err.to_string())
+    }
+
+    pub async fn benchmark(&self, config: &BenchmarkConfig) -> Result<Benchmark, String> {
+        let usb_hub = self
+            .usb_hub()
+            .ok_or_else(|| format!("{} has no USB hub to benchmark", self.model()))?
+            .clone();
+        self.thread_client()
+            .benchmark(self.board(), usb_hub, config.clone())
+            .await
     }
 
-    pub async fn nelson(&self, kind: NelsonKind) -> Result<Nelson, String> {
-        self.thread_client().nelson(self.board(), kind).await
+    pub async fn nelson(&self, config: &NelsonConfig) -> Result<Nelson, String> {
+        self.thread_client()
+            .nelson(self.board(), config.clone())
+            .await
     }
 
     pub async fn led_save(&self) -> Result<(), String> {
         if self.0.led_save_blocked.load(Ordering::SeqCst) {
             return Ok(());
         }
-        if self.has_led_save() && self.0.leds_changed.load(Ordering::SeqCst) {
+        if self.0.capabilities.has(Capability::LedSave)
+            && self.0.leds_changed.load(Ordering::SeqCst)
+        {
             self.thread_client().led_save(self.board()).await?;
             self.0.leds_changed.store(false, Ordering::SeqCst);
             debug!("led_save");
@@ -216,18 +291,6 @@ impl Board {
         RE.is_match(self.model())
     }
 
-    pub fn is_updated(&self) -> bool {
-        self.0.updated
-    }
-
-    pub fn has_led_save(&self) -> bool {
-        self.0.has_led_save
-    }
-
-    pub fn has_keymap(&self) -> bool {
-        self.0.has_keymap
-    }
-
     pub fn layout(&self) -> &Layout {
         &self.0.layout
     }
@@ -274,6 +337,11 @@ impl Board {
         }
     }
 
+    /// Check the currently loaded keymap for unreachable layers and lockout risks
+    pub fn lint_keymap(&self) -> Vec<KeyMapLint> {
+        self.export_keymap().lint(self.layout())
+    }
+
     pub async fn set_no_input(&self, no_input: bool) -> Result<(), String> {
         self.thread_client()
             .set_no_input(self.board(), no_input)
@@ -290,15 +358,3 @@ impl WeakBoard {
         Some(Board(self.0.upgrade()?))
     }
 }
-
-pub fn is_launch_updated() -> Result<bool, String> {
-    use regex::bytes::Regex;
-    let stdout = Command::new("fwupdmgr")
-        .args(["get-updates", "--json"])
-        .output()
-        .map_err(|e| format!("Failed to use fwupdmgr: {}", e))?
-        .stdout;
-
-    static RE: Lazy<Regex> = Lazy::new(|| Regex::new("Launch.* Configurable Keyboard").unwrap());
-    Ok(!RE.is_match(&stdout))
-}
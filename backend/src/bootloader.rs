@@ -0,0 +1,95 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    benchmark::{UsbDev, UsbHub},
+    Bootloaded,
+};
+
+const ATMEL_VID: u16 = 0x03eb;
+const ATMEGA32U4_DFU_PID: u16 = 0x2ff4;
+const AT90USB646_DFU_PID: u16 = 0x2ff9;
+
+impl Bootloaded {
+    /// Find a keyboard in bootloader mode among the USB devices in the sysfs mounted at
+    /// `sys_root`. Launch 2 and Launch Heavy are told apart from Launch Lite, which has the same
+    /// MCU, by the USB hub only they have.
+    pub fn detect(sys_root: &Path) -> io::Result<Option<Self>> {
+        let devices = match fs::read_dir(sys_root.join("bus/usb/devices")) {
+            Ok(devices) => devices,
+            // No sysfs on this platform
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut mcus = Vec::new();
+        for entry_res in devices {
+            let usb = UsbDev::new(entry_res?.path());
+            // Interfaces are listed alongside devices, without IDs
+            if !usb.path().join("idVendor").is_file() {
+                continue;
+            }
+            match (usb.vendor_id()?, usb.product_id()?) {
+                (ATMEL_VID, AT90USB646_DFU_PID) => mcus.push(AT90USB646_DFU_PID),
+                (ATMEL_VID, ATMEGA32U4_DFU_PID) => mcus.push(ATMEGA32U4_DFU_PID),
+                _ => (),
+            }
+        }
+
+        Ok(if mcus.contains(&AT90USB646_DFU_PID) {
+            if UsbHub::probe(sys_root)?.is_empty() {
+                Some(Self::At90usb646Lite)
+            } else {
+                Some(Self::At90usb646)
+            }
+        } else if mcus.contains(&ATMEGA32U4_DFU_PID) {
+            Some(Self::AtMega32u4)
+        } else {
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn bootloader_detect() {
+        let root = env::temp_dir().join(format!("keyboard-bootloader-{}", process::id()));
+        let devices = root.join("bus/usb/devices");
+        let device = |name: &str, vid: &str, pid: &str| {
+            let path = devices.join(name);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("idVendor"), format!("{}\n", vid)).unwrap();
+            fs::write(path.join("idProduct"), format!("{}\n", pid)).unwrap();
+        };
+
+        assert_eq!(Bootloaded::detect(&root).unwrap(), None);
+
+        fs::create_dir_all(devices.join("1-0:1.0")).unwrap();
+        device("usb1", "1d6b", "0002");
+        assert_eq!(Bootloaded::detect(&root).unwrap(), None);
+
+        device("1-2", "03eb", "2ff4");
+        assert_eq!(
+            Bootloaded::detect(&root).unwrap(),
+            Some(Bootloaded::AtMega32u4)
+        );
+
+        fs::remove_dir_all(devices.join("1-2")).unwrap();
+        device("1-3", "03eb", "2ff9");
+        assert_eq!(
+            Bootloaded::detect(&root).unwrap(),
+            Some(Bootloaded::At90usb646Lite)
+        );
+
+        device("1-4", "3384", "0003");
+        assert_eq!(
+            Bootloaded::detect(&root).unwrap(),
+            Some(Bootloaded::At90usb646)
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{FirmwareFeature, FirmwareVersion, Meta};
+
+/// Feature a board may support
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Capability {
+    /// Keymap can be read and changed
+    Keymap,
+    /// Key matrix can be read, for testing and diagnostics
+    Matrix,
+    /// LED settings can be saved to firmware
+    LedSave,
+    LedBrightness,
+    LedColor,
+    /// Per-key LED modes, like patterns and reactive modes
+    LedModes,
+    /// LED settings are per-layer, not for the whole keyboard
+    PerLayerLeds,
+    ModTap,
+    InvertFKeys,
+    /// `PAUSE` scancode
+    Pause,
+    /// `FNLOCK` scancode
+    FnLock,
+    /// Integrated USB hub
+    UsbHub,
+    /// Keymap can be exported as a QMK `keymap.json` or `keymap.c`
+    QmkExport,
+    /// Firmware updates are published through fwupd
+    Fwupd,
+}
+
+impl Capability {
+    pub fn iter_all() -> impl Iterator<Item = Self> {
+        [
+            Self::Keymap,
+            Self::Matrix,
+            Self::LedSave,
+            Self::LedBrightness,
+            Self::LedColor,
+            Self::LedModes,
+            Self::PerLayerLeds,
+            Self::ModTap,
+            Self::InvertFKeys,
+            Self::Pause,
+            Self::FnLock,
+            Self::UsbHub,
+            Self::QmkExport,
+            Self::Fwupd,
+        ]
+        .into_iter()
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Keymap => "keymap",
+            Self::Matrix => "matrix",
+            Self::LedSave => "led-save",
+            Self::LedBrightness => "led-brightness",
+            Self::LedColor => "led-color",
+            Self::LedModes => "led-modes",
+            Self::PerLayerLeds => "per-layer-leds",
+            Self::ModTap => "mod-tap",
+            Self::InvertFKeys => "invert-f-keys",
+            Self::Pause => "pause",
+            Self::FnLock => "fnlock",
+            Self::UsbHub => "usb-hub",
+            Self::QmkExport => "qmk-export",
+            Self::Fwupd => "fwupd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Results of commands sent to a board to see which it responds to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapabilityProbes {
+    pub keymap: Result<(), String>,
+    pub matrix: Result<(), String>,
+    pub led_save: Result<(), String>,
+}
+
+/// What a board supports, from its layout metadata, firmware version, and probes, with the
+/// reason for each unsupported capability
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities(BTreeMap<Capability, Result<(), String>>);
+
+impl Capabilities {
+    pub fn new(meta: &Meta, firmware: &FirmwareVersion, probes: CapabilityProbes) -> Self {
+        fn meta_flag(flag: bool, reason: &str) -> Result<(), String> {
+            if flag {
+                Ok(())
+            } else {
+                Err(reason.to_string())
+            }
+        }
+
+        fn probe(res: Result<(), String>, command: &str) -> Result<(), String> {
+            res.map_err(|err| format!("Firmware does not support {}: {}", command, err))
+        }
+
+        let capabilities = Capability::iter_all()
+            .map(|capability| {
+                let res = match capability {
+                    Capability::Keymap => probe(probes.keymap.clone(), "reading the keymap"),
+                    Capability::Matrix => probe(probes.matrix.clone(), "reading the key matrix"),
+                    Capability::LedSave => probe(probes.led_save.clone(), "saving LEDs"),
+                    Capability::LedBrightness => {
+                        meta_flag(meta.has_brightness, "Keyboard has no backlight")
+                    }
+                    Capability::LedColor => {
+                        meta_flag(meta.has_color, "Keyboard backlight is monochrome")
+                    }
+                    Capability::LedModes => {
+                        meta_flag(meta.has_mode, "Keyboard has no per-key LED modes")
+                    }
+                    Capability::PerLayerLeds => meta_flag(
+                        meta.has_per_layer,
+                        "LED settings apply to the whole keyboard, not each layer",
+                    ),
+                    Capability::ModTap => {
+                        meta_flag(meta.has_mod_tap, "Layout does not support mod-tap")
+                            .and_then(|()| firmware.supports(FirmwareFeature::ModTap))
+                    }
+                    Capability::InvertFKeys => meta_flag(
+                        !meta.no_fn_f,
+                        "F keys are not on the Fn layer of the default keymap",
+                    ),
+                    Capability::Pause => firmware.supports(FirmwareFeature::Pause),
+                    Capability::FnLock => firmware.supports(FirmwareFeature::FnLock),
+                    Capability::UsbHub => {
+                        meta_flag(meta.usb_hub.is_some(), "Keyboard has no USB hub")
+                    }
+                    Capability::QmkExport if !meta.is_qmk => {
+                        Err("Keyboard does not use QMK firmware".to_string())
+                    }
+                    Capability::QmkExport => meta_flag(
+                        !firmware.has(FirmwareFeature::LegacyScancodes),
+                        "QMK firmware older than 0.19 uses different keycodes",
+                    ),
+                    Capability::Fwupd => meta_flag(
+                        meta.is_qmk,
+                        "EC firmware is updated by system76-firmware, not fwupd",
+                    ),
+                };
+                (capability, res)
+            })
+            .collect();
+        Self(capabilities)
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        self.0.get(&capability).map_or(false, |x| x.is_ok())
+    }
+
+    /// Why `capability` is not supported, or `None` if it is
+    pub fn reason(&self, capability: Capability) -> Option<&str> {
+        self.0.get(&capability)?.as_ref().err().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Capability, Result<(), &str>)> {
+        self.0.iter().map(|(capability, res)| {
+            (
+                *capability,
+                res.as_ref().map(|_| ()).map_err(|x| x.as_str()),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Layout;
+
+    #[test]
+    fn capabilities_reasons() {
+        let layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        let firmware = FirmwareVersion::new("0.19.12", layout.meta.firmware_kind());
+        let capabilities = Capabilities::new(
+            &layout.meta,
+            &firmware,
+            CapabilityProbes {
+                keymap: Ok(()),
+                matrix: Ok(()),
+                led_save: Err("Invalid command".to_string()),
+            },
+        );
+
+        assert!(capabilities.has(Capability::Keymap));
+        assert!(capabilities.has(Capability::UsbHub));
+        assert!(capabilities.has(Capability::Pause));
+        assert!(capabilities.has(Capability::QmkExport));
+        assert!(capabilities.has(Capability::Fwupd));
+        assert_eq!(
+            capabilities.reason(Capability::LedSave),
+            Some("Firmware does not support saving LEDs: Invalid command")
+        );
+        assert_eq!(
+            capabilities.reason(Capability::FnLock),
+            Some("Not supported by QMK firmware")
+        );
+        assert_eq!(capabilities.iter().count(), Capability::iter_all().count());
+    }
+}
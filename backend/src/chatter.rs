@@ -0,0 +1,248 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::KeyEvent;
+
+/// Thresholds used by `ChatterDetector`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChatterConfig {
+    /// Transitions closer together than this are treated as chatter
+    pub min_transition: Duration,
+    /// Keys held down longer than this are reported as stuck
+    pub stuck_after: Duration,
+}
+
+impl Default for ChatterConfig {
+    fn default() -> Self {
+        Self {
+            min_transition: Duration::from_millis(20),
+            stuck_after: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct KeyHistory {
+    physical_name: String,
+    electrical: (u8, u8),
+    pressed_since: Option<Instant>,
+    last_transition: Instant,
+    presses: u32,
+    chatters: u32,
+    fastest_transition: Option<Duration>,
+}
+
+/// Problems found with one key by `ChatterDetector`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChatterKey {
+    pub logical_name: String,
+    pub physical_name: String,
+    pub electrical: (u8, u8),
+    pub presses: u32,
+    /// Number of transitions faster than `ChatterConfig::min_transition`
+    pub chatters: u32,
+    pub fastest_transition: Option<Duration>,
+    /// How long the key has been held, if longer than `ChatterConfig::stuck_after`
+    pub stuck_for: Option<Duration>,
+}
+
+impl ChatterKey {
+    pub fn is_chattering(&self) -> bool {
+        self.chatters > 0
+    }
+
+    pub fn is_stuck(&self) -> bool {
+        self.stuck_for.is_some()
+    }
+}
+
+impl fmt::Display for ChatterKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.physical_name.replace('\n', " "),
+            self.logical_name
+        )?;
+        if self.is_chattering() {
+            write!(
+                f,
+                ": {} of {} presses chattered",
+                self.chatters, self.presses
+            )?;
+            if let Some(fastest) = self.fastest_transition {
+                write!(f, ", fastest transition {} ms", fastest.as_millis())?;
+            }
+        }
+        if let Some(stuck_for) = self.stuck_for {
+            write!(f, ": held for {} s", stuck_for.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// Summary of a chatter diagnostic session
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChatterReport {
+    /// Keys pressed at least once
+    pub keys_tested: usize,
+    /// Keys that chattered or are stuck, sorted by logical name
+    pub keys: Vec<ChatterKey>,
+}
+
+impl ChatterReport {
+    pub fn success(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Detect switch chatter and stuck keys from the key events produced by matrix polling
+///
+/// Matrix polling only sees transitions slower than the polling rate, so this should be used
+/// with a fast `Backend::set_matrix_get_rate`.
+#[derive(Clone, Debug, Default)]
+pub struct ChatterDetector {
+    config: ChatterConfig,
+    keys: BTreeMap<String, KeyHistory>,
+}
+
+impl ChatterDetector {
+    pub fn new(config: ChatterConfig) -> Self {
+        Self {
+            config,
+            keys: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ChatterConfig {
+        &self.config
+    }
+
+    pub fn handle_event(&mut self, event: &KeyEvent) {
+        let config = self.config;
+        let history = self
+            .keys
+            .entry(event.logical_name.clone())
+            .or_insert_with(|| KeyHistory {
+                physical_name: event.physical_name.clone(),
+                electrical: event.electrical,
+                pressed_since: None,
+                last_transition: event.time,
+                presses: 0,
+                chatters: 0,
+                fastest_transition: None,
+            });
+
+        // The first event for a key has nothing to compare against
+        if history.presses > 0 || history.pressed_since.is_some() {
+            let interval = event
+                .time
+                .saturating_duration_since(history.last_transition);
+            if interval < config.min_transition {
+                history.chatters += 1;
+            }
+            if history.fastest_transition.map_or(true, |x| interval < x) {
+                history.fastest_transition = Some(interval);
+            }
+        }
+        history.last_transition = event.time;
+
+        if event.pressed {
+            history.presses += 1;
+            history.pressed_since = Some(event.time);
+        } else {
+            history.pressed_since = None;
+        }
+    }
+
+    fn history_stuck_for(&self, history: &KeyHistory, now: Instant) -> Option<Duration> {
+        history
+            .pressed_since
+            .map(|since| now.saturating_duration_since(since))
+            .filter(|held| *held >= self.config.stuck_after)
+    }
+
+    /// How long a key has been held at `now`, if longer than `ChatterConfig::stuck_after`
+    pub fn stuck_for(&self, logical_name: &str, now: Instant) -> Option<Duration> {
+        self.history_stuck_for(self.keys.get(logical_name)?, now)
+    }
+
+    /// Report keys with problems, treating keys held at `now` as stuck if held too long
+    pub fn report(&self, now: Instant) -> ChatterReport {
+        let keys = self
+            .keys
+            .iter()
+            .filter_map(|(logical_name, history)| {
+                let stuck_for = self.history_stuck_for(history, now);
+                if history.chatters == 0 && stuck_for.is_none() {
+                    return None;
+                }
+                Some(ChatterKey {
+                    logical_name: logical_name.clone(),
+                    physical_name: history.physical_name.clone(),
+                    electrical: history.electrical,
+                    presses: history.presses,
+                    chatters: history.chatters,
+                    fastest_transition: history.fastest_transition,
+                    stuck_for,
+                })
+            })
+            .collect();
+        ChatterReport {
+            keys_tested: self.keys.values().filter(|x| x.presses > 0).count(),
+            keys,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.keys.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chatter_and_stuck() {
+        let event = KeyEvent::mock;
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut detector = ChatterDetector::new(ChatterConfig::default());
+
+        // Normal typing
+        detector.handle_event(&event("K00", true, ms(0)));
+        detector.handle_event(&event("K00", false, ms(80)));
+        detector.handle_event(&event("K00", true, ms(200)));
+        detector.handle_event(&event("K00", false, ms(290)));
+
+        // Release and press again within a few milliseconds
+        detector.handle_event(&event("K01", true, ms(0)));
+        detector.handle_event(&event("K01", false, ms(70)));
+        detector.handle_event(&event("K01", true, ms(75)));
+        detector.handle_event(&event("K01", false, ms(150)));
+
+        // Never released
+        detector.handle_event(&event("K02", true, ms(100)));
+
+        let report = detector.report(ms(1000));
+        assert_eq!(report.keys_tested, 3);
+        assert_eq!(report.keys.len(), 1);
+        assert!(!report.success());
+
+        let report = detector.report(ms(20_000));
+        assert_eq!(report.keys.len(), 2);
+        assert_eq!(report.keys[0].logical_name, "K01");
+        assert_eq!(report.keys[0].chatters, 1);
+        assert_eq!(
+            report.keys[0].fastest_transition,
+            Some(Duration::from_millis(5))
+        );
+        assert!(!report.keys[0].is_stuck());
+        assert_eq!(report.keys[1].logical_name, "K02");
+        assert!(report.keys[1].is_stuck());
+    }
+}
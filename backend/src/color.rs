@@ -47,7 +47,7 @@ impl Hs {
 
 /// Integer RGB color
 #[cfg_attr(feature = "glib", derive(glib::Boxed))]
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "glib", boxed_type(name = "S76Rgb"))]
 pub struct Rgb {
     /// Red
@@ -59,7 +59,7 @@ pub struct Rgb {
 }
 
 impl Rgb {
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
 
@@ -75,13 +75,19 @@ impl Rgb {
         (self.r.convert(), self.g.convert(), self.b.convert())
     }
 
-    /// Parse from hexadecimal string
+    /// Parse a color like `#ff8000`, or the short form `#f80`
     pub fn parse(s: &str) -> Option<Self> {
-        if s.len() == 7 && s.starts_with('#') {
+        if !s.is_ascii() || !s.starts_with('#') {
+            return None;
+        }
+        if s.len() == 7 {
             let r = u8::from_str_radix(&s[1..3], 16).ok()?;
             let g = u8::from_str_radix(&s[3..5], 16).ok()?;
             let b = u8::from_str_radix(&s[5..7], 16).ok()?;
             Some(Self::new(r, g, b))
+        } else if s.len() == 4 {
+            let digit = |i| Some(u8::from_str_radix(&s[i..i + 1], 16).ok()? * 0x11);
+            Some(Self::new(digit(1)?, digit(2)?, digit(3)?))
         } else {
             None
         }
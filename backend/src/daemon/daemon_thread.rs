@@ -6,20 +6,23 @@ use futures::{
     task::LocalSpawnExt,
 };
 use futures_timer::Delay;
-use once_cell::sync::Lazy;
 use std::{
     cell::{Cell, RefCell},
     cmp::PartialEq,
     collections::HashMap,
     hash::{Hash, Hasher},
+    path::Path,
     rc::Rc,
     sync::{Arc, Mutex, Weak},
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use super::{Benchmark, BoardId, Daemon, Matrix, Nelson, NelsonKind};
-use crate::{Board, BoardEvent, Bootloaded, Event};
+use super::{Benchmark, BoardId, Daemon, Matrix, Nelson};
+use crate::{
+    BenchmarkConfig, Board, BoardEvent, Bootloaded, Capability, Event, KeyEvent, NelsonConfig,
+    UsbHubLayout,
+};
 
 #[derive(Clone, Debug)]
 struct Item<K: Hash + Eq, V> {
@@ -53,8 +56,8 @@ enum SetEnum {
     Color(Item<(BoardId, u8), (u8, u8, u8)>),
     Brightness(Item<(BoardId, u8), i32>),
     Mode(Item<(BoardId, u8), (u8, u8)>),
-    Benchmark(BoardId),
-    Nelson(BoardId, NelsonKind),
+    Benchmark(BoardId, UsbHubLayout, BenchmarkConfig),
+    Nelson(BoardId, NelsonConfig),
     LedSave(BoardId),
     MatrixGetRate(Item<(), Option<Duration>>),
     Refresh,
@@ -65,7 +68,7 @@ enum SetEnum {
 
 impl SetEnum {
     fn is_cancelable(&self) -> bool {
-        !matches!(self, Self::Nelson(_, _) | Self::Benchmark(_))
+        !matches!(self, Self::Nelson(_, _) | Self::Benchmark(_, _, _))
     }
 }
 
@@ -166,33 +169,8 @@ impl ThreadClient {
     }
 
     pub async fn check_for_bootloader(&self) -> Result<(), String> {
-        use regex::bytes::Regex;
-        static HAS_USB_HUB: Lazy<Regex> =
-            Lazy::new(|| Regex::new("3384:000.*System76 USB").unwrap());
-        static ATMEGA32U4: Lazy<Regex> =
-            Lazy::new(|| Regex::new("03eb:2ff4.*atmega32u4.*bootloader").unwrap());
-        static AT90USB646: Lazy<Regex> =
-            Lazy::new(|| Regex::new("03eb:2ff9.*at90usb646.*bootloader").unwrap());
-
-        let lsusb = async_process::Command::new("lsusb")
-            .arg("--verbose")
-            .output()
-            .await
-            .map_err(|_| "Failed to run lsusb".to_string())?
-            .stdout;
-
-        let update = if AT90USB646.is_match(&lsusb) {
-            if HAS_USB_HUB.is_match(&lsusb) {
-                Some(Bootloaded::At90usb646)
-            } else {
-                Some(Bootloaded::At90usb646Lite)
-            }
-        } else if ATMEGA32U4.is_match(&lsusb) {
-            Some(Bootloaded::AtMega32u4)
-        } else {
-            None
-        };
-
+        let update = Bootloaded::detect(Path::new("/sys"))
+            .map_err(|err| format!("Failed to read USB devices: {}", err))?;
         self.send_noresp(SetEnum::BootLoaderUpdate(update)).await
     }
 
@@ -247,8 +225,15 @@ impl ThreadClient {
             .await
     }
 
-    pub async fn benchmark(&self, board: BoardId) -> Result<Benchmark, String> {
-        let resp = self.send(SetEnum::Benchmark(board)).await?;
+    pub async fn benchmark(
+        &self,
+        board: BoardId,
+        usb_hub: UsbHubLayout,
+        config: BenchmarkConfig,
+    ) -> Result<Benchmark, String> {
+        let resp = self
+            .send(SetEnum::Benchmark(board, usb_hub, config))
+            .await?;
         if let Response::Benchmark(benchmark) = resp {
             Ok(benchmark)
         } else {
@@ -256,8 +241,8 @@ impl ThreadClient {
         }
     }
 
-    pub async fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, String> {
-        let resp = self.send(SetEnum::Nelson(board, kind)).await?;
+    pub async fn nelson(&self, board: BoardId, config: NelsonConfig) -> Result<Nelson, String> {
+        let resp = self.send(SetEnum::Nelson(board, config)).await?;
         if let Response::Nelson(nelson) = resp {
             Ok(*nelson)
         } else {
@@ -293,30 +278,60 @@ impl ThreadClient {
     }
 }
 
-/* TODO
-    BootloadedAdded(Bootloaded),
-    BootloadedRemoved
-*/
-
 struct ThreadBoard {
     matrix: Arc<Mutex<Matrix>>,
     board: BoardId,
     event_sender: async_mpsc::UnboundedSender<Event>,
     has_matrix: bool,
+    /// Logical and physical key names, by electrical position
+    key_names: HashMap<(u8, u8), (String, String)>,
 }
 
 impl ThreadBoard {
     fn new(
-        board: BoardId,
+        board: &Board,
         event_sender: async_mpsc::UnboundedSender<Event>,
-        has_matrix: bool,
         matrix: Arc<Mutex<Matrix>>,
     ) -> Self {
+        let key_names = board
+            .keys()
+            .iter()
+            .map(|key| {
+                (
+                    key.electrical,
+                    (key.logical_name.clone(), key.physical_name.clone()),
+                )
+            })
+            .collect();
         Self {
             matrix,
-            board,
+            board: board.board(),
             event_sender,
-            has_matrix,
+            has_matrix: board.capabilities().has(Capability::Matrix),
+            key_names,
+        }
+    }
+
+    fn send_event(&self, event: BoardEvent) {
+        let _ = self
+            .event_sender
+            .unbounded_send(Event::Board(self.board, event));
+    }
+
+    /// Send key events for differences between the stored matrix and `matrix`
+    fn send_key_events(&self, previous: &Matrix, matrix: &Matrix) {
+        let time = Instant::now();
+        for (row, col, pressed) in matrix.changes(previous) {
+            let electrical = (row as u8, col as u8);
+            if let Some((logical_name, physical_name)) = self.key_names.get(&electrical) {
+                self.send_event(BoardEvent::Key(KeyEvent {
+                    pressed,
+                    logical_name: logical_name.clone(),
+                    physical_name: physical_name.clone(),
+                    electrical,
+                    time,
+                }));
+            }
         }
     }
 }
@@ -397,8 +412,16 @@ impl Thread {
             SetEnum::Mode(Item { key, value }) => {
                 set.reply(self.daemon.set_mode(key.0, key.1, value.0, value.1))
             }
-            SetEnum::Benchmark(board) => set.reply(self.daemon.benchmark(board)),
-            SetEnum::Nelson(board, kind) => set.reply(self.daemon.nelson(board, kind)),
+            SetEnum::Benchmark(board, ref usb_hub, ref config) => {
+                let res = self
+                    .daemon
+                    .benchmark(board, usb_hub.clone(), config.clone());
+                set.reply(res)
+            }
+            SetEnum::Nelson(board, ref config) => {
+                let res = self.daemon.nelson(board, config.clone());
+                set.reply(res)
+            }
             SetEnum::LedSave(board) => set.reply(self.daemon.led_save(board)),
             SetEnum::MatrixGetRate(Item { value, .. }) => {
                 self.matrix_get_rate.set(value);
@@ -429,10 +452,10 @@ impl Thread {
             };
             let mut matrix_lock = v.matrix.lock().unwrap();
             if *matrix_lock != matrix {
-                *matrix_lock = matrix;
-                let _ = v
-                    .event_sender
-                    .unbounded_send(Event::Board(v.board, BoardEvent::MatrixChanged));
+                let previous = std::mem::replace(&mut *matrix_lock, matrix);
+                v.send_key_events(&previous, &matrix_lock);
+                drop(matrix_lock);
+                v.send_event(BoardEvent::MatrixChanged);
             }
         }
     }
@@ -455,6 +478,13 @@ impl Thread {
             (Some(_), None) => {
                 let _ = self.event_sender.unbounded_send(Event::BootloadedRemoved);
             }
+            // A different board was plugged in between checks
+            (Some(previous), Some(board)) if previous != board => {
+                let _ = self.event_sender.unbounded_send(Event::BootloadedRemoved);
+                let _ = self
+                    .event_sender
+                    .unbounded_send(Event::BootloadedAdded(board));
+            }
             _ => {}
         }
         Ok(())
@@ -498,10 +528,7 @@ impl Thread {
                 self.event_sender.clone(),
             ) {
                 Ok(board) => {
-                    boards.insert(
-                        *i,
-                        ThreadBoard::new(*i, event_sender.clone(), board.has_matrix(), matrix),
-                    );
+                    boards.insert(*i, ThreadBoard::new(&board, event_sender.clone(), matrix));
                     let _ = self.event_sender.unbounded_send(Event::BoardAdded(board));
                 }
                 Err(err) => error!("Failed to add board: {}", err),
@@ -1,7 +1,7 @@
 use std::{cell::RefCell, collections::HashMap};
 
 use super::{BoardId, Daemon};
-use crate::{fl, Benchmark, Layout, Matrix, Nelson, NelsonKind};
+use crate::{fl, Benchmark, BenchmarkConfig, Layout, Matrix, Nelson, NelsonConfig, UsbHubLayout};
 
 struct BoardDummy {
     name: String,
@@ -32,19 +32,21 @@ impl DaemonDummy {
     pub fn new(board_names: Vec<String>) -> Result<Self, String> {
         let mut boards = Vec::with_capacity(board_names.len());
         for name in board_names {
-            if let Some(layout) = Layout::from_board(&name, "dummy") {
-                boards.push(BoardDummy {
+            match Layout::from_board(&name, "dummy") {
+                Ok(layout) => boards.push(BoardDummy {
                     layout,
                     name,
                     keymap: Default::default(),
                     colors: Default::default(),
                     brightnesses: Default::default(),
                     modes: Default::default(),
-                })
-            } else {
-                return Err(format!(
-                    "'{name}' is an invalid board name. Might need a prefix, 'system76/{name}'?"
-                ));
+                }),
+                Err(_) if !name.contains('/') => {
+                    return Err(format!(
+                        "'{name}' is an invalid board name. Might need a prefix, 'system76/{name}'?"
+                    ));
+                }
+                Err(err) => return Err(err),
             }
         }
         Ok(Self { boards })
@@ -70,6 +72,10 @@ impl Daemon for DaemonDummy {
         Ok("1970-01-01-deadbee".to_string())
     }
 
+    fn layer_count(&self, _board: BoardId) -> Result<u8, String> {
+        Err("Unimplemented".to_string())
+    }
+
     fn is_fake(&self) -> bool {
         true
     }
@@ -96,11 +102,16 @@ impl Daemon for DaemonDummy {
         Ok(Matrix::new(0, 0, Vec::new().into_boxed_slice()))
     }
 
-    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, String> {
+    fn benchmark(
+        &self,
+        _board: BoardId,
+        _usb_hub: UsbHubLayout,
+        _config: BenchmarkConfig,
+    ) -> Result<Benchmark, String> {
         Err("Unimplemented".to_string())
     }
 
-    fn nelson(&self, _board: BoardId, _kind: NelsonKind) -> Result<Nelson, String> {
+    fn nelson(&self, _board: BoardId, _config: NelsonConfig) -> Result<Nelson, String> {
         Err("Unimplemented".to_string())
     }
 
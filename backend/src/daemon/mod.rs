@@ -1,18 +1,19 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{Benchmark, Matrix, Nelson, NelsonKind};
+use crate::{Benchmark, BenchmarkConfig, Matrix, Nelson, NelsonConfig, UsbHubLayout};
 
 mod client;
 mod daemon_thread;
 mod dummy;
 mod server;
+mod via;
 
 #[cfg(target_os = "linux")]
 mod s76power;
 #[cfg(target_os = "linux")]
 pub use self::s76power::*;
 
-pub use self::{client::*, daemon_thread::*, dummy::*, server::*};
+pub use self::{client::*, daemon_thread::*, dummy::*, server::*, via::*};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub struct BoardId(u128);
@@ -81,12 +82,13 @@ commands! {
     fn boards(&self) -> Result<Vec<BoardId>, String>;
     fn model(&self, board: BoardId) -> Result<String, String>;
     fn version(&self, board: BoardId) -> Result<String, String>;
+    fn layer_count(&self, board: BoardId) -> Result<u8, String>;
     fn refresh(&self) -> Result<(), String>;
     fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String>;
     fn keymap_set(&self, board: BoardId, layer: u8, output: u8, input: u8, value: u16) -> Result<(), String>;
     fn matrix_get(&self, board: BoardId) -> Result<Matrix, String>;
-    fn benchmark(&self, board: BoardId) -> Result<Benchmark, String>;
-    fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, String>;
+    fn benchmark(&self, board: BoardId, usb_hub: UsbHubLayout, config: BenchmarkConfig) -> Result<Benchmark, String>;
+    fn nelson(&self, board: BoardId, config: NelsonConfig) -> Result<Nelson, String>;
     fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String>;
     fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String>;
     fn max_brightness(&self, board: BoardId) -> Result<i32, String>;
@@ -99,6 +101,6 @@ commands! {
     fn exit(&self) -> Result<(), String>;
 }
 
-fn err_str<E: std::fmt::Debug>(err: E) -> String {
+pub(crate) fn err_str<E: std::fmt::Debug>(err: E) -> String {
     format!("{:?}", err)
 }
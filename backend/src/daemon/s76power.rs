@@ -6,7 +6,7 @@ use std::iter::Iterator;
 use zbus::{dbus_proxy, fdo::ObjectManagerProxy, Connection};
 
 use super::{err_str, BoardId, Daemon, Matrix};
-use crate::{fl, Benchmark, Nelson, NelsonKind, Rgb};
+use crate::{fl, Benchmark, BenchmarkConfig, Nelson, NelsonConfig, Rgb, UsbHubLayout};
 
 const DBUS_NAME: &str = "com.system76.PowerDaemon";
 
@@ -89,6 +89,10 @@ impl Daemon for DaemonS76Power {
         Err("Unimplemented".to_string())
     }
 
+    fn layer_count(&self, _board: BoardId) -> Result<u8, String> {
+        Err("Unimplemented".to_string())
+    }
+
     fn keymap_get(
         &self,
         _board: BoardId,
@@ -114,11 +118,16 @@ impl Daemon for DaemonS76Power {
         Err("Unimplemented".to_string())
     }
 
-    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, String> {
+    fn benchmark(
+        &self,
+        _board: BoardId,
+        _usb_hub: UsbHubLayout,
+        _config: BenchmarkConfig,
+    ) -> Result<Benchmark, String> {
         Err("Unimplemented".to_string())
     }
 
-    fn nelson(&self, _board: BoardId, _kind: NelsonKind) -> Result<Nelson, String> {
+    fn nelson(&self, _board: BoardId, _config: NelsonConfig) -> Result<Nelson, String> {
         Err("Unimplemented".to_string())
     }
 
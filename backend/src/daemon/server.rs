@@ -4,7 +4,8 @@ use ectool::{Access, AccessHid, Ec};
 use hidapi::{DeviceInfo, HidApi};
 use std::{
     cell::{Cell, RefCell, RefMut},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    ffi::{CStr, CString},
     io::{self, BufRead, BufReader, Read, Write},
     str,
     thread::sleep,
@@ -12,8 +13,11 @@ use std::{
 };
 use uuid::Uuid;
 
-use super::{err_str, BoardId, Daemon, DaemonCommand};
-use crate::{Benchmark, Matrix, Nelson, NelsonKind};
+use super::{err_str, BoardId, Daemon, DaemonCommand, ViaKeyboard};
+use crate::{
+    via_board, Benchmark, BenchmarkConfig, Matrix, Nelson, NelsonConfig, NelsonFixture,
+    UsbHubLayout,
+};
 
 const QMK_RAW_USAGE_PAGE: u16 = 0xFF60;
 const QMK_RAW_USAGE_ID: u16 = 0x61;
@@ -25,6 +29,10 @@ pub struct DaemonServer<R: Read + Send + 'static, W: Write + Send + 'static> {
     read: BufReader<R>,
     write: W,
     boards: RefCell<HashMap<BoardId, (Ec<Box<dyn Access>>, Option<DeviceInfo>)>>,
+    /// Other QMK keyboards, which speak the VIA protocol instead of the EC protocol
+    via_boards: RefCell<HashMap<BoardId, (ViaKeyboard, ViaDevice)>>,
+    /// Raw HID interfaces that didn't answer VIA commands, so they aren't probed every refresh
+    not_via: RefCell<HashSet<CString>>,
     board_ids: RefCell<Vec<BoardId>>,
     nelson: RefCell<Option<Ec<AccessHid>>>,
 }
@@ -73,22 +81,50 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
             read: BufReader::new(read),
             write,
             boards: RefCell::new(boards),
+            via_boards: RefCell::new(HashMap::new()),
+            not_via: RefCell::new(HashSet::new()),
             board_ids: RefCell::new(board_ids),
             nelson: RefCell::new(None),
         })
     }
 
-    fn have_device(&self, info: &DeviceInfo) -> bool {
-        for (_, i) in self.boards.borrow().values() {
-            if let Some(i) = i {
-                if (i.vendor_id(), i.product_id(), i.path())
-                    == (info.vendor_id(), info.product_id(), info.path())
-                {
-                    return true;
-                }
-            }
-        }
-        false
+    fn have_device(&self, vendor_id: u16, product_id: u16, path: &CStr) -> bool {
+        let device = (vendor_id, product_id, path);
+        let boards = self.boards.borrow();
+        let via_boards = self.via_boards.borrow();
+        boards
+            .values()
+            .filter_map(|(_, i)| i.as_ref())
+            .any(|i| (i.vendor_id(), i.product_id(), i.path()) == device)
+            || via_boards
+                .values()
+                .any(|(_, i)| (i.vendor_id, i.product_id, i.path.as_c_str()) == device)
+    }
+
+    fn add_via_board(&self, via: ViaKeyboard, device: ViaDevice) -> BoardId {
+        info!(
+            "Adding VIA keyboard at {:?}, with protocol version {} and {} layers",
+            device.path,
+            via.protocol_version(),
+            via.layer_count()
+        );
+        let id = BoardId(Uuid::new_v4().as_u128());
+        self.via_boards.borrow_mut().insert(id, (via, device));
+        self.board_ids.borrow_mut().push(id);
+        id
+    }
+
+    /// Remove USB boards that are no longer attached
+    fn remove_detached(&self) {
+        let mut boards = self.boards.borrow_mut();
+        let mut via_boards = self.via_boards.borrow_mut();
+        let mut board_ids = self.board_ids.borrow_mut();
+
+        boards.retain(|_, (ec, _)| unsafe {
+            !(ec.access().is::<AccessHid>() && ec.probe().is_err())
+        });
+        via_boards.retain(|_, (via, _)| via.probe().is_ok());
+        board_ids.retain(|i| boards.contains_key(i) || via_boards.contains_key(i));
     }
 
     pub fn run(mut self) -> io::Result<()> {
@@ -120,6 +156,11 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
             Err("failed to find board".to_string())
         }
     }
+
+    fn via_board(&self, board: BoardId) -> Option<RefMut<ViaKeyboard>> {
+        let boards = self.via_boards.borrow_mut();
+        RefMut::filter_map(boards, |x| x.get_mut(&board).map(|x| &mut x.0)).ok()
+    }
 }
 
 impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServer<R, W> {
@@ -128,6 +169,9 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
     }
 
     fn model(&self, board: BoardId) -> Result<String, String> {
+        if let Some((_, device)) = self.via_boards.borrow().get(&board) {
+            return Ok(via_board(device.vendor_id, device.product_id));
+        }
         let mut ec = self.board(board)?;
         let data_size = unsafe { ec.access().data_size() };
         let mut data = vec![0; data_size];
@@ -137,6 +181,9 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
     }
 
     fn version(&self, board: BoardId) -> Result<String, String> {
+        if let Some(via) = self.via_board(board) {
+            return Ok(via.version());
+        }
         let mut ec = self.board(board)?;
         let data_size = unsafe { ec.access().data_size() };
         let mut data = vec![0; data_size];
@@ -145,7 +192,17 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         Ok(version.to_string())
     }
 
+    fn layer_count(&self, board: BoardId) -> Result<u8, String> {
+        match self.via_board(board) {
+            Some(via) => Ok(via.layer_count()),
+            None => Err("Layer count not reported by EC protocol".to_string()),
+        }
+    }
+
     fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.keymap_get(layer, output, input);
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.keymap_get(layer, output, input).map_err(err_str) }
     }
@@ -158,11 +215,17 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         input: u8,
         value: u16,
     ) -> Result<(), String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.keymap_set(layer, output, input, value);
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.keymap_set(layer, output, input, value).map_err(err_str) }
     }
 
     fn matrix_get(&self, board: BoardId) -> Result<Matrix, String> {
+        if self.via_board(board).is_some() {
+            return Err("Matrix not supported over VIA".to_string());
+        }
         let mut ec = self.board(board)?;
 
         let data_size = unsafe { ec.access().data_size() };
@@ -174,61 +237,21 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         Ok(Matrix::new(rows, cols, data.into_boxed_slice()))
     }
 
-    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, String> {
-        Benchmark::new().map_err(err_str)
+    fn benchmark(
+        &self,
+        _board: BoardId,
+        usb_hub: UsbHubLayout,
+        config: BenchmarkConfig,
+    ) -> Result<Benchmark, String> {
+        Benchmark::new(&usb_hub, &config).map_err(err_str)
     }
 
-    fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, String> {
+    fn nelson(&self, board: BoardId, config: NelsonConfig) -> Result<Nelson, String> {
         if let Some(nelson) = &mut *self.nelson.borrow_mut() {
-            const DELAY_MS: u64 = 200;
-            info!("Nelson delay is {} ms", DELAY_MS);
-            let delay = Duration::from_millis(DELAY_MS);
-
-            // Check if Nelson is already closed
-            if unsafe { nelson.led_get_value(0).map_err(err_str)?.0 > 0 } {
-                info!("Open Nelson");
-                unsafe { nelson.led_set_value(0, 0).map_err(err_str)? };
-
-                info!("Sleep");
-                sleep(delay);
-            }
-
-            info!("Close Nelson");
-            unsafe { nelson.led_set_value(0, 1).map_err(err_str)? };
-
-            info!("Sleep");
-            sleep(delay);
-
-            // Get pressed keys while nelson is closed
-            let matrix = self.matrix_get(board)?;
-
-            // Either missing or bouncing is set depending on test
-            let (mut missing, bouncing) = match kind {
-                NelsonKind::Normal => (matrix, Matrix::default()),
-                NelsonKind::Bouncing => (Matrix::default(), matrix),
-            };
-
-            // Missing must be inverted, since missing keys are not pressed
-            for row in 0..missing.rows() {
-                for col in 0..missing.cols() {
-                    let value = missing.get(row, col).unwrap_or(false);
-                    missing.set(row, col, !value);
-                }
-            }
-
-            info!("Open Nelson");
-            unsafe { nelson.led_set_value(0, 0).map_err(err_str)? };
-
-            info!("Sleep");
-            sleep(delay);
-
-            // Anything still pressed after nelson is opened is sticking
-            let sticking = self.matrix_get(board)?;
-
-            Ok(Nelson {
-                missing,
-                bouncing,
-                sticking,
+            config.run(&mut EcNelson {
+                nelson,
+                server: self,
+                board,
             })
         } else {
             Err("failed to find Nelson".to_string())
@@ -236,11 +259,17 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
     }
 
     fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.color(index);
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.led_get_color(index) }.map_err(err_str)
     }
 
     fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.set_color(index, color);
+        }
         let mut ec = self.board(board)?;
         unsafe {
             ec.led_set_color(index, color.0, color.1, color.2)
@@ -249,6 +278,9 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
     }
 
     fn max_brightness(&self, board: BoardId) -> Result<i32, String> {
+        if let Some(via) = self.via_board(board) {
+            return via.max_brightness();
+        }
         let mut ec = self.board(board)?;
         let index = if unsafe { ec.access().is::<AccessHid>() } {
             0xf0
@@ -261,47 +293,58 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
     }
 
     fn brightness(&self, board: BoardId, index: u8) -> Result<i32, String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.brightness(index);
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.led_get_value(index).map(|x| x.0 as i32).map_err(err_str) }
     }
 
     fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.set_brightness(index, brightness);
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.led_set_value(index, brightness as u8).map_err(err_str) }
     }
 
     fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.mode();
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.led_get_mode(layer).map_err(err_str) }
     }
 
     fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.set_mode(mode, speed);
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.led_set_mode(layer, mode, speed).map_err(err_str) }
     }
 
     fn led_save(&self, board: BoardId) -> Result<(), String> {
+        if let Some(mut via) = self.via_board(board) {
+            return via.led_save();
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.led_save().map_err(err_str) }
     }
 
     fn refresh(&self) -> Result<(), String> {
         if let Some(api) = &mut *self.hidapi.borrow_mut() {
-            // Remove USB boards that are no longer attached
-            {
-                let mut boards = self.boards.borrow_mut();
-                let mut board_ids = self.board_ids.borrow_mut();
-
-                boards.retain(|_, (ec, _)| unsafe {
-                    !(ec.access().is::<AccessHid>() && ec.probe().is_err())
-                });
-                board_ids.retain(|i| boards.contains_key(i));
-            }
+            self.remove_detached();
 
             if let Err(err) = api.refresh_devices() {
                 error!("Failed to refresh hidapi devices: {}", err);
             }
 
+            // Forget interfaces that were unplugged, in case something else is plugged in there
+            self.not_via
+                .borrow_mut()
+                .retain(|path| api.device_list().any(|x| x.path() == path.as_c_str()));
+
             for info in api.device_list() {
                 match (
                     info.vendor_id(),
@@ -328,7 +371,7 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
                         if is_qmk_raw_interface(interface, usage_page, usage) =>
                     {
                         // Skip if device already open
-                        if self.have_device(info) {
+                        if self.have_device(info.vendor_id(), info.product_id(), info.path()) {
                             continue;
                         }
 
@@ -390,6 +433,41 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
                             }
                         }
                     }
+                    // Other QMK keyboards, if they have VIA enabled
+                    (vendor_id, _, interface, usage_page, usage)
+                        if vendor_id != 0x3384
+                            && is_qmk_raw_interface(interface, usage_page, usage) =>
+                    {
+                        // Skip if device already open, or known not to speak VIA
+                        let path = info.path();
+                        if self.have_device(info.vendor_id(), info.product_id(), path)
+                            || self.not_via.borrow().contains(path)
+                        {
+                            continue;
+                        }
+
+                        let device = ViaDevice {
+                            vendor_id: info.vendor_id(),
+                            product_id: info.product_id(),
+                            path: path.to_owned(),
+                        };
+                        match info.open_device(api) {
+                            Ok(hid) => match ViaKeyboard::new(Box::new(hid)) {
+                                Ok(via) => {
+                                    self.add_via_board(via, device);
+                                }
+                                // Raw HID is also used without VIA, so this isn't an error
+                                Err(err) => {
+                                    debug!("No VIA keyboard at {:?}: {}", path, err);
+                                    self.not_via.borrow_mut().insert(device.path);
+                                }
+                            },
+                            Err(err) => {
+                                debug!("Failed to open raw HID at {:?}: {:?}", path, err);
+                                self.not_via.borrow_mut().insert(device.path);
+                            }
+                        }
+                    }
                     _ => (),
                 }
             }
@@ -399,6 +477,9 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
     }
 
     fn set_no_input(&self, board: BoardId, no_input: bool) -> Result<(), String> {
+        if self.via_board(board).is_some() {
+            return Err("No input mode not supported over VIA".to_string());
+        }
         let mut ec = self.board(board)?;
         unsafe { ec.set_no_input(no_input) }.map_err(err_str)
     }
@@ -409,6 +490,38 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
     }
 }
 
+/// USB IDs and path of a VIA keyboard, to name it and to not open it twice
+struct ViaDevice {
+    vendor_id: u16,
+    product_id: u16,
+    path: CString,
+}
+
+/// The Nelson, controlled through its EC, testing `board`
+struct EcNelson<'a, R: Read + Send + 'static, W: Write + Send + 'static> {
+    nelson: &'a mut Ec<AccessHid>,
+    server: &'a DaemonServer<R, W>,
+    board: BoardId,
+}
+
+impl<'a, R: Read + Send + 'static, W: Write + Send + 'static> NelsonFixture for EcNelson<'a, R, W> {
+    fn closed(&mut self) -> Result<bool, String> {
+        Ok(unsafe { self.nelson.led_get_value(0).map_err(err_str)?.0 > 0 })
+    }
+
+    fn set_closed(&mut self, closed: bool) -> Result<(), String> {
+        unsafe { self.nelson.led_set_value(0, closed.into()).map_err(err_str) }
+    }
+
+    fn matrix(&mut self) -> Result<Matrix, String> {
+        self.server.matrix_get(self.board)
+    }
+
+    fn sleep(&mut self, ms: u64) {
+        sleep(Duration::from_millis(ms));
+    }
+}
+
 // Getting the interface number isn't working on macOS 13.3
 // (https://github.com/libusb/hidapi/pull/530)
 // And `usage_page` and `usage` seem to have issues on Linux with older versions of `hidapi`.
@@ -419,3 +532,92 @@ fn is_qmk_raw_interface(interface: i32, usage_page: u16, usage: u16) -> bool {
         interface == 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::daemon::{DaemonResponse, MockViaHid};
+
+    fn server() -> DaemonServer<io::Empty, io::Sink> {
+        DaemonServer {
+            hidapi: RefCell::new(None),
+            running: Cell::new(true),
+            read: BufReader::new(io::empty()),
+            write: io::sink(),
+            boards: RefCell::new(HashMap::new()),
+            via_boards: RefCell::new(HashMap::new()),
+            not_via: RefCell::new(HashSet::new()),
+            board_ids: RefCell::new(Vec::new()),
+            nelson: RefCell::new(None),
+        }
+    }
+
+    #[test]
+    fn daemon_server_via() {
+        let daemon = server();
+        let detached = Arc::new(AtomicBool::new(false));
+        let hid = MockViaHid {
+            protocol: 12,
+            layers: 2,
+            rows: 2,
+            cols: 3,
+            // RGB light
+            channel: 2,
+            detached: detached.clone(),
+            ..Default::default()
+        };
+        let path = CString::new("/dev/hidraw9").unwrap();
+        let device = ViaDevice {
+            vendor_id: 0xfeed,
+            product_id: 0x6060,
+            path: path.clone(),
+        };
+        let id = daemon.add_via_board(ViaKeyboard::new(Box::new(hid)).unwrap(), device);
+
+        assert_eq!(daemon.boards().unwrap(), vec![id]);
+        assert!(daemon.have_device(0xfeed, 0x6060, &path));
+        assert!(!daemon.have_device(0xfeed, 0x6061, &path));
+        assert_eq!(daemon.model(id).unwrap(), "via/feed_6060");
+        assert_eq!(daemon.version(id).unwrap(), "0.19.0 (VIA protocol 12)");
+        assert_eq!(daemon.layer_count(id).unwrap(), 2);
+
+        daemon.keymap_set(id, 1, 1, 2, 0x2129).unwrap();
+        assert_eq!(daemon.keymap_get(id, 1, 1, 2).unwrap(), 0x2129);
+        assert!(daemon.keymap_get(id, 2, 0, 0).is_err());
+        // As sent by the GUI, through the daemon protocol
+        let command = DaemonCommand::keymap_get {
+            board: id,
+            layer: 1,
+            output: 1,
+            input: 2,
+        };
+        assert!(matches!(
+            daemon.dispatch_command_to_method(command),
+            Ok(DaemonResponse::keymap_get(0x2129))
+        ));
+
+        assert_eq!(daemon.max_brightness(id).unwrap(), 255);
+        daemon.set_brightness(id, 0xff, 100).unwrap();
+        assert_eq!(daemon.brightness(id, 0xff).unwrap(), 100);
+        daemon.set_color(id, 0xff, (0, 0, 255)).unwrap();
+        assert_eq!(daemon.color(id, 0xff).unwrap(), (0, 0, 255));
+        daemon.led_save(id).unwrap();
+        assert!(daemon.matrix_get(id).is_err());
+        assert!(daemon.set_no_input(id, true).is_err());
+
+        // Still attached
+        daemon.remove_detached();
+        assert_eq!(daemon.boards().unwrap(), vec![id]);
+
+        detached.store(true, Ordering::SeqCst);
+        daemon.remove_detached();
+        assert_eq!(daemon.boards().unwrap(), Vec::new());
+        assert!(!daemon.have_device(0xfeed, 0x6060, &path));
+        assert!(daemon.model(id).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+use hidapi::HidDevice;
+#[cfg(test)]
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::err_str;
+use crate::{Hs, Rgb};
+
+/// Size of VIA raw HID reports, not counting the report ID
+pub const VIA_REPORT_SIZE: usize = 32;
+
+/// Oldest VIA protocol using QMK 0.19 keycode numbering and lighting channels
+const VIA_PROTOCOL_CHANNELS: u16 = 12;
+
+const ID_GET_PROTOCOL_VERSION: u8 = 0x01;
+const ID_DYNAMIC_KEYMAP_GET_KEYCODE: u8 = 0x04;
+const ID_DYNAMIC_KEYMAP_SET_KEYCODE: u8 = 0x05;
+const ID_CUSTOM_SET_VALUE: u8 = 0x07;
+const ID_CUSTOM_GET_VALUE: u8 = 0x08;
+const ID_CUSTOM_SAVE: u8 = 0x09;
+const ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+const ID_UNHANDLED: u8 = 0xFF;
+
+/// Lighting channels, in the order they are probed
+const CHANNEL_RGB_MATRIX: u8 = 3;
+const CHANNEL_RGBLIGHT: u8 = 2;
+const CHANNEL_BACKLIGHT: u8 = 1;
+
+/// Value IDs, which are the same in each lighting channel
+const VALUE_BRIGHTNESS: u8 = 1;
+const VALUE_EFFECT: u8 = 2;
+const VALUE_EFFECT_SPEED: u8 = 3;
+const VALUE_COLOR: u8 = 4;
+
+/// Raw HID connection to a keyboard speaking the VIA protocol
+pub trait ViaHid: Send {
+    /// Send `report`, and return the keyboard's reply
+    fn transfer(&mut self, report: &[u8; VIA_REPORT_SIZE])
+        -> Result<[u8; VIA_REPORT_SIZE], String>;
+}
+
+impl ViaHid for HidDevice {
+    fn transfer(
+        &mut self,
+        report: &[u8; VIA_REPORT_SIZE],
+    ) -> Result<[u8; VIA_REPORT_SIZE], String> {
+        // Report ID 0, since the QMK raw HID interface doesn't use report IDs
+        let mut data = [0; VIA_REPORT_SIZE + 1];
+        data[1..].copy_from_slice(report);
+        self.write(&data).map_err(err_str)?;
+
+        let mut reply = [0; VIA_REPORT_SIZE];
+        let len = self.read_timeout(&mut reply, 1000).map_err(err_str)?;
+        if len == 0 {
+            return Err("Timed out waiting for VIA reply".to_string());
+        }
+        Ok(reply)
+    }
+}
+
+/// QMK keyboard with VIA enabled, accessed with the VIA raw HID protocol
+///
+/// Keymaps are read and written with the dynamic keymap commands, with the electrical position of
+/// a key as its row and column. Lighting is only supported with VIA protocol 12 or newer, on the
+/// first of the RGB matrix, RGB light, and backlight channels the firmware handles, and applies
+/// to the whole keyboard.
+pub struct ViaKeyboard {
+    hid: Box<dyn ViaHid>,
+    protocol: u16,
+    layers: u8,
+    channel: Option<u8>,
+}
+
+impl ViaKeyboard {
+    /// Probe the protocol version, layer count, and lighting of the keyboard on `hid`
+    pub fn new(hid: Box<dyn ViaHid>) -> Result<Self, String> {
+        let mut keyboard = Self {
+            hid,
+            protocol: 0,
+            layers: 0,
+            channel: None,
+        };
+
+        let reply = keyboard.command(&[ID_GET_PROTOCOL_VERSION])?;
+        keyboard.protocol = u16::from_be_bytes([reply[1], reply[2]]);
+
+        let reply = keyboard.command(&[ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT])?;
+        keyboard.layers = reply[1];
+
+        if keyboard.protocol >= VIA_PROTOCOL_CHANNELS {
+            keyboard.channel = [CHANNEL_RGB_MATRIX, CHANNEL_RGBLIGHT, CHANNEL_BACKLIGHT]
+                .into_iter()
+                .find(|channel| {
+                    keyboard
+                        .command(&[ID_CUSTOM_GET_VALUE, *channel, VALUE_BRIGHTNESS])
+                        .is_ok()
+                });
+        }
+
+        Ok(keyboard)
+    }
+
+    /// Send a command, padded to a full report, and check that the keyboard handled it
+    fn command(&mut self, data: &[u8]) -> Result<[u8; VIA_REPORT_SIZE], String> {
+        let mut report = [0; VIA_REPORT_SIZE];
+        report[..data.len()].copy_from_slice(data);
+        let reply = self.hid.transfer(&report)?;
+        match reply[0] {
+            x if x == data[0] => Ok(reply),
+            ID_UNHANDLED => Err(format!("VIA command 0x{:02X} not handled", data[0])),
+            x => Err(format!(
+                "VIA command 0x{:02X} got reply to 0x{:02X}",
+                data[0], x
+            )),
+        }
+    }
+
+    /// Check that the keyboard is still attached
+    pub fn probe(&mut self) -> Result<(), String> {
+        self.command(&[ID_GET_PROTOCOL_VERSION])?;
+        Ok(())
+    }
+
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol
+    }
+
+    /// Version of QMK implied by the protocol version, since VIA doesn't report the QMK version
+    ///
+    /// Protocol 12 came with the renumbering of keycodes in QMK 0.19, which decides the keymap
+    /// table `Layout` uses.
+    pub fn version(&self) -> String {
+        let qmk = if self.protocol >= VIA_PROTOCOL_CHANNELS {
+            "0.19.0"
+        } else {
+            "0.18.0"
+        };
+        format!("{} (VIA protocol {})", qmk, self.protocol)
+    }
+
+    pub fn layer_count(&self) -> u8 {
+        self.layers
+    }
+
+    fn check_layer(&self, layer: u8) -> Result<(), String> {
+        if layer < self.layers {
+            Ok(())
+        } else {
+            Err(format!(
+                "Layer {} out of range, keyboard has {} layers",
+                layer, self.layers
+            ))
+        }
+    }
+
+    pub fn keymap_get(&mut self, layer: u8, row: u8, col: u8) -> Result<u16, String> {
+        self.check_layer(layer)?;
+        let reply = self.command(&[ID_DYNAMIC_KEYMAP_GET_KEYCODE, layer, row, col])?;
+        Ok(u16::from_be_bytes([reply[4], reply[5]]))
+    }
+
+    pub fn keymap_set(&mut self, layer: u8, row: u8, col: u8, value: u16) -> Result<(), String> {
+        self.check_layer(layer)?;
+        let [high, low] = value.to_be_bytes();
+        self.command(&[ID_DYNAMIC_KEYMAP_SET_KEYCODE, layer, row, col, high, low])?;
+        Ok(())
+    }
+
+    /// Lighting channel, if `index` is one of the indices used for the whole keyboard
+    fn channel(&self, index: u8) -> Result<u8, String> {
+        let channel = self
+            .channel
+            .ok_or_else(|| format!("No VIA lighting, with protocol version {}", self.protocol))?;
+        match index {
+            0xf0 | 0xff => Ok(channel),
+            _ => Err(format!("VIA lighting has no LED index {}", index)),
+        }
+    }
+
+    fn get_value(&mut self, index: u8, value: u8) -> Result<[u8; VIA_REPORT_SIZE], String> {
+        let channel = self.channel(index)?;
+        self.command(&[ID_CUSTOM_GET_VALUE, channel, value])
+    }
+
+    fn set_value(&mut self, index: u8, value: u8, data: &[u8]) -> Result<(), String> {
+        let channel = self.channel(index)?;
+        let mut command = vec![ID_CUSTOM_SET_VALUE, channel, value];
+        command.extend_from_slice(data);
+        self.command(&command)?;
+        Ok(())
+    }
+
+    pub fn max_brightness(&self) -> Result<i32, String> {
+        self.channel(0xff)?;
+        Ok(255)
+    }
+
+    pub fn brightness(&mut self, index: u8) -> Result<i32, String> {
+        Ok(self.get_value(index, VALUE_BRIGHTNESS)?[3].into())
+    }
+
+    pub fn set_brightness(&mut self, index: u8, brightness: i32) -> Result<(), String> {
+        let brightness = brightness.clamp(0, 255) as u8;
+        self.set_value(index, VALUE_BRIGHTNESS, &[brightness])
+    }
+
+    /// Color, which VIA stores as hue and saturation
+    pub fn color(&mut self, index: u8) -> Result<(u8, u8, u8), String> {
+        let reply = self.get_value(index, VALUE_COLOR)?;
+        let rgb = Hs::from_ints(reply[3], reply[4]).to_rgb();
+        Ok((rgb.r, rgb.g, rgb.b))
+    }
+
+    pub fn set_color(&mut self, index: u8, color: (u8, u8, u8)) -> Result<(), String> {
+        let (h, s) = Rgb::new(color.0, color.1, color.2).to_hs_lossy().to_ints();
+        self.set_value(index, VALUE_COLOR, &[h, s])
+    }
+
+    /// Effect and effect speed, numbered as in the firmware rather than as a `Mode`
+    pub fn mode(&mut self) -> Result<(u8, u8), String> {
+        let effect = self.get_value(0xff, VALUE_EFFECT)?[3];
+        let speed = self.get_value(0xff, VALUE_EFFECT_SPEED)?[3];
+        Ok((effect, speed))
+    }
+
+    pub fn set_mode(&mut self, mode: u8, speed: u8) -> Result<(), String> {
+        self.set_value(0xff, VALUE_EFFECT, &[mode])?;
+        self.set_value(0xff, VALUE_EFFECT_SPEED, &[speed])
+    }
+
+    pub fn led_save(&mut self) -> Result<(), String> {
+        let channel = self.channel(0xff)?;
+        self.command(&[ID_CUSTOM_SAVE, channel])?;
+        Ok(())
+    }
+}
+
+/// Keyboard emulating the VIA protocol in memory
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockViaHid {
+    pub protocol: u16,
+    pub layers: u8,
+    pub rows: u8,
+    pub cols: u8,
+    /// Lighting channel handled, or 0 for none
+    pub channel: u8,
+    /// Set to make transfers fail, like an unplugged keyboard
+    pub detached: Arc<AtomicBool>,
+    pub keymap: HashMap<(u8, u8, u8), u16>,
+    pub values: HashMap<u8, Vec<u8>>,
+}
+
+#[cfg(test)]
+impl ViaHid for MockViaHid {
+    fn transfer(
+        &mut self,
+        report: &[u8; VIA_REPORT_SIZE],
+    ) -> Result<[u8; VIA_REPORT_SIZE], String> {
+        if self.detached.load(Ordering::SeqCst) {
+            return Err("Mock keyboard detached".to_string());
+        }
+        let mut reply = *report;
+        let in_matrix = report[1] < self.layers && report[2] < self.rows && report[3] < self.cols;
+        match report[0] {
+            ID_GET_PROTOCOL_VERSION => reply[1..3].copy_from_slice(&self.protocol.to_be_bytes()),
+            ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT => reply[1] = self.layers,
+            ID_DYNAMIC_KEYMAP_GET_KEYCODE if in_matrix => {
+                let key = (report[1], report[2], report[3]);
+                let value = self.keymap.get(&key).copied().unwrap_or(0);
+                reply[4..6].copy_from_slice(&value.to_be_bytes());
+            }
+            ID_DYNAMIC_KEYMAP_SET_KEYCODE if in_matrix => {
+                let key = (report[1], report[2], report[3]);
+                let value = u16::from_be_bytes([report[4], report[5]]);
+                self.keymap.insert(key, value);
+            }
+            ID_CUSTOM_GET_VALUE if report[1] == self.channel => {
+                let value = self.values.get(&report[2]).cloned().unwrap_or_default();
+                reply[3..3 + value.len()].copy_from_slice(&value);
+            }
+            ID_CUSTOM_SET_VALUE if report[1] == self.channel => {
+                let len = if report[2] == VALUE_COLOR { 2 } else { 1 };
+                self.values.insert(report[2], report[3..3 + len].to_vec());
+            }
+            ID_CUSTOM_SAVE if report[1] == self.channel => {}
+            // Commands out of range are ignored by QMK, and others are unhandled
+            ID_DYNAMIC_KEYMAP_GET_KEYCODE | ID_DYNAMIC_KEYMAP_SET_KEYCODE => {}
+            _ => reply[0] = ID_UNHANDLED,
+        }
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn via_keyboard_mock() {
+        let hid = MockViaHid {
+            protocol: 12,
+            layers: 4,
+            rows: 2,
+            cols: 3,
+            channel: CHANNEL_RGBLIGHT,
+            ..Default::default()
+        };
+        let mut keyboard = ViaKeyboard::new(Box::new(hid)).unwrap();
+        assert_eq!(keyboard.protocol_version(), 12);
+        assert_eq!(keyboard.version(), "0.19.0 (VIA protocol 12)");
+        assert_eq!(keyboard.layer_count(), 4);
+        assert_eq!(keyboard.channel, Some(CHANNEL_RGBLIGHT));
+
+        // Keycodes are big-endian, like `MT(MOD_LCTL, KC_ESC)`
+        keyboard.keymap_set(1, 1, 2, 0x2129).unwrap();
+        assert_eq!(keyboard.keymap_get(1, 1, 2).unwrap(), 0x2129);
+        assert_eq!(keyboard.keymap_get(0, 1, 2).unwrap(), 0);
+        assert!(keyboard.keymap_get(4, 0, 0).is_err());
+
+        keyboard.set_brightness(0xff, 300).unwrap();
+        assert_eq!(keyboard.brightness(0xff).unwrap(), 255);
+        assert_eq!(keyboard.brightness(0xf0).unwrap(), 255);
+        assert!(keyboard.brightness(0).is_err());
+        assert_eq!(keyboard.max_brightness().unwrap(), 255);
+
+        keyboard.set_color(0xff, (255, 0, 0)).unwrap();
+        assert_eq!(keyboard.color(0xff).unwrap(), (255, 0, 0));
+
+        keyboard.set_mode(5, 128).unwrap();
+        assert_eq!(keyboard.mode().unwrap(), (5, 128));
+
+        keyboard.led_save().unwrap();
+
+        // Before protocol 12, only keymaps are supported
+        let hid = MockViaHid {
+            protocol: 9,
+            layers: 2,
+            rows: 1,
+            cols: 1,
+            channel: CHANNEL_RGBLIGHT,
+            ..Default::default()
+        };
+        let mut keyboard = ViaKeyboard::new(Box::new(hid)).unwrap();
+        assert_eq!(keyboard.version(), "0.18.0 (VIA protocol 9)");
+        assert!(keyboard.brightness(0xff).is_err());
+        assert!(keyboard.led_save().is_err());
+        keyboard.keymap_set(1, 0, 0, 0x04).unwrap();
+        assert_eq!(keyboard.keymap_get(1, 0, 0).unwrap(), 0x04);
+
+        // No lighting channel handled
+        let hid = MockViaHid {
+            protocol: 12,
+            layers: 1,
+            ..Default::default()
+        };
+        let keyboard = ViaKeyboard::new(Box::new(hid)).unwrap();
+        assert_eq!(keyboard.channel, None);
+        assert!(keyboard.max_brightness().is_err());
+    }
+}
@@ -0,0 +1,15 @@
+use std::{env, path::PathBuf};
+
+/// Directory for persistent application data, following the XDG base directory spec
+pub fn data_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    let dir = env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+    #[cfg(not(target_os = "windows"))]
+    let dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".local/share")));
+
+    Some(dir?.join("system76-keyboard-configurator"))
+}
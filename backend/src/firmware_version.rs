@@ -0,0 +1,231 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{cmp::Ordering, fmt};
+
+/// Firmware a keyboard runs, which determines how its version is numbered
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FirmwareKind {
+    /// System76 EC, versioned by build date, like `2022-05-23_c4bc87b`
+    Ec,
+    /// QMK, versioned like `0.19.12`
+    Qmk,
+}
+
+impl fmt::Display for FirmwareKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ec => write!(f, "EC"),
+            Self::Qmk => write!(f, "QMK"),
+        }
+    }
+}
+
+/// Version reported by keyboard firmware
+///
+/// Versions of the same kind compare by date or version number. Versions that could not be
+/// parsed, like those of fake keyboards, don't compare with anything.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FirmwareVersion {
+    kind: FirmwareKind,
+    version: String,
+    number: Option<(u16, u16, u16)>,
+}
+
+impl FirmwareVersion {
+    pub fn new(version: &str, kind: FirmwareKind) -> Self {
+        static EC_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\d+)-(\d+)-(\d+)_").unwrap());
+        static QMK_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\d+)\.(\d+)\.(\d+)").unwrap());
+
+        let re = match kind {
+            FirmwareKind::Ec => &EC_RE,
+            FirmwareKind::Qmk => &QMK_RE,
+        };
+        let number = re.captures(version).and_then(|groups| {
+            let group = |i| groups.get(i)?.as_str().parse::<u16>().ok();
+            Some((group(1)?, group(2)?, group(3)?))
+        });
+        Self {
+            kind,
+            version: version.to_string(),
+            number,
+        }
+    }
+
+    pub fn kind(&self) -> FirmwareKind {
+        self.kind
+    }
+
+    /// Build date of EC firmware, as year, month, and day
+    pub fn ec_date(&self) -> Option<(u16, u16, u16)> {
+        self.number.filter(|_| self.kind == FirmwareKind::Ec)
+    }
+
+    /// Major, minor, and patch version of QMK firmware
+    pub fn qmk_version(&self) -> Option<(u16, u16, u16)> {
+        self.number.filter(|_| self.kind == FirmwareKind::Qmk)
+    }
+
+    /// Check if this firmware supports `feature`, or explain why not
+    pub fn supports(&self, feature: FirmwareFeature) -> Result<(), String> {
+        let (_, ec, qmk) = FEATURES.iter().find(|(x, _, _)| *x == feature).unwrap();
+        let requirement = match self.kind {
+            FirmwareKind::Ec => ec,
+            FirmwareKind::Qmk => qmk,
+        };
+        let (number, met) = match *requirement {
+            Requirement::Always => return Ok(()),
+            Requirement::Never => return Err(format!("Not supported by {} firmware", self.kind)),
+            // Assume unknown versions are recent
+            Requirement::Since(number) => (number, self.number.map_or(true, |x| x >= number)),
+            Requirement::Before(number) => (number, self.number.map_or(false, |x| x < number)),
+        };
+        if met {
+            return Ok(());
+        }
+        let (a, b, c) = number;
+        let number = match self.kind {
+            FirmwareKind::Ec => format!("{}-{:02}-{:02}", a, b, c),
+            FirmwareKind::Qmk => format!("{}.{}.{}", a, b, c),
+        };
+        Err(match requirement {
+            Requirement::Since(_) => format!(
+                "Requires {} firmware {} or newer, but version is {}",
+                self.kind, number, self.version
+            ),
+            _ => format!(
+                "Only used by {} firmware older than {}, but version is {}",
+                self.kind, number, self.version
+            ),
+        })
+    }
+
+    pub fn has(&self, feature: FirmwareFeature) -> bool {
+        self.supports(feature).is_ok()
+    }
+
+    /// Support for every feature in the capability table
+    pub fn features(&self) -> Vec<(FirmwareFeature, Result<(), String>)> {
+        FEATURES
+            .iter()
+            .map(|(feature, _, _)| (*feature, self.supports(*feature)))
+            .collect()
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.version)
+    }
+}
+
+impl PartialOrd for FirmwareVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.kind != other.kind {
+            return None;
+        }
+        match (self.number, other.number) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ if self.version == other.version => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+/// Feature that depends on the firmware version
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FirmwareFeature {
+    /// `PAUSE` scancode
+    Pause,
+    /// `FNLOCK` scancode
+    FnLock,
+    /// Mod-tap bindings, like `MT(LEFT_CTRL, ESC)`
+    ModTap,
+    /// QMK keycodes from before the renumbering in QMK 0.19
+    LegacyScancodes,
+}
+
+impl fmt::Display for FirmwareFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Pause => "pause",
+            Self::FnLock => "fnlock",
+            Self::ModTap => "mod-tap",
+            Self::LegacyScancodes => "legacy scancodes",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+enum Requirement {
+    Always,
+    Never,
+    /// Date or version this was added in
+    Since((u16, u16, u16)),
+    /// Date or version this was removed in
+    Before((u16, u16, u16)),
+}
+
+/// Firmware needed for each feature, on EC and on QMK
+const FEATURES: &[(FirmwareFeature, Requirement, Requirement)] = &[
+    // Merge date of https://github.com/system76/ec/pull/229
+    (
+        FirmwareFeature::Pause,
+        Requirement::Since((2022, 5, 23)),
+        Requirement::Always,
+    ),
+    // https://github.com/system76/ec/pull/263
+    (
+        FirmwareFeature::FnLock,
+        Requirement::Since((2023, 8, 1)),
+        Requirement::Never,
+    ),
+    (
+        FirmwareFeature::ModTap,
+        Requirement::Never,
+        Requirement::Always,
+    ),
+    (
+        FirmwareFeature::LegacyScancodes,
+        Requirement::Never,
+        Requirement::Before((0, 19, 0)),
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_version_features() {
+        let ec = |x| FirmwareVersion::new(x, FirmwareKind::Ec);
+        let qmk = |x| FirmwareVersion::new(x, FirmwareKind::Qmk);
+
+        assert_eq!(ec("2022-05-23_c4bc87b").ec_date(), Some((2022, 5, 23)));
+        assert!(ec("2022-05-23_c4bc87b") > ec("2021-12-01_1234567"));
+        assert!(qmk("0.19.12") > qmk("0.7.104"));
+        assert_eq!(ec("2022-05-23_c4bc87b").partial_cmp(&qmk("0.19.12")), None);
+        assert_eq!(ec("dummy").partial_cmp(&ec("2022-05-23_c4bc87b")), None);
+
+        assert!(ec("2022-05-23_c4bc87b").has(FirmwareFeature::Pause));
+        assert_eq!(
+            ec("2022-05-22_c4bc87b").supports(FirmwareFeature::Pause),
+            Err(
+                "Requires EC firmware 2022-05-23 or newer, but version is 2022-05-22_c4bc87b"
+                    .to_string()
+            )
+        );
+        assert!(!ec("2023-07-31_c4bc87b").has(FirmwareFeature::FnLock));
+        assert!(ec("dummy").has(FirmwareFeature::FnLock));
+        assert!(!ec("dummy").has(FirmwareFeature::ModTap));
+
+        assert!(qmk("0.7.103").has(FirmwareFeature::LegacyScancodes));
+        assert!(qmk("0.12.20").has(FirmwareFeature::LegacyScancodes));
+        assert!(!qmk("0.19.12").has(FirmwareFeature::LegacyScancodes));
+        assert!(!qmk("dummy").has(FirmwareFeature::LegacyScancodes));
+        assert_eq!(
+            qmk("0.19.12").supports(FirmwareFeature::FnLock),
+            Err("Not supported by QMK firmware".to_string())
+        );
+        assert_eq!(qmk("0.19.12").features().len(), 4);
+    }
+}
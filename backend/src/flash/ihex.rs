@@ -0,0 +1,170 @@
+use std::{fs, path::Path};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Contiguous bytes of a `HexImage`, starting at `address`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HexChunk {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl HexChunk {
+    /// Address after the last byte
+    pub fn end(&self) -> u32 {
+        self.address + self.data.len() as u32
+    }
+}
+
+/// Firmware image parsed from an Intel HEX file, with every record checksum verified
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HexImage {
+    /// Data records, sorted by address and merged where contiguous
+    pub chunks: Vec<HexChunk>,
+}
+
+impl HexImage {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let hex = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read '{}': {}", path.display(), err))?;
+        Self::parse(&hex).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(hex: &str) -> Result<Self, String> {
+        let mut chunks: Vec<HexChunk> = Vec::new();
+        let mut base = 0u32;
+        let mut eof = false;
+
+        for (i, line) in hex.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if eof {
+                return Err(format!("line {}: record after end of file", i + 1));
+            }
+            let record = parse_record(line).map_err(|err| format!("line {}: {}", i + 1, err))?;
+            match record.kind {
+                DATA => {
+                    // Every chunk must end within the address space, for `HexChunk::end`
+                    let address = base
+                        .checked_add(u32::from(record.address))
+                        .filter(|x| x.checked_add(record.data.len() as u32).is_some())
+                        .ok_or_else(|| format!("line {}: data past 4 GiB address space", i + 1))?;
+                    chunks.push(HexChunk {
+                        address,
+                        data: record.data,
+                    });
+                }
+                END_OF_FILE => eof = true,
+                EXTENDED_SEGMENT_ADDRESS => base = u32::from(record.word(i)?) << 4,
+                EXTENDED_LINEAR_ADDRESS => base = u32::from(record.word(i)?) << 16,
+                // Entry points mean nothing to an AVR bootloader
+                START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+                kind => return Err(format!("line {}: unknown record type {:02X}", i + 1, kind)),
+            }
+        }
+        if !eof {
+            return Err("missing end of file record".to_string());
+        }
+
+        chunks.sort_by_key(|x| x.address);
+        let mut merged: Vec<HexChunk> = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            match merged.last_mut() {
+                Some(last) if chunk.address < last.end() => {
+                    return Err(format!("data overlaps at address 0x{:X}", chunk.address));
+                }
+                Some(last) if chunk.address == last.end() => last.data.extend(chunk.data),
+                _ => merged.push(chunk),
+            }
+        }
+        Ok(Self { chunks: merged })
+    }
+
+    /// Number of data bytes
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|x| x.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Address after the last data byte
+    pub fn end(&self) -> u32 {
+        self.chunks.last().map_or(0, |x| x.end())
+    }
+
+    /// Address after the last data byte of chunks starting below `limit`
+    pub fn end_below(&self, limit: u32) -> u32 {
+        self.chunks
+            .iter()
+            .filter(|x| x.address < limit)
+            .map(|x| x.end())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+struct Record {
+    kind: u8,
+    address: u16,
+    data: Vec<u8>,
+}
+
+impl Record {
+    fn word(&self, i: usize) -> Result<u16, String> {
+        match self.data[..] {
+            [high, low] => Ok(u16::from_be_bytes([high, low])),
+            _ => Err(format!(
+                "line {}: address record has {} bytes instead of 2",
+                i + 1,
+                self.data.len()
+            )),
+        }
+    }
+}
+
+fn parse_record(line: &str) -> Result<Record, String> {
+    let hex = line
+        .strip_prefix(':')
+        .ok_or("record does not start with ':'")?;
+    if !hex.is_ascii() {
+        return Err("record contains non-ASCII characters".to_string());
+    }
+    if hex.len() % 2 != 0 {
+        return Err("odd number of hex digits".to_string());
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("invalid hex digits '{}'", &hex[i..i + 2]))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    // Byte count, address, type, data, checksum
+    if bytes.len() < 5 || bytes.len() != usize::from(bytes[0]) + 5 {
+        return Err("length does not match byte count".to_string());
+    }
+    let sum = bytes.iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+    if sum != 0 {
+        return Err(format!(
+            "checksum {:02X} does not match",
+            bytes[bytes.len() - 1]
+        ));
+    }
+
+    Ok(Record {
+        kind: bytes[3],
+        address: u16::from_be_bytes([bytes[1], bytes[2]]),
+        data: bytes[4..bytes.len() - 1].to_vec(),
+    })
+}
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::Bootloaded;
+
+mod ihex;
+pub use ihex::{HexChunk, HexImage};
+mod programmer;
+pub use programmer::{DfuProgrammer, Programmer};
+
+/// Directory the packaged default firmware is installed to
+pub const FIRMWARE_DIR: &str = "/var/lib/system76-keyboard-configurator";
+
+/// Path of the packaged default firmware for `board`, like `system76/launch_2`
+pub fn default_firmware_path(board: &str) -> PathBuf {
+    Path::new(FIRMWARE_DIR).join(format!("{}_default.hex", board.replace('/', "_")))
+}
+
+/// Microcontroller targeted by a flash
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mcu {
+    /// Target name used by `dfu-programmer`
+    pub name: &'static str,
+    /// Size of program flash, in bytes
+    pub flash_size: u32,
+    /// Size of the bootloader section at the end of program flash, in bytes
+    pub bootloader_size: u32,
+    /// Whether flash must be erased before writing
+    pub erase: bool,
+    /// `dfu-programmer` command that runs the new firmware
+    pub launch_command: &'static str,
+}
+
+impl Mcu {
+    /// Address of the start of the bootloader section
+    pub fn bootloader_start(&self) -> u32 {
+        self.flash_size - self.bootloader_size
+    }
+
+    /// Check `image` fits in flash, outside the bootloader section.
+    /// Data inside the bootloader section is ignored when flashing.
+    pub fn check_size(&self, image: &HexImage) -> Result<(), String> {
+        if image.is_empty() {
+            return Err("Firmware contains no data".to_string());
+        }
+        if image.end() > self.flash_size {
+            return Err(format!(
+                "Firmware ends at 0x{:X}, past the {} byte flash of {}",
+                image.end(),
+                self.flash_size,
+                self.name
+            ));
+        }
+        let app_end = image.end_below(self.bootloader_start());
+        if app_end > self.bootloader_start() {
+            return Err(format!(
+                "Firmware needs {} bytes, but {} has {} bytes outside the bootloader",
+                app_end,
+                self.name,
+                self.bootloader_start()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Steps run to flash this MCU
+    pub fn steps(&self) -> Vec<FlashStep> {
+        let mut steps = vec![FlashStep::Validate];
+        if self.erase {
+            steps.push(FlashStep::Erase);
+        }
+        steps.push(FlashStep::Flash);
+        steps.push(FlashStep::Launch);
+        steps
+    }
+}
+
+static AT90USB646: Mcu = Mcu {
+    name: "at90usb646",
+    flash_size: 64 * 1024,
+    bootloader_size: 4 * 1024,
+    erase: false,
+    launch_command: "reset",
+};
+
+static ATMEGA32U4: Mcu = Mcu {
+    name: "atmega32u4",
+    flash_size: 32 * 1024,
+    bootloader_size: 4 * 1024,
+    erase: true,
+    launch_command: "start",
+};
+
+impl Bootloaded {
+    pub fn mcu(self) -> &'static Mcu {
+        match self {
+            Self::At90usb646 | Self::At90usb646Lite => &AT90USB646,
+            Self::AtMega32u4 => &ATMEGA32U4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum FlashStep {
+    /// Parse the firmware file and check it fits the MCU
+    Validate,
+    Erase,
+    Flash,
+    /// Leave the bootloader
+    Launch,
+}
+
+/// Progress of `flash_firmware`, also sent as `Event::Flash` by `Backend::flash`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FlashEvent {
+    /// Step `index` (starting at 1) of `total` has started
+    Step {
+        step: FlashStep,
+        index: usize,
+        total: usize,
+    },
+    Done,
+    Failed(String),
+}
+
+/// Validate the firmware at `path` and flash it to `target`, reporting progress to `on_event`
+pub fn flash_firmware(
+    programmer: &mut dyn Programmer,
+    target: Bootloaded,
+    path: &Path,
+    mut on_event: impl FnMut(FlashEvent),
+) -> Result<(), String> {
+    let mcu = target.mcu();
+    let steps = mcu.steps();
+    let mut run = || -> Result<(), String> {
+        for (i, step) in steps.iter().enumerate() {
+            on_event(FlashEvent::Step {
+                step: *step,
+                index: i + 1,
+                total: steps.len(),
+            });
+            match step {
+                FlashStep::Validate => mcu.check_size(&HexImage::from_path(path)?)?,
+                FlashStep::Erase => programmer.erase(mcu)?,
+                FlashStep::Flash => programmer.flash(mcu, path)?,
+                FlashStep::Launch => programmer.launch(mcu)?,
+            }
+        }
+        Ok(())
+    };
+    let res = run();
+    on_event(match &res {
+        Ok(()) => FlashEvent::Done,
+        Err(err) => FlashEvent::Failed(err.clone()),
+    });
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Programmer that records steps instead of flashing, optionally failing at one of them
+    #[derive(Clone, Debug, Default)]
+    struct MockProgrammer {
+        steps: Vec<FlashStep>,
+        fail_at: Option<FlashStep>,
+    }
+
+    impl MockProgrammer {
+        fn step(&mut self, step: FlashStep) -> Result<(), String> {
+            self.steps.push(step);
+            if self.fail_at == Some(step) {
+                Err(format!("Mock failure at {:?}", step))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Programmer for MockProgrammer {
+        fn erase(&mut self, _mcu: &Mcu) -> Result<(), String> {
+            self.step(FlashStep::Erase)
+        }
+
+        fn flash(&mut self, _mcu: &Mcu, _path: &Path) -> Result<(), String> {
+            self.step(FlashStep::Flash)
+        }
+
+        fn launch(&mut self, _mcu: &Mcu) -> Result<(), String> {
+            self.step(FlashStep::Launch)
+        }
+    }
+
+    fn data_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../data")
+            .join(name)
+    }
+
+    #[test]
+    fn ihex_parse() {
+        let image =
+            HexImage::parse(":0400000001020304F2\n:02000004000AF0\n:020000000506F3\n:00000001FF\n")
+                .unwrap();
+        assert_eq!(image.len(), 6);
+        assert_eq!(image.chunks[1].address, 0xA0000);
+
+        // Bad checksum, byte count, and missing end of file
+        assert!(HexImage::parse(":0400000001020304F3\n:00000001FF\n")
+            .unwrap_err()
+            .starts_with("line 1: checksum"));
+        assert!(HexImage::parse(":0500000001020304F2\n:00000001FF\n").is_err());
+        assert!(HexImage::parse(":0400000001020304F2\n").is_err());
+        assert_eq!(
+            HexImage::parse(":02000004FFFFFC\n:02FFFF000102FD\n:00000001FF\n").unwrap_err(),
+            "line 2: data past 4 GiB address space"
+        );
+
+        // Packaged firmware fits its own MCU, but not a smaller one
+        let launch_1 = HexImage::from_path(data_path("system76_launch_1_default.hex")).unwrap();
+        Bootloaded::AtMega32u4.mcu().check_size(&launch_1).unwrap();
+        let lite_1 = HexImage::from_path(data_path("system76_launch_lite_1_default.hex")).unwrap();
+        Bootloaded::At90usb646Lite
+            .mcu()
+            .check_size(&lite_1)
+            .unwrap();
+        for name in [
+            "system76_launch_2_default.hex",
+            "system76_launch_heavy_1_default.hex",
+        ] {
+            let image = HexImage::from_path(data_path(name)).unwrap();
+            Bootloaded::At90usb646.mcu().check_size(&image).unwrap();
+            assert!(Bootloaded::AtMega32u4.mcu().check_size(&image).is_err());
+        }
+    }
+
+    #[test]
+    fn flash_mock() {
+        let path = data_path("system76_launch_1_default.hex");
+        let mut events = Vec::new();
+        let mut programmer = MockProgrammer::default();
+        flash_firmware(&mut programmer, Bootloaded::AtMega32u4, &path, |x| {
+            events.push(x)
+        })
+        .unwrap();
+        assert_eq!(
+            programmer.steps,
+            vec![FlashStep::Erase, FlashStep::Flash, FlashStep::Launch]
+        );
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[4], FlashEvent::Done);
+
+        let mut events = Vec::new();
+        let mut programmer = MockProgrammer {
+            fail_at: Some(FlashStep::Flash),
+            ..Default::default()
+        };
+        let res = flash_firmware(&mut programmer, Bootloaded::At90usb646, &path, |x| {
+            events.push(x)
+        });
+        assert!(res.is_err());
+        assert_eq!(programmer.steps, vec![FlashStep::Flash]);
+        assert!(matches!(events.last(), Some(FlashEvent::Failed(_))));
+
+        // Nothing is flashed if validation fails
+        let mut programmer = MockProgrammer::default();
+        let path = data_path("system76_launch_2_default.hex");
+        assert!(flash_firmware(&mut programmer, Bootloaded::AtMega32u4, &path, |_| {}).is_err());
+        assert!(programmer.steps.is_empty());
+    }
+}
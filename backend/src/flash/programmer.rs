@@ -0,0 +1,67 @@
+use std::{path::Path, process::Command};
+
+use super::Mcu;
+
+/// Tool that writes firmware to a keyboard in bootloader mode
+pub trait Programmer: Send {
+    fn erase(&mut self, mcu: &Mcu) -> Result<(), String>;
+    /// Write the Intel HEX file at `path`, leaving the bootloader section untouched
+    fn flash(&mut self, mcu: &Mcu, path: &Path) -> Result<(), String>;
+    /// Leave the bootloader and run the new firmware
+    fn launch(&mut self, mcu: &Mcu) -> Result<(), String>;
+}
+
+/// Programmer using the `dfu-programmer` command
+#[derive(Clone, Debug)]
+pub struct DfuProgrammer {
+    command: String,
+}
+
+impl Default for DfuProgrammer {
+    fn default() -> Self {
+        Self::new("dfu-programmer")
+    }
+}
+
+impl DfuProgrammer {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+
+    fn run(&self, mcu: &Mcu, args: &[&str]) -> Result<(), String> {
+        info!("{} {} {}", self.command, mcu.name, args.join(" "));
+        let output = Command::new(&self.command)
+            .arg(mcu.name)
+            .args(args)
+            .output()
+            .map_err(|err| format!("Failed to run {}: {}", self.command, err))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} {} failed ({}): {}",
+                self.command,
+                args[0],
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+impl Programmer for DfuProgrammer {
+    fn erase(&mut self, mcu: &Mcu) -> Result<(), String> {
+        self.run(mcu, &["erase"])
+    }
+
+    fn flash(&mut self, mcu: &Mcu, path: &Path) -> Result<(), String> {
+        let path = path.to_str().ok_or("Firmware path is not valid UTF-8")?;
+        self.run(mcu, &["flash", path, "--suppress-bootloader-mem"])
+    }
+
+    fn launch(&mut self, mcu: &Mcu) -> Result<(), String> {
+        self.run(mcu, &[mcu.launch_command])
+    }
+}
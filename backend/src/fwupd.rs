@@ -0,0 +1,256 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Firmware of a device, as known to fwupd
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FwupdDevice {
+    /// fwupd device ID, used to request an update
+    pub id: String,
+    pub name: String,
+    /// Currently installed version
+    pub version: String,
+    /// Newest version available from fwupd remotes, if newer than `version`
+    pub update_version: Option<String>,
+}
+
+impl FwupdDevice {
+    /// Keyboard model, like `system76/launch_2`, for System76 keyboards
+    ///
+    /// Launch firmware reports a USB product name like `Launch Configurable Keyboard (launch_2)`,
+    /// which fwupd uses as the device name.
+    pub fn board_model(&self) -> Option<String> {
+        static RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^Launch.* Configurable Keyboard \((\w+)\)$").unwrap());
+        let captures = RE.captures(&self.name)?;
+        Some(format!("system76/{}", &captures[1]))
+    }
+}
+
+/// Progress of `fwupd_update`, also sent as `Event::Fwupd` by `Backend`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FwupdEvent {
+    /// Result of `Backend::fwupd_check`
+    Devices(Result<Vec<FwupdDevice>, String>),
+    /// Percentage of the update that is done
+    Progress(u32),
+    Done,
+    Failed(String),
+}
+
+/// Client for the fwupd firmware update daemon
+pub trait Fwupd: Send {
+    /// Devices fwupd can update, with any available update
+    fn devices(&mut self) -> Result<Vec<FwupdDevice>, String>;
+    /// Install the newest firmware for `device`, reporting percentage done to `on_progress`
+    fn update(
+        &mut self,
+        device: &FwupdDevice,
+        on_progress: &mut dyn FnMut(u32),
+    ) -> Result<(), String>;
+}
+
+/// Update `device`, reporting progress to `on_event`
+pub fn fwupd_update(
+    fwupd: &mut dyn Fwupd,
+    device: &FwupdDevice,
+    mut on_event: impl FnMut(FwupdEvent),
+) -> Result<(), String> {
+    let res = match &device.update_version {
+        Some(_) => fwupd.update(device, &mut |percentage| {
+            on_event(FwupdEvent::Progress(percentage))
+        }),
+        None => Err(format!("No firmware update available for {}", device.name)),
+    };
+    on_event(match &res {
+        Ok(()) => FwupdEvent::Done,
+        Err(err) => FwupdEvent::Failed(err.clone()),
+    });
+    res
+}
+
+#[cfg(target_os = "linux")]
+mod dbus {
+    use std::{collections::HashMap, process::Command, thread, time::Duration};
+    use zbus::{dbus_proxy, zvariant::OwnedValue, Connection};
+
+    use super::{Fwupd, FwupdDevice};
+    use crate::daemon::err_str;
+
+    type Dict = HashMap<String, OwnedValue>;
+
+    #[dbus_proxy(
+        interface = "org.freedesktop.fwupd",
+        default_service = "org.freedesktop.fwupd",
+        default_path = "/"
+    )]
+    trait Manager {
+        fn get_devices(&self) -> zbus::Result<Vec<Dict>>;
+        fn get_upgrades(&self, device_id: &str) -> zbus::Result<Vec<Dict>>;
+        #[dbus_proxy(property)]
+        fn percentage(&self) -> zbus::Result<u32>;
+    }
+
+    fn get_str(dict: &Dict, key: &str) -> Option<String> {
+        dict.get(key)
+            .and_then(|x| <&str>::try_from(x).ok())
+            .map(str::to_string)
+    }
+
+    /// Client for the system fwupd daemon, over D-Bus
+    ///
+    /// Updates are installed with `fwupdmgr`, which downloads the firmware archive.
+    #[derive(Clone, Debug)]
+    pub struct FwupdDbus {
+        fwupdmgr: String,
+    }
+
+    impl Default for FwupdDbus {
+        fn default() -> Self {
+            Self::new("fwupdmgr")
+        }
+    }
+
+    impl FwupdDbus {
+        pub fn new(fwupdmgr: &str) -> Self {
+            Self {
+                fwupdmgr: fwupdmgr.to_string(),
+            }
+        }
+    }
+
+    impl Fwupd for FwupdDbus {
+        fn devices(&mut self) -> Result<Vec<FwupdDevice>, String> {
+            let connection = Connection::new_system().map_err(err_str)?;
+            let proxy = ManagerProxy::new(&connection).map_err(err_str)?;
+            let mut devices = Vec::new();
+            for dict in proxy.get_devices().map_err(err_str)? {
+                let (id, name) = match (get_str(&dict, "DeviceId"), get_str(&dict, "Name")) {
+                    (Some(id), Some(name)) => (id, name),
+                    _ => continue,
+                };
+                // fwupd returns an error, rather than an empty list, if nothing is newer
+                let update_version = match proxy.get_upgrades(&id) {
+                    Ok(releases) => releases.first().and_then(|x| get_str(x, "Version")),
+                    Err(err) => {
+                        debug!("No fwupd upgrades for {}: {}", name, err);
+                        None
+                    }
+                };
+                devices.push(FwupdDevice {
+                    id,
+                    name,
+                    version: get_str(&dict, "Version").unwrap_or_default(),
+                    update_version,
+                });
+            }
+            Ok(devices)
+        }
+
+        fn update(
+            &mut self,
+            device: &FwupdDevice,
+            on_progress: &mut dyn FnMut(u32),
+        ) -> Result<(), String> {
+            let connection = Connection::new_system().map_err(err_str)?;
+            let proxy = ManagerProxy::new(&connection).map_err(err_str)?;
+
+            info!("{} update {}", self.fwupdmgr, device.id);
+            let mut child = Command::new(&self.fwupdmgr)
+                .args(["update", &device.id, "--assume-yes", "--no-reboot-check"])
+                .spawn()
+                .map_err(|err| format!("Failed to run {}: {}", self.fwupdmgr, err))?;
+            let status = loop {
+                if let Some(status) = child.try_wait().map_err(err_str)? {
+                    break status;
+                }
+                if let Ok(percentage) = proxy.percentage() {
+                    on_progress(percentage);
+                }
+                thread::sleep(Duration::from_millis(250));
+            };
+            if status.success() {
+                Ok(())
+            } else {
+                Err(format!("{} update failed ({})", self.fwupdmgr, status))
+            }
+        }
+    }
+}
+#[cfg(target_os = "linux")]
+pub use dbus::FwupdDbus;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stand-in for fwupd that updates devices in memory, optionally failing
+    #[derive(Clone, Debug, Default)]
+    struct MockFwupd {
+        devices: Vec<FwupdDevice>,
+        fail: bool,
+    }
+
+    impl Fwupd for MockFwupd {
+        fn devices(&mut self) -> Result<Vec<FwupdDevice>, String> {
+            Ok(self.devices.clone())
+        }
+
+        fn update(
+            &mut self,
+            device: &FwupdDevice,
+            on_progress: &mut dyn FnMut(u32),
+        ) -> Result<(), String> {
+            let device = self
+                .devices
+                .iter_mut()
+                .find(|x| x.id == device.id)
+                .ok_or_else(|| format!("No fwupd device '{}'", device.id))?;
+            on_progress(0);
+            if self.fail {
+                return Err(format!("Mock failure updating {}", device.name));
+            }
+            on_progress(100);
+            if let Some(version) = device.update_version.take() {
+                device.version = version;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fwupd_mock() {
+        let device = FwupdDevice {
+            id: "1234".to_string(),
+            name: "Launch Configurable Keyboard (launch_2)".to_string(),
+            version: "0.7.103".to_string(),
+            update_version: Some("0.19.12".to_string()),
+        };
+        assert_eq!(device.board_model().as_deref(), Some("system76/launch_2"));
+
+        let mut fwupd = MockFwupd {
+            devices: vec![device.clone()],
+            fail: false,
+        };
+        let mut events = Vec::new();
+        fwupd_update(&mut fwupd, &device, |x| events.push(x)).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                FwupdEvent::Progress(0),
+                FwupdEvent::Progress(100),
+                FwupdEvent::Done
+            ]
+        );
+        let devices = fwupd.devices().unwrap();
+        assert_eq!(devices[0].version, "0.19.12");
+        assert_eq!(devices[0].update_version, None);
+
+        // Already up to date
+        assert!(fwupd_update(&mut fwupd, &devices[0], |_| {}).is_err());
+
+        fwupd.fail = true;
+        let mut events = Vec::new();
+        assert!(fwupd_update(&mut fwupd, &device, |x| events.push(x)).is_err());
+        assert!(matches!(events.last(), Some(FwupdEvent::Failed(_))));
+    }
+}
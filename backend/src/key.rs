@@ -3,7 +3,7 @@ use std::sync::{
     Mutex,
 };
 
-use crate::{Board, BoardEvent, Daemon, Hs, PhysicalLayoutKey, Rect, Rgb, WeakBoard};
+use crate::{Board, BoardEvent, Daemon, Hs, PhysicalLayoutKey, Rect, Rgb, Rotation, WeakBoard};
 
 #[derive(Debug)]
 pub struct Key {
@@ -14,8 +14,15 @@ pub struct Key {
     pub logical_name: String,
     /// Physical position and size
     pub physical: Rect,
+    /// Second rectangle of a key that isn't a rectangle, like an ISO enter
+    pub physical2: Option<Rect>,
+    /// Rotation of `physical` and `physical2`
+    pub rotation: Rotation,
     /// Physical key name (what is printed on the keycap)
     pub physical_name: String,
+    /// Lines of `physical_name` by position on the keycap: a 3x3 grid from top left, then 3 on
+    /// the front edge. Positions without a legend are empty.
+    pub legends: Vec<String>,
     /// Electrical mapping (output, input)
     pub electrical: (u8, u8),
     /// Electrical name (output, input)
@@ -105,7 +112,10 @@ impl Key {
             logical,
             logical_name,
             physical,
+            physical2: physical_key.physical2,
+            rotation: physical_key.rotation,
             physical_name,
+            legends: physical_key.legends.clone(),
             electrical,
             electrical_name: format!("{}, {}", electrical.0, electrical.1),
             leds,
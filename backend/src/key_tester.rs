@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{csv_escape, ChatterConfig, ChatterDetector, KeyEvent, Layout};
+
+/// Keys held down longer than this are reported as stuck by `KeyTester`
+pub const KEY_TEST_STUCK_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+struct KeyTestState {
+    logical_name: String,
+    physical_name: String,
+    electrical: (u8, u8),
+    presses: u32,
+}
+
+/// State of one key in a `KeyTestReport`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyTestEntry {
+    pub logical_name: String,
+    pub physical_name: String,
+    pub electrical: (u8, u8),
+    pub presses: u32,
+    /// Seconds the key has been held, if longer than the stuck threshold
+    pub stuck_secs: Option<u64>,
+}
+
+impl KeyTestEntry {
+    pub fn is_tested(&self) -> bool {
+        self.presses > 0
+    }
+
+    pub fn is_stuck(&self) -> bool {
+        self.stuck_secs.is_some()
+    }
+}
+
+/// Coverage of a key test, with keys in layout order
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyTestReport {
+    pub model: String,
+    pub keys: Vec<KeyTestEntry>,
+}
+
+impl KeyTestReport {
+    pub fn tested(&self) -> usize {
+        self.keys.iter().filter(|x| x.is_tested()).count()
+    }
+
+    pub fn untested(&self) -> impl Iterator<Item = &KeyTestEntry> {
+        self.keys.iter().filter(|x| !x.is_tested())
+    }
+
+    pub fn stuck(&self) -> impl Iterator<Item = &KeyTestEntry> {
+        self.keys.iter().filter(|x| x.is_stuck())
+    }
+
+    /// Every key has been pressed, and none are stuck
+    pub fn success(&self) -> bool {
+        self.keys.iter().all(|x| x.is_tested() && !x.is_stuck())
+    }
+
+    pub fn to_csv_writer<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        writeln!(wtr, "logical_name,physical_name,presses,stuck")?;
+        for key in self.keys.iter() {
+            writeln!(
+                wtr,
+                "{},{},{},{}",
+                csv_escape(&key.logical_name),
+                csv_escape(&key.physical_name),
+                key.presses,
+                key.is_stuck()
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn to_json_writer_pretty<W: Write>(&self, wtr: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(wtr, self)
+    }
+}
+
+/// Track which physical keys have been pressed, from the key events produced by matrix polling
+#[derive(Clone, Debug)]
+pub struct KeyTester {
+    model: String,
+    keys: Vec<KeyTestState>,
+    /// Tracks how long keys are held, to find stuck keys
+    held: ChatterDetector,
+}
+
+impl KeyTester {
+    pub fn new(model: &str, layout: &Layout, stuck_after: Duration) -> Self {
+        let keys = layout
+            .physical
+            .keys
+            .iter()
+            .filter_map(|key| {
+                let logical_name = key.logical_name();
+                let electrical = *layout.layout.get(&logical_name)?;
+                Some(KeyTestState {
+                    logical_name,
+                    physical_name: key.physical_name.clone(),
+                    electrical,
+                    presses: 0,
+                })
+            })
+            .collect();
+        Self {
+            model: model.to_string(),
+            keys,
+            held: ChatterDetector::new(ChatterConfig {
+                stuck_after,
+                ..ChatterConfig::default()
+            }),
+        }
+    }
+
+    /// Mark a key already held when the test started, which produces no press event
+    pub fn set_held(&mut self, logical_name: &str, since: Instant) {
+        if let Some(key) = self.keys.iter().find(|x| x.logical_name == logical_name) {
+            self.held.handle_event(&KeyEvent {
+                pressed: true,
+                logical_name: key.logical_name.clone(),
+                physical_name: key.physical_name.clone(),
+                electrical: key.electrical,
+                time: since,
+            });
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &KeyEvent) {
+        let key = match self
+            .keys
+            .iter_mut()
+            .find(|x| x.logical_name == event.logical_name)
+        {
+            Some(key) => key,
+            None => return,
+        };
+        if event.pressed {
+            key.presses += 1;
+        }
+        self.held.handle_event(event);
+    }
+
+    /// Report coverage, treating keys held at `now` as stuck if held too long
+    pub fn report(&self, now: Instant) -> KeyTestReport {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| KeyTestEntry {
+                logical_name: key.logical_name.clone(),
+                physical_name: key.physical_name.clone(),
+                electrical: key.electrical,
+                presses: key.presses,
+                stuck_secs: self
+                    .held
+                    .stuck_for(&key.logical_name, now)
+                    .map(|held| held.as_secs()),
+            })
+            .collect();
+        KeyTestReport {
+            model: self.model.clone(),
+            keys,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for key in self.keys.iter_mut() {
+            key.presses = 0;
+        }
+        self.held.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_tester_coverage() {
+        let layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        let event = KeyEvent::mock;
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut tester = KeyTester::new("system76/launch_2", &layout, KEY_TEST_STUCK_AFTER);
+        let total = tester.report(start).keys.len();
+        assert_eq!(tester.report(start).untested().count(), total);
+
+        tester.handle_event(&event("K00", true, secs(0)));
+        tester.handle_event(&event("K00", false, secs(0)));
+        tester.handle_event(&event("K01", true, secs(1)));
+        tester.set_held("K02", secs(0));
+        // Keys not in the layout are ignored
+        tester.handle_event(&event("K99", true, secs(0)));
+
+        let report = tester.report(secs(2));
+        assert_eq!(report.tested(), 2);
+        assert_eq!(report.untested().count(), total - 2);
+        assert_eq!(report.stuck().count(), 0);
+
+        let report = tester.report(secs(10));
+        let stuck: Vec<_> = report.stuck().map(|x| x.logical_name.as_str()).collect();
+        assert_eq!(stuck, vec!["K01", "K02"]);
+        assert_eq!(report.keys[1].stuck_secs, Some(9));
+        assert!(!report.success());
+
+        let mut csv = Vec::new();
+        report.to_csv_writer(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("logical_name,physical_name,presses,stuck\nK00,Esc,1,false\n"));
+        assert!(csv.contains("K01,F1,1,true\n"));
+
+        tester.reset();
+        assert_eq!(tester.report(secs(10)).tested(), 0);
+    }
+}
@@ -5,7 +5,7 @@ use std::{
     io::{Read, Write},
 };
 
-use crate::Hs;
+use crate::{Hs, Layout};
 
 mod hs_serde {
     use super::*;
@@ -69,6 +69,17 @@ impl KeyMap {
         serde_json::from_reader(rdr)
     }
 
+    /// Replace QMK keycode aliases, like `KC_BSPC`, with the names used by `layout`
+    ///
+    /// Names `layout` doesn't recognize are left unchanged.
+    pub fn canonicalize(&mut self, layout: &Layout) {
+        for scancode_name in self.map.values_mut().flatten() {
+            if let Some(name) = layout.canonical_scancode_name(scancode_name) {
+                *scancode_name = name;
+            }
+        }
+    }
+
     /// Write layout to json file, pretty printed
     pub fn to_writer_pretty<W: Write>(&self, wtr: W) -> serde_json::Result<()> {
         serde_json::to_writer_pretty(wtr, self)
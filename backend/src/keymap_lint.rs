@@ -0,0 +1,369 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+
+use crate::{fl, layer_label, KeyMap, Layout, LT_RE};
+
+const MODIFIERS: &[&str] = &[
+    "LEFT_CTRL",
+    "LEFT_SHIFT",
+    "LEFT_ALT",
+    "LEFT_SUPER",
+    "RIGHT_CTRL",
+    "RIGHT_SHIFT",
+    "RIGHT_ALT",
+    "RIGHT_SUPER",
+];
+
+/// Potential problem found in a keymap by `KeyMap::lint`
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeyMapLint {
+    /// No key on a reachable layer activates this layer
+    UnreachableLayer { layer: usize },
+    /// Layer can be latched on, but has no key that leads back to the base layer
+    NoEscape { layer: usize },
+    /// No reachable key enters the bootloader
+    NoReset,
+    /// The same modifier is bound to more than one key on a layer
+    DuplicateModifier {
+        layer: usize,
+        scancode_name: String,
+        keys: Vec<String>,
+    },
+    /// Every key in a row is bound to `NONE`
+    EmptyRow { layer: usize, row: u8 },
+}
+
+impl KeyMapLint {
+    /// Lints that can leave the keyboard stuck on a layer, or impossible to reflash
+    pub fn is_lockout(&self) -> bool {
+        matches!(self, Self::NoEscape { .. } | Self::NoReset)
+    }
+}
+
+impl fmt::Display for KeyMapLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            Self::UnreachableLayer { layer } => {
+                fl!("keymap-lint-unreachable-layer", layer = layer_label(*layer))
+            }
+            Self::NoEscape { layer } => {
+                fl!("keymap-lint-no-escape", layer = layer_label(*layer))
+            }
+            Self::NoReset => fl!("keymap-lint-no-reset"),
+            Self::DuplicateModifier {
+                layer,
+                scancode_name,
+                keys,
+            } => fl!(
+                "keymap-lint-duplicate-modifier",
+                scancode_name = scancode_name.as_str(),
+                layer = layer_label(*layer),
+                keys = keys.join(", ")
+            ),
+            Self::EmptyRow { layer, row } => fl!(
+                "keymap-lint-empty-row",
+                row = row.to_string(),
+                layer = layer_label(*layer)
+            ),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// How a keycode changes the active layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LayerAction {
+    /// Active while held (`LAYER_ACCESS_*`, `FN`, `LT(...)`)
+    Momentary(usize),
+    /// Toggled on or off (`LAYER_TOGGLE_*`)
+    Toggle(usize),
+    /// Replaces the active layer (`LAYER_SWITCH_*`)
+    Switch(usize),
+}
+
+impl LayerAction {
+    fn from_name(name: &str) -> Option<Self> {
+        fn layer_num(s: &str) -> Option<usize> {
+            s.parse::<usize>().ok()?.checked_sub(1)
+        }
+
+        if name == "FN" {
+            return Some(Self::Momentary(1));
+        }
+        if let Some(num) = name.strip_prefix("LAYER_ACCESS_") {
+            return layer_num(num).map(Self::Momentary);
+        }
+        if let Some(num) = name.strip_prefix("LAYER_TOGGLE_") {
+            return layer_num(num).map(Self::Toggle);
+        }
+        if let Some(num) = name.strip_prefix("LAYER_SWITCH_") {
+            return layer_num(num).map(Self::Switch);
+        }
+
+        let captures = LT_RE.captures(name)?;
+        captures[1].parse().ok().map(Self::Momentary)
+    }
+
+    fn layer(self) -> usize {
+        match self {
+            Self::Momentary(layer) | Self::Toggle(layer) | Self::Switch(layer) => layer,
+        }
+    }
+}
+
+/// Name of a keycode as `Layout` exports it, so QMK aliases like `MO(1)` are recognized
+fn canonical<'a>(layout: &Layout, name: &'a str) -> Cow<'a, str> {
+    layout
+        .canonical_scancode_name(name)
+        .map_or(Cow::Borrowed(name), Cow::Owned)
+}
+
+/// Keycode for a key on a layer, looking through transparent keys to lower layers
+fn effective<'a>(layout: &Layout, scancodes: &'a [String], layer: usize) -> Cow<'a, str> {
+    for i in (0..=layer).rev() {
+        if let Some(name) = scancodes.get(i) {
+            let name = canonical(layout, name);
+            if name != "ROLL_OVER" {
+                return name;
+            }
+        }
+    }
+    Cow::Borrowed("ROLL_OVER")
+}
+
+fn layer_actions<'a>(
+    keymap: &'a KeyMap,
+    layout: &'a Layout,
+    layer: usize,
+) -> impl Iterator<Item = LayerAction> + 'a {
+    keymap
+        .map
+        .values()
+        .filter_map(move |scancodes| LayerAction::from_name(&effective(layout, scancodes, layer)))
+}
+
+/// Check if the base layer can be restored after latching on `layer`
+fn has_escape(keymap: &KeyMap, layout: &Layout, layer: usize) -> bool {
+    let num_layers = layout.meta.num_layers as usize;
+    let mut visited = BTreeSet::new();
+    let mut stack = vec![layer];
+    while let Some(layer) = stack.pop() {
+        if layer == 0 {
+            return true;
+        }
+        if !visited.insert(layer) {
+            continue;
+        }
+        for action in layer_actions(keymap, layout, layer) {
+            match action {
+                LayerAction::Switch(next) if next < num_layers => stack.push(next),
+                // Toggling the current layer off falls back to the layers below
+                LayerAction::Toggle(next) if next == layer => stack.push(0),
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+impl KeyMap {
+    /// Check the keymap for unreachable layers and other likely mistakes
+    pub fn lint(&self, layout: &Layout) -> Vec<KeyMapLint> {
+        let num_layers = layout.meta.num_layers as usize;
+        let mut lints = Vec::new();
+
+        // Build the set of layers reachable from the base layer
+        let mut reachable = BTreeSet::new();
+        let mut latched = BTreeSet::new();
+        let mut stack = vec![0];
+        while let Some(layer) = stack.pop() {
+            if !reachable.insert(layer) {
+                continue;
+            }
+            for action in layer_actions(self, layout, layer) {
+                let next = action.layer();
+                if next >= num_layers {
+                    continue;
+                }
+                if !matches!(action, LayerAction::Momentary(_)) {
+                    latched.insert(next);
+                }
+                stack.push(next);
+            }
+        }
+
+        for layer in 0..num_layers {
+            if !reachable.contains(&layer) {
+                lints.push(KeyMapLint::UnreachableLayer { layer });
+            }
+        }
+
+        for layer in latched {
+            if layer != 0 && !has_escape(self, layout, layer) {
+                lints.push(KeyMapLint::NoEscape { layer });
+            }
+        }
+
+        if layout.scancode_from_name("RESET").is_some() {
+            let has_reset = reachable.iter().any(|layer| {
+                self.map
+                    .values()
+                    .any(|scancodes| effective(layout, scancodes, *layer) == "RESET")
+            });
+            if !has_reset {
+                lints.push(KeyMapLint::NoReset);
+            }
+        }
+
+        for layer in 0..num_layers {
+            let mut modifiers = HashMap::<Cow<str>, Vec<String>>::new();
+            let mut rows = BTreeMap::<u8, bool>::new();
+            for key in layout.physical.keys.iter() {
+                let logical_name = key.logical_name();
+                let scancode_name = match self.map.get(&logical_name).and_then(|x| x.get(layer)) {
+                    Some(scancode_name) => canonical(layout, scancode_name),
+                    None => continue,
+                };
+                *rows.entry(key.logical.0).or_insert(true) &= scancode_name == "NONE";
+                if MODIFIERS.contains(&&*scancode_name) {
+                    modifiers
+                        .entry(scancode_name)
+                        .or_default()
+                        .push(logical_name);
+                }
+            }
+
+            let mut duplicates = modifiers
+                .into_iter()
+                .filter(|(_, keys)| keys.len() > 1)
+                .collect::<Vec<_>>();
+            duplicates.sort();
+            for (scancode_name, keys) in duplicates {
+                lints.push(KeyMapLint::DuplicateModifier {
+                    layer,
+                    scancode_name: scancode_name.to_string(),
+                    keys,
+                });
+            }
+
+            for (row, empty) in rows {
+                if empty {
+                    lints.push(KeyMapLint::EmptyRow { layer, row });
+                }
+            }
+        }
+
+        lints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layouts;
+
+    fn launch_2() -> Layout {
+        Layout::from_board("system76/launch_2", "0.19.12").unwrap()
+    }
+
+    fn replace(keymap: &mut KeyMap, from: &str, to: &str) {
+        for scancodes in keymap.map.values_mut() {
+            for scancode in scancodes.iter_mut() {
+                if scancode == from {
+                    *scancode = to.to_string();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn default_keymaps_have_escape() {
+        // Some default keymaps (Launch Lite) have no `RESET` key, so only check layer escapes
+        for i in layouts() {
+            let layout = Layout::from_board(i, "0.19.12").unwrap();
+            let lints = layout.default.lint(&layout);
+            assert!(
+                !lints
+                    .iter()
+                    .any(|lint| matches!(lint, KeyMapLint::NoEscape { .. })),
+                "{}: {:?}",
+                i,
+                lints
+            );
+        }
+    }
+
+    #[test]
+    fn removed_fn() {
+        let layout = launch_2();
+        let mut keymap = layout.default.clone();
+        replace(&mut keymap, "FN", "RIGHT_CTRL");
+        let lints = keymap.lint(&layout);
+        assert!(lints.contains(&KeyMapLint::UnreachableLayer { layer: 1 }));
+        // `RESET` was only reachable through the Fn layer
+        assert!(lints.contains(&KeyMapLint::NoReset));
+    }
+
+    #[test]
+    fn qmk_aliases() {
+        let layout = launch_2();
+        let mut keymap = layout.default.clone();
+        replace(&mut keymap, "FN", "MO(1)");
+        replace(&mut keymap, "RESET", "QK_BOOT");
+        replace(&mut keymap, "ROLL_OVER", "_______");
+        assert_eq!(keymap.lint(&layout), layout.default.lint(&layout));
+    }
+
+    #[test]
+    fn toggle_without_escape() {
+        let layout = launch_2();
+        let mut keymap = layout.default.clone();
+        keymap.map.get_mut("K00").unwrap()[0] = "LAYER_SWITCH_3".to_string();
+        let lints = keymap.lint(&layout);
+        assert!(lints.contains(&KeyMapLint::NoEscape { layer: 2 }));
+
+        keymap.map.get_mut("K01").unwrap()[2] = "LAYER_SWITCH_1".to_string();
+        let lints = keymap.lint(&layout);
+        assert!(!lints.contains(&KeyMapLint::NoEscape { layer: 2 }));
+    }
+
+    #[test]
+    fn layer_tap_reaches_layer() {
+        let layout = launch_2();
+        let mut keymap = layout.default.clone();
+        keymap.map.get_mut("K00").unwrap()[0] = "LT(3, ESC)".to_string();
+        let lints = keymap.lint(&layout);
+        assert!(!lints.contains(&KeyMapLint::UnreachableLayer { layer: 3 }));
+    }
+
+    #[test]
+    fn duplicate_modifier_and_empty_row() {
+        let layout = launch_2();
+        let mut keymap = layout.default.clone();
+        for (k, scancodes) in keymap.map.iter_mut() {
+            if k.starts_with("K0") {
+                scancodes[0] = "NONE".to_string();
+            }
+        }
+        keymap.map.get_mut("K00").unwrap()[0] = "LEFT_SHIFT".to_string();
+        let lints = keymap.lint(&layout);
+        assert!(lints.iter().any(|lint| matches!(
+            lint,
+            KeyMapLint::DuplicateModifier { layer: 0, scancode_name, .. } if scancode_name == "LEFT_SHIFT"
+        )));
+        assert!(!lints.contains(&KeyMapLint::EmptyRow { layer: 0, row: 0 }));
+
+        keymap.map.get_mut("K00").unwrap()[0] = "NONE".to_string();
+        let lints = keymap.lint(&layout);
+        assert!(lints.contains(&KeyMapLint::EmptyRow { layer: 0, row: 0 }));
+
+        // Fluent isolates arguments with Unicode directional marks
+        let message = KeyMapLint::EmptyRow { layer: 0, row: 2 }
+            .to_string()
+            .replace(['\u{2068}', '\u{2069}'], "");
+        assert_eq!(message, "Row 2 on layer 1 only has NONE keys");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::Rgb;
+use crate::{FirmwareKind, Rgb, UsbHubLayout};
 use serde::Deserialize;
 
 fn num_layers_default() -> u8 {
@@ -32,4 +32,17 @@ pub struct Meta {
     pub pressed_color: Rgb,
     #[serde(default)]
     pub is_qmk: bool,
+    /// Topology of the integrated USB hub, if the keyboard has one
+    #[serde(default)]
+    pub usb_hub: Option<UsbHubLayout>,
+}
+
+impl Meta {
+    pub fn firmware_kind(&self) -> FirmwareKind {
+        if self.is_qmk {
+            FirmwareKind::Qmk
+        } else {
+            FirmwareKind::Ec
+        }
+    }
 }
@@ -1,6 +1,12 @@
 use cascade::cascade;
 use regex::Regex;
-use std::{collections::HashMap, convert::TryFrom, fs, path::Path, str::FromStr};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::TryFrom,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
 
 mod meta;
 use once_cell::sync::Lazy;
@@ -8,18 +14,32 @@ mod physical_layout;
 pub use self::meta::Meta;
 pub(crate) use physical_layout::{PhysicalLayout, PhysicalLayoutKey};
 
-use crate::KeyMap;
-
-// Merge date of https://github.com/system76/ec/pull/229
-// Before this, `PAUSE` will not work.
-const EC_PAUSE_DATE: (u16, u16, u16) = (2022, 5, 23);
-// https://github.com/system76/ec/pull/263
-const EC_FNLOCK_DATE: (u16, u16, u16) = (2023, 8, 1);
+use crate::{
+    data_dir, import_via_definition, qmk_keycode_name, via_definition_board, FirmwareFeature,
+    FirmwareKind, FirmwareVersion, KeyMap,
+};
+
+/// Fields of `meta.json` needed to find the rest of a layout
+#[derive(Deserialize)]
+struct MetaKeyboard {
+    keyboard: String,
+    #[serde(default)]
+    is_qmk: bool,
+}
 
 const QK_MOD_TAP_LEGACY: u16 = 0x6000;
 const QK_MOD_TAP_MAX_LEGACY: u16 = 0x7FFF;
 const QK_MOD_TAP: u16 = 0x2000;
 const QK_MOD_TAP_MAX: u16 = 0x3FFF;
+const QK_LAYER_TAP: u16 = 0x4000;
+const QK_LAYER_TAP_MAX: u16 = 0x4FFF;
+
+/// Mod-tap name, like `MT(LEFT_SHIFT, A)`, capturing the modifier and key
+pub(crate) static MT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"MT\(([^(),]+),\s*([^(),]+)\)").unwrap());
+/// Layer-tap name, like `LT(1, A)`, capturing the layer, numbered from 0, and key
+pub(crate) static LT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^LT\((\d+),\s*([^()]+)\)$").unwrap());
 
 pub static MOD_TAP_MODS: Lazy<HashMap<&str, u16>> = Lazy::new(|| {
     cascade! {
@@ -46,12 +66,12 @@ pub struct Layout {
     pub(crate) physical: PhysicalLayout,
     pub(crate) layout: HashMap<String, (u8, u8)>,
     pub(crate) leds: HashMap<String, Vec<u8>>,
-    use_legacy_scancodes: bool,
+    pub(crate) use_legacy_scancodes: bool,
 }
 
 macro_rules! keyboards {
     ($( ($board:expr, $keyboard:expr, $is_qmk:expr) ),* $(,)?) => {
-        fn layout_data(board: &str, use_legacy_scancodes: bool) -> Option<(&'static str, &'static str, &'static str, &'static str, &'static str, &'static str)> {
+        fn layout_data(board: &str) -> Option<(&'static str, &'static str, &'static str, &'static str, &'static str)> {
             match board {
                 $(
                 $board => {
@@ -59,20 +79,13 @@ macro_rules! keyboards {
                         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/", $board, "/meta.json"));
                     let default_json =
                         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/", $board, "/default.json"));
-                    let keymap_json = if use_legacy_scancodes && $is_qmk {
-                        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keymap/qmk_legacy.json"))
-                    } else if $is_qmk {
-                        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keymap/qmk.json"))
-                    } else {
-                        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keymap/ec.json"))
-                    };
                     let layout_json =
                         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keyboards/", $keyboard, "/layout.json"));
                     let leds_json =
                         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keyboards/", $keyboard, "/leds.json"));
                     let physical_json =
                         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keyboards/", $keyboard, "/physical.json"));
-                    Some((meta_json, default_json, keymap_json, layout_json, leds_json, physical_json))
+                    Some((meta_json, default_json, layout_json, leds_json, physical_json))
                 }
                 )*
                 _ => None
@@ -83,12 +96,138 @@ macro_rules! keyboards {
         pub fn layouts() -> &'static [&'static str] {
             &[$( $board ),*]
         }
+
+        fn firmware_kind(board: &str) -> Option<FirmwareKind> {
+            match board {
+                $( $board => Some(if $is_qmk { FirmwareKind::Qmk } else { FirmwareKind::Ec }), )*
+                _ => None
+            }
+        }
+
+        /// Built-in `layout.json`, `leds.json`, and `physical.json` of `keyboard`
+        fn keyboard_data(keyboard: &str) -> Option<(&'static str, &'static str, &'static str)> {
+            let board = [$( ($board, $keyboard) ),*]
+                .iter()
+                .find(|(_, x)| *x == keyboard)?
+                .0;
+            let (_, _, layout_json, leds_json, physical_json) = layout_data(board)?;
+            Some((layout_json, leds_json, physical_json))
+        }
     };
 }
 
 // Calls the `keyboards!` macro
 include!(concat!(env!("OUT_DIR"), "/keyboards.rs"));
 
+/// File name and built-in contents of the keymap for `kind` of firmware
+fn keymap_data(kind: FirmwareKind, use_legacy_scancodes: bool) -> (&'static str, &'static str) {
+    match kind {
+        FirmwareKind::Qmk if use_legacy_scancodes => (
+            "qmk_legacy.json",
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../layouts/keymap/qmk_legacy.json"
+            )),
+        ),
+        FirmwareKind::Qmk => (
+            "qmk.json",
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../layouts/keymap/qmk.json"
+            )),
+        ),
+        FirmwareKind::Ec => (
+            "ec.json",
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../layouts/keymap/ec.json"
+            )),
+        ),
+    }
+}
+
+/// Directories searched by `Layout::from_board`, in order, before the built-in layouts
+///
+/// These are the directories listed in `SYSTEM76_KEYBOARD_LAYOUT_PATH`, followed by `layouts` in
+/// `data_dir()`. Each is structured like the `layouts` directory of this repository: see
+/// `Layout::from_dir`. Unit tests search no directories, so they only see built-in layouts.
+pub fn layout_search_path() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(test) {
+        return dirs;
+    }
+    if let Some(paths) = env::var_os("SYSTEM76_KEYBOARD_LAYOUT_PATH") {
+        dirs.extend(env::split_paths(&paths).filter(|x| x.is_absolute()));
+    }
+    if let Some(dir) = data_dir() {
+        dirs.push(dir.join("layouts"));
+    }
+    dirs
+}
+
+/// Names of board layouts found in `layout_search_path()`, which may override built-in layouts
+pub fn user_layouts() -> Vec<String> {
+    let mut boards = BTreeSet::new();
+    for dir in layout_search_path() {
+        let vendors = fs::read_dir(&dir).into_iter().flatten();
+        for vendor in vendors.filter_map(Result::ok) {
+            let vendor_name = vendor.file_name().to_string_lossy().into_owned();
+            if vendor_name == "keyboards" || vendor_name == "keymap" {
+                continue;
+            }
+            let board_dirs = fs::read_dir(vendor.path()).into_iter().flatten();
+            for board in board_dirs.filter_map(Result::ok) {
+                if board.path().join("meta.json").is_file() {
+                    boards.insert(format!(
+                        "{}/{}",
+                        vendor_name,
+                        board.file_name().to_string_lossy()
+                    ));
+                }
+            }
+        }
+    }
+    boards.into_iter().collect()
+}
+
+/// Contents of the VIA definition for `board` in `<dir>/via`, if there is one
+fn find_via_definition(dir: &Path, board: &str) -> Option<String> {
+    let files = fs::read_dir(dir.join("via")).into_iter().flatten();
+    for path in files.filter_map(Result::ok).map(|x| x.path()) {
+        if path.extension().map_or(true, |x| x != "json") {
+            continue;
+        }
+        let definition_json = match fs::read_to_string(&path) {
+            Ok(definition_json) => definition_json,
+            Err(err) => {
+                warn!("Failed to read '{}': {}", path.display(), err);
+                continue;
+            }
+        };
+        match via_definition_board(&definition_json) {
+            Ok(x) if x == board => return Some(definition_json),
+            Ok(_) => {}
+            Err(err) => warn!("{}: {}", path.display(), err),
+        }
+    }
+    None
+}
+
+fn parse_json<T: DeserializeOwned>(json: &str, name: &str) -> Result<T, String> {
+    serde_json::from_str(json).map_err(|err| format!("Failed to parse {}: {}", name, err))
+}
+
+/// Read `path`, or use `builtin` if it doesn't exist
+fn read_or_builtin(path: &Path, builtin: Option<&str>) -> Result<String, String> {
+    match (fs::read_to_string(path), builtin) {
+        (Ok(data), _) => Ok(data),
+        (Err(err), Some(builtin)) if err.kind() == io::ErrorKind::NotFound => {
+            Ok(builtin.to_string())
+        }
+        (Err(err), _) => Err(format!("Failed to read '{}': {}", path.display(), err)),
+    }
+}
+
 impl Layout {
     pub fn from_data(
         board: &str,
@@ -100,24 +239,19 @@ impl Layout {
         physical_json: &str,
         version: &str,
         use_legacy_scancodes: bool,
-    ) -> Self {
-        let meta: Meta = serde_json::from_str(meta_json).unwrap();
-        let mut default = KeyMap::try_from(default_json).unwrap();
+    ) -> Result<Self, String> {
+        let meta: Meta = parse_json(meta_json, "meta.json")?;
+        let mut default = KeyMap::try_from(default_json)
+            .map_err(|err| format!("Failed to parse default.json: {}", err))?;
 
-        let has_pause_scancode = if meta.is_qmk {
-            true
-        } else {
-            parse_ec_date(version).map_or(true, |date| date >= EC_PAUSE_DATE)
-        };
+        let firmware = FirmwareVersion::new(version, meta.firmware_kind());
+
+        let has_pause_scancode = firmware.has(FirmwareFeature::Pause);
         if !has_pause_scancode {
             keymap_remove_pause(&mut default);
         }
 
-        let has_fnlock_scancode = if meta.is_qmk {
-            false
-        } else {
-            parse_ec_date(version).map_or(true, |date| date >= EC_FNLOCK_DATE)
-        };
+        let has_fnlock_scancode = firmware.has(FirmwareFeature::FnLock);
         if !has_fnlock_scancode {
             keymap_remove_fnlock(&mut default);
         }
@@ -128,11 +262,18 @@ impl Layout {
             &meta,
             has_pause_scancode,
             has_fnlock_scancode,
-        );
-        let layout = serde_json::from_str(layout_json).unwrap();
-        let leds = serde_json::from_str(leds_json).unwrap();
-        let physical = PhysicalLayout::from_str(physical_json);
-        Self {
+        )?;
+        let layout: HashMap<String, (u8, u8)> = parse_json(layout_json, "layout.json")?;
+        if layout.is_empty() {
+            return Err("layout.json has no keys".to_string());
+        }
+        if let Some(key) = default.map.iter().find(|(_, v)| v.is_empty()) {
+            return Err(format!("Key '{}' in default.json has no layers", key.0));
+        }
+        let leds = parse_json(leds_json, "leds.json")?;
+        let physical = PhysicalLayout::from_str(physical_json)
+            .map_err(|err| format!("Failed to parse physical.json: {}", err))?;
+        Ok(Self {
             meta,
             default,
             keymap,
@@ -141,25 +282,42 @@ impl Layout {
             layout,
             leds,
             use_legacy_scancodes,
-        }
+        })
     }
 
-    #[allow(dead_code)]
-    pub fn from_dir<P: AsRef<Path>>(board: &str, dir: P) -> Self {
+    /// Load the layout of `board` from `dir`, which is structured like the `layouts` directory of
+    /// this repository
+    ///
+    /// `meta.json` and `default.json` are read from `<dir>/<board>`, and the files of the keyboard
+    /// named in `meta.json` from `<dir>/keyboards/<keyboard>`. Keyboard and keymap files not found
+    /// in `dir` are taken from the built-in layouts, so a custom board can reuse a System76
+    /// keyboard.
+    pub fn from_dir<P: AsRef<Path>>(board: &str, dir: P, version: &str) -> Result<Self, String> {
         let dir = dir.as_ref();
+        let board_dir = dir.join(board);
+
+        let meta_json = read_or_builtin(&board_dir.join("meta.json"), None)?;
+        let default_json = read_or_builtin(&board_dir.join("default.json"), None)?;
+
+        let meta: MetaKeyboard = parse_json(&meta_json, "meta.json")?;
+        let kind = if meta.is_qmk {
+            FirmwareKind::Qmk
+        } else {
+            FirmwareKind::Ec
+        };
+        let use_legacy_scancodes =
+            FirmwareVersion::new(version, kind).has(FirmwareFeature::LegacyScancodes);
 
-        let meta_json =
-            fs::read_to_string(dir.join("meta.json")).expect("Failed to load meta.json");
-        let default_json =
-            fs::read_to_string(dir.join("default.json")).expect("Failed to load default.json");
+        let (keymap_name, keymap_builtin) = keymap_data(kind, use_legacy_scancodes);
         let keymap_json =
-            fs::read_to_string(dir.join("keymap.json")).expect("Failed to load keymap.json");
-        let layout_json =
-            fs::read_to_string(dir.join("layout.json")).expect("Failed to load layout.json");
-        let leds_json =
-            fs::read_to_string(dir.join("leds.json")).expect("Failed to load leds.json");
+            read_or_builtin(&dir.join("keymap").join(keymap_name), Some(keymap_builtin))?;
+
+        let keyboard_dir = dir.join("keyboards").join(&meta.keyboard);
+        let builtin = keyboard_data(&meta.keyboard);
+        let layout_json = read_or_builtin(&keyboard_dir.join("layout.json"), builtin.map(|x| x.0))?;
+        let leds_json = read_or_builtin(&keyboard_dir.join("leds.json"), builtin.map(|x| x.1))?;
         let physical_json =
-            fs::read_to_string(dir.join("physical.json")).expect("Failed to load physical.json");
+            read_or_builtin(&keyboard_dir.join("physical.json"), builtin.map(|x| x.2))?;
 
         Self::from_data(
             board,
@@ -169,29 +327,68 @@ impl Layout {
             &layout_json,
             &leds_json,
             &physical_json,
-            "dummy",
-            false,
+            version,
+            use_legacy_scancodes,
         )
+        .map_err(|err| format!("{}: {}", board_dir.display(), err))
     }
 
-    pub fn from_board(board: &str, version: &str) -> Option<Self> {
-        let use_legacy_scancodes = version.contains("0.7.103")
-            || version.contains("0.7.104")
-            || version.contains("0.12.20");
-        layout_data(board, use_legacy_scancodes).map(
-            |(meta_json, default_json, keymap_json, layout_json, leds_json, physical_json)| {
-                Self::from_data(
-                    board,
-                    meta_json,
-                    default_json,
-                    keymap_json,
-                    layout_json,
-                    leds_json,
-                    physical_json,
-                    version,
-                    use_legacy_scancodes,
-                )
-            },
+    /// Load the layout of a VIA keyboard from its VIA definition
+    ///
+    /// See `import_via_definition`.
+    pub fn from_via_definition(definition_json: &str, version: &str) -> Result<Self, String> {
+        let files = import_via_definition(definition_json)?;
+        let use_legacy_scancodes =
+            FirmwareVersion::new(version, FirmwareKind::Qmk).has(FirmwareFeature::LegacyScancodes);
+        let (_, keymap_json) = keymap_data(FirmwareKind::Qmk, use_legacy_scancodes);
+        Self::from_data(
+            &files.board,
+            &files.meta_json,
+            &files.default_json,
+            keymap_json,
+            &files.layout_json,
+            &files.leds_json,
+            &files.physical_json,
+            version,
+            use_legacy_scancodes,
+        )
+    }
+
+    /// Load the layout of `board`, from the first directory in `layout_search_path()` that has
+    /// one, or the built-in layouts
+    ///
+    /// Boards named by `via_board` are also found as VIA definitions in the `via` directory of
+    /// each, with any file name.
+    pub fn from_board(board: &str, version: &str) -> Result<Self, String> {
+        for dir in layout_search_path() {
+            if dir.join(board).join("meta.json").exists() {
+                info!("Loading layout of {} from {}", board, dir.display());
+                return Self::from_dir(board, &dir, version);
+            }
+            if board.starts_with("via/") {
+                if let Some(definition_json) = find_via_definition(&dir, board) {
+                    info!("Loading VIA definition of {} from {}", board, dir.display());
+                    return Self::from_via_definition(&definition_json, version);
+                }
+            }
+        }
+
+        let (meta_json, default_json, layout_json, leds_json, physical_json) =
+            layout_data(board).ok_or_else(|| format!("No layout found for '{}'", board))?;
+        let kind = firmware_kind(board).unwrap();
+        let use_legacy_scancodes =
+            FirmwareVersion::new(version, kind).has(FirmwareFeature::LegacyScancodes);
+        let (_, keymap_json) = keymap_data(kind, use_legacy_scancodes);
+        Self::from_data(
+            board,
+            meta_json,
+            default_json,
+            keymap_json,
+            layout_json,
+            leds_json,
+            physical_json,
+            version,
+            use_legacy_scancodes,
         )
     }
 
@@ -210,27 +407,66 @@ impl Layout {
                 let kc_name = self.scancode_names.get(&kc)?;
                 return Some(format!("MT({}, {})", mod_name, kc_name));
             }
+            if (QK_LAYER_TAP..=QK_LAYER_TAP_MAX).contains(&scancode) {
+                let layer = (scancode >> 8) & 0xf;
+                let kc_name = self.scancode_names.get(&(scancode & 0xff))?;
+                return Some(format!("LT({}, {})", layer, kc_name));
+            }
         }
         self.scancode_names.get(&scancode).cloned()
     }
 
     /// Get the name corresponding to a scancode number
+    ///
+    /// QMK keycode names and aliases, like `KC_BSPC` for `BKSP`, are also accepted.
     pub fn scancode_from_name(&self, name: &str) -> Option<u16> {
         if self.meta.is_qmk {
             // Check if mod-tap
-            let mt_re = Regex::new("MT\\(([^()]+), ([^()]+)\\)").unwrap();
-            if let Some(captures) = mt_re.captures(name) {
+            if let Some(captures) = MT_RE.captures(name) {
                 let qk_mod_tap = if self.use_legacy_scancodes {
                     QK_MOD_TAP_LEGACY
                 } else {
                     QK_MOD_TAP
                 };
-                let mod_ = *MOD_TAP_MODS.get(&captures.get(1).unwrap().as_str())?;
-                let kc = *self.keymap.get(captures.get(2).unwrap().as_str())?;
+                let mod_name = captures[1].trim();
+                let mod_ = match MOD_TAP_MODS.get(mod_name) {
+                    Some(mod_) => *mod_,
+                    None => {
+                        let mod_name = mod_name.strip_prefix("MOD_").unwrap_or(mod_name);
+                        *MOD_TAP_MODS.get(qmk_keycode_name(mod_name).as_str())?
+                    }
+                };
+                let kc = self.keycode_from_name(&captures[2])?;
                 return Some(qk_mod_tap | ((mod_ & 0x1f) << 8) | (kc & 0xff));
             }
+
+            // Check if layer-tap, where the layer is numbered from 0, and the keycode must be basic
+            if let Some(captures) = LT_RE.captures(name) {
+                let layer = captures[1]
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|x| *x <= 0xf && *x < self.meta.num_layers)?;
+                let kc = self
+                    .keycode_from_name(&captures[2])
+                    .filter(|x| *x <= 0xff)?;
+                return Some(QK_LAYER_TAP | (u16::from(layer) << 8) | kc);
+            }
+        }
+        self.keycode_from_name(name)
+    }
+
+    fn keycode_from_name(&self, name: &str) -> Option<u16> {
+        match self.keymap.get(name) {
+            Some(keycode) => Some(*keycode),
+            None => self.keymap.get(&qmk_keycode_name(name)).copied(),
         }
-        self.keymap.get(name).copied()
+    }
+
+    /// Name of the keycode `name` refers to, as returned by `scancode_to_name`
+    ///
+    /// This replaces QMK aliases with the names used here, or returns `None` for unknown names.
+    pub fn canonical_scancode_name(&self, name: &str) -> Option<String> {
+        self.scancode_to_name(self.scancode_from_name(name)?)
     }
 
     pub fn f_keys(&self) -> impl Iterator<Item = &str> {
@@ -249,14 +485,17 @@ impl Layout {
     }
 }
 
+type Keymap = HashMap<String, u16>;
+type ScancodeNames = HashMap<u16, String>;
+
 fn parse_keymap_json(
     keymap_json: &str,
     board: &str,
     meta: &Meta,
     has_pause_scancode: bool,
     has_fnlock_scancode: bool,
-) -> (HashMap<String, u16>, HashMap<u16, String>) {
-    let mut keymap: HashMap<String, u16> = serde_json::from_str(keymap_json).unwrap();
+) -> Result<(Keymap, ScancodeNames), String> {
+    let mut keymap: Keymap = parse_json(keymap_json, "keymap")?;
 
     // Filter out keycodes that aren't relevant to this particular model
     // TODO: Support bonw backlight over USB?
@@ -283,22 +522,7 @@ fn parse_keymap_json(
         scancode_names.insert(*scancode, scancode_name.clone());
     }
 
-    (keymap, scancode_names)
-}
-
-fn parse_ec_date(version: &str) -> Option<(u16, u16, u16)> {
-    let groups = Regex::new(r"^(\d+)-(\d+)-(\d+)_")
-        .unwrap()
-        .captures(version)?;
-    let mut groups = groups
-        .iter()
-        .skip(1)
-        .map(|g| u16::from_str(g.unwrap().as_str()).unwrap());
-    Some((
-        groups.next().unwrap(),
-        groups.next().unwrap(),
-        groups.next().unwrap(),
-    ))
+    Ok((keymap, scancode_names))
 }
 
 fn keymap_remove_pause(keymap: &mut KeyMap) {
@@ -398,6 +622,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn launch_usb_hubs() {
+        for board in [
+            "system76/launch_1",
+            "system76/launch_2",
+            "system76/launch_3",
+            "system76/launch_heavy_1",
+            "system76/launch_heavy_3",
+        ] {
+            let layout = Layout::from_board(board, "0.19.12").unwrap();
+            let usb_hub = layout.meta.usb_hub.as_ref();
+            assert!(usb_hub.map_or(false, |x| x.ports.len() == 4), "{}", board);
+        }
+        let layout = Layout::from_board("system76/launch_lite_1", "0.19.12").unwrap();
+        assert!(layout.meta.usb_hub.is_none());
+    }
+
     #[test]
     fn has_all_layouts_in_dir() -> io::Result<()> {
         let layouts = layouts();
@@ -457,6 +698,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn layout_from_dir() {
+        let dir = env::temp_dir().join(format!("layout-from-dir-{}", std::process::id()));
+        let board_dir = dir.join("acme/custom");
+        fs::create_dir_all(&board_dir).unwrap();
+        let meta = fs::read_to_string("../layouts/system76/launch_2/meta.json").unwrap();
+        fs::write(
+            board_dir.join("meta.json"),
+            meta.replace("Launch", "Custom"),
+        )
+        .unwrap();
+
+        // Missing files are reported, not a panic
+        let err = Layout::from_dir("acme/custom", &dir, "0.19.12").unwrap_err();
+        assert!(err.contains("default.json"), "{}", err);
+
+        // Keyboard and keymap files fall back to the built-in layouts
+        fs::copy(
+            "../layouts/system76/launch_2/default.json",
+            board_dir.join("default.json"),
+        )
+        .unwrap();
+        let layout = Layout::from_dir("acme/custom", &dir, "0.19.12").unwrap();
+        assert_eq!(layout.meta.display_name, "Custom Keyboard");
+        assert_eq!(layout.f_keys().count(), 12);
+
+        fs::create_dir_all(dir.join("keyboards/system76/launch_2")).unwrap();
+        fs::write(dir.join("keyboards/system76/launch_2/leds.json"), "{").unwrap();
+        let err = Layout::from_dir("acme/custom", &dir, "0.19.12").unwrap_err();
+        assert!(err.contains("Failed to parse leds.json"), "{}", err);
+        fs::remove_file(dir.join("keyboards/system76/launch_2/leds.json")).unwrap();
+
+        // Empty layouts and layers are reported, not a panic
+        fs::write(dir.join("keyboards/system76/launch_2/layout.json"), "{}").unwrap();
+        let err = Layout::from_dir("acme/custom", &dir, "0.19.12").unwrap_err();
+        assert!(err.contains("layout.json has no keys"), "{}", err);
+        fs::remove_file(dir.join("keyboards/system76/launch_2/layout.json")).unwrap();
+
+        let mut default = layout.default;
+        default.map.values_mut().next().unwrap().clear();
+        fs::write(
+            board_dir.join("default.json"),
+            serde_json::to_string(&default).unwrap(),
+        )
+        .unwrap();
+        let err = Layout::from_dir("acme/custom", &dir, "0.19.12").unwrap_err();
+        assert!(err.contains("has no layers"), "{}", err);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn layout_has_f_keys() {
         for i in layouts() {
@@ -470,4 +762,46 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn qmk_scancode_aliases() {
+        let layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        let bksp = layout.scancode_from_name("BKSP");
+        assert!(bksp.is_some());
+        assert_eq!(layout.scancode_from_name("KC_BSPC"), bksp);
+        assert_eq!(layout.scancode_from_name("BSPC"), bksp);
+        assert_eq!(layout.scancode_from_name("KC_BACKSPACE"), bksp);
+        assert_eq!(layout.scancode_from_name("KC_NOT_A_KEY"), None);
+        assert_eq!(
+            layout.canonical_scancode_name("KC_VOLU").as_deref(),
+            Some("VOLUME_UP")
+        );
+        assert_eq!(
+            layout
+                .canonical_scancode_name("MT(MOD_LCTL,KC_ESC)")
+                .as_deref(),
+            Some("MT(LEFT_CTRL, ESC)")
+        );
+        assert_eq!(
+            layout.scancode_from_name("MT(LSFT, KC_A)"),
+            layout.scancode_from_name("MT(LEFT_SHIFT, A)")
+        );
+
+        assert_eq!(
+            layout.canonical_scancode_name("LT(2,KC_SPC)").as_deref(),
+            Some("LT(2, SPACE)")
+        );
+        assert_eq!(layout.scancode_from_name("LT(16, A)"), None);
+        // Layer past those of the board, or not a basic keycode
+        assert_eq!(layout.scancode_from_name("LT(4, A)"), None);
+        assert_eq!(layout.scancode_from_name("LT(1, QK_BOOT)"), None);
+        assert_eq!(layout.scancode_from_name("LT(1, LAYER_ACCESS_1)"), None);
+        assert_eq!(layout.scancode_from_name("XLT(1, A)"), None);
+
+        let layout = Layout::from_board("system76/darp6", "0.19.12").unwrap();
+        assert_eq!(
+            layout.canonical_scancode_name("KC_GRV").as_deref(),
+            Some("TICK")
+        );
+    }
 }
@@ -3,7 +3,25 @@
 use serde::Deserialize;
 use std::char;
 
-use crate::{Rect, Rgb};
+use crate::{Rect, Rgb, Rotation};
+
+/// Position of each line of a key's text, for each KLE alignment flag
+///
+/// Positions are numbered left to right, then top to bottom, in a 3x3 grid on top of the key,
+/// followed by 3 on its front edge. From `kle-serial`.
+const LABEL_MAP: [[i8; 12]; 8] = [
+    [0, 6, 2, 8, 9, 11, 3, 5, 1, 4, 7, 10],         // No centering
+    [1, 7, -1, -1, 9, 11, 4, -1, -1, -1, -1, 10],   // Center x
+    [3, -1, 5, -1, 9, 11, -1, -1, 4, -1, -1, 10],   // Center y
+    [4, -1, -1, -1, 9, 11, -1, -1, -1, -1, -1, 10], // Center x and y
+    [0, 6, 2, 8, 10, -1, 3, 5, 1, 4, 7, -1],        // Center front (default)
+    [1, 7, -1, -1, 10, -1, 4, -1, -1, -1, -1, -1],  // Center front and x
+    [3, -1, 5, -1, 10, -1, -1, -1, 4, -1, -1, -1],  // Center front and y
+    [4, -1, -1, -1, 10, -1, -1, -1, -1, -1, -1, -1], // Center front, x, and y
+];
+
+/// Default KLE alignment flag
+const DEFAULT_ALIGN: u8 = 4;
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -13,17 +31,28 @@ pub(crate) struct PhysicalLayout {
 }
 
 impl PhysicalLayout {
-    pub fn from_str(physical_json: &str) -> Self {
-        let json = serde_json::from_str::<PhysicalLayoutJson>(physical_json).unwrap();
+    /// Parse JSON downloaded from keyboard-layout-editor.com
+    ///
+    /// Decals are not keys, and are skipped without counting towards the logical position of
+    /// keys after them.
+    pub fn from_str(physical_json: &str) -> Result<Self, String> {
+        let json = serde_json::from_str::<PhysicalLayoutJson>(physical_json)
+            .map_err(|err| err.to_string())?;
 
         let mut keys = Vec::new();
 
         let mut row_i = 0;
         let mut col_i = 0;
-        let mut physical = Rect::new(0.0, 0.0, 1.0, 1.0);
-        let mut background_color = Rgb::new(0xcc, 0xcc, 0xcc);
         let mut meta = None;
 
+        // KLE coordinates, with y increasing downwards
+        let mut x = 0.0;
+        let mut y = 0.0;
+        let mut rotation = Rotation::default();
+        let mut current = KeyState::default();
+        let mut background_color = Rgb::new(0xcc, 0xcc, 0xcc);
+        let mut align = DEFAULT_ALIGN;
+
         for entry in json.0 {
             match entry {
                 PhysicalLayoutEntry::Meta(data) => {
@@ -34,32 +63,64 @@ impl PhysicalLayout {
                         match i {
                             PhysicalKeyEnum::Meta(meta) => {
                                 debug!("Key metadata {:?}", meta);
-                                physical.x += meta.x;
-                                physical.y -= meta.y;
-                                physical.w = meta.w.unwrap_or(physical.w);
-                                physical.h = meta.h.unwrap_or(physical.h);
+                                if let Some(r) = meta.r {
+                                    rotation.angle = r;
+                                }
+                                // Setting a rotation origin starts a new cluster of keys there
+                                if let Some(rx) = meta.rx {
+                                    rotation.x = rx;
+                                    x = rotation.x;
+                                    y = rotation.y;
+                                }
+                                if let Some(ry) = meta.ry {
+                                    rotation.y = ry;
+                                    x = rotation.x;
+                                    y = rotation.y;
+                                }
+                                x += meta.x;
+                                y += meta.y;
+                                if let Some(w) = meta.w {
+                                    current.w = w;
+                                    current.w2 = w;
+                                }
+                                if let Some(h) = meta.h {
+                                    current.h = h;
+                                    current.h2 = h;
+                                }
+                                current.x2 = meta.x2.unwrap_or(current.x2);
+                                current.y2 = meta.y2.unwrap_or(current.y2);
+                                current.w2 = meta.w2.unwrap_or(current.w2);
+                                current.h2 = meta.h2.unwrap_or(current.h2);
+                                current.decal = meta.d;
                                 background_color = meta.c.unwrap_or(background_color);
+                                align = meta.a.unwrap_or(align);
                             }
                             PhysicalKeyEnum::Name(name) => {
-                                keys.push(PhysicalLayoutKey {
-                                    logical: (row_i as u8, col_i as u8),
-                                    physical,
-                                    physical_name: name.clone(),
-                                    background_color,
-                                });
+                                if !current.decal {
+                                    keys.push(PhysicalLayoutKey {
+                                        logical: (row_i as u8, col_i as u8),
+                                        physical: Rect::new(x, -y, current.w, current.h),
+                                        physical2: current.secondary(x, y),
+                                        rotation: Rotation {
+                                            angle: rotation.angle,
+                                            x: rotation.x,
+                                            y: -rotation.y,
+                                        },
+                                        physical_name: name.clone(),
+                                        legends: legends(name, align),
+                                        background_color,
+                                    });
+                                    col_i += 1;
+                                }
 
-                                physical.x += physical.w;
-
-                                physical.w = 1.0;
-                                physical.h = 1.0;
-
-                                col_i += 1;
+                                x += current.w;
+                                current = KeyState::default();
                             }
                         }
                     }
 
-                    physical.x = 0.0;
-                    physical.y -= 1.0;
+                    x = rotation.x;
+                    y += 1.0;
 
                     col_i = 0;
                     row_i += 1;
@@ -67,17 +128,70 @@ impl PhysicalLayout {
             }
         }
 
-        let meta = meta.expect("No layout meta");
+        Ok(Self {
+            keys,
+            meta: meta.unwrap_or_default(),
+        })
+    }
+}
+
+/// Properties that KLE resets after each key
+struct KeyState {
+    w: f64,
+    h: f64,
+    x2: f64,
+    y2: f64,
+    w2: f64,
+    h2: f64,
+    decal: bool,
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        Self {
+            w: 1.0,
+            h: 1.0,
+            x2: 0.0,
+            y2: 0.0,
+            w2: 1.0,
+            h2: 1.0,
+            decal: false,
+        }
+    }
+}
+
+impl KeyState {
+    /// Second rectangle of a key at `(x, y)`, like the lower part of an ISO enter
+    fn secondary(&self, x: f64, y: f64) -> Option<Rect> {
+        if self.x2 == 0.0 && self.y2 == 0.0 && self.w2 == self.w && self.h2 == self.h {
+            return None;
+        }
+        Some(Rect::new(x + self.x2, -(y + self.y2), self.w2, self.h2))
+    }
+}
 
-        Self { keys, meta }
+/// Split KLE key text into its 12 legend positions
+fn legends(name: &str, align: u8) -> Vec<String> {
+    let label_map = LABEL_MAP
+        .get(usize::from(align))
+        .unwrap_or(&LABEL_MAP[usize::from(DEFAULT_ALIGN)]);
+    let mut legends = vec![String::new(); 12];
+    for (line, position) in name.split('\n').zip(label_map.iter()) {
+        if let Ok(position) = usize::try_from(*position) {
+            legends[position] = line.to_string();
+        }
     }
+    legends
 }
 
 #[derive(Debug)]
 pub(crate) struct PhysicalLayoutKey {
     pub logical: (u8, u8),
     pub physical: Rect,
+    pub physical2: Option<Rect>,
+    pub rotation: Rotation,
     pub physical_name: String,
+    pub legends: Vec<String>,
     pub background_color: Rgb,
 }
 
@@ -97,14 +211,17 @@ struct PhysicalLayoutJson(Vec<PhysicalLayoutEntry>);
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 enum PhysicalLayoutEntry {
-    Meta(PhysicalLayoutMeta),
+    // Before `Meta`, which could also be deserialized from a short row
     Row(PhysicalRow),
+    Meta(PhysicalLayoutMeta),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 #[allow(dead_code)]
 pub(crate) struct PhysicalLayoutMeta {
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub author: String,
 }
 
@@ -126,5 +243,70 @@ struct PhysicalKeyMeta {
     y: f64,
     w: Option<f64>,
     h: Option<f64>,
+    x2: Option<f64>,
+    y2: Option<f64>,
+    w2: Option<f64>,
+    h2: Option<f64>,
+    r: Option<f64>,
+    rx: Option<f64>,
+    ry: Option<f64>,
+    a: Option<u8>,
     c: Option<Rgb>,
+    /// Decal, which is only a label, not a key
+    #[serde(default)]
+    d: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn physical_layout_kle() {
+        // No metadata, an ISO enter, a decal, and a rotated cluster
+        let layout = PhysicalLayout::from_str(
+            r##"[
+                [{"a": 7, "c": "#abc"}, "Tab", {"x": 0.25, "w": 1.25, "h": 2, "w2": 1.5, "h2": 1, "x2": -0.25}, "Enter"],
+                [{"a": 4}, "A\nB\nC", {"d": true}, "Logo", "D"],
+                [{"r": 15, "rx": 5, "ry": 1, "y": -1}, "E", "F"],
+                ["G"]
+            ]"##,
+        )
+        .unwrap();
+        let key = |name: &str| {
+            layout
+                .keys
+                .iter()
+                .find(|x| x.physical_name == name)
+                .unwrap()
+        };
+
+        assert_eq!(layout.meta.name, "");
+        assert_eq!(key("Tab").background_color, Rgb::new(0xaa, 0xbb, 0xcc));
+        assert_eq!(key("Enter").physical, Rect::new(1.25, 0.0, 1.25, 2.0));
+        assert_eq!(key("Enter").physical2, Some(Rect::new(1.0, 0.0, 1.5, 1.0)));
+        assert_eq!(key("Tab").physical2, None);
+
+        // Decal is skipped
+        assert!(layout.keys.iter().all(|x| x.physical_name != "Logo"));
+        assert_eq!(key("D").logical, (1, 1));
+        assert_eq!(key("D").physical.x, 2.0);
+
+        assert_eq!(key("A\nB\nC").legends[0], "A");
+        assert_eq!(key("A\nB\nC").legends[6], "B");
+        assert_eq!(key("A\nB\nC").legends[2], "C");
+        assert_eq!(key("Tab").legends[4], "Tab");
+
+        // Rotated cluster starts at its origin, and following rows return to it
+        let rotation = Rotation {
+            angle: 15.0,
+            x: 5.0,
+            y: -1.0,
+        };
+        assert_eq!(key("E").rotation, rotation);
+        assert_eq!(key("E").physical, Rect::new(5.0, 0.0, 1.0, 1.0));
+        assert_eq!(key("F").physical, Rect::new(6.0, 0.0, 1.0, 1.0));
+        assert_eq!(key("G").physical, Rect::new(5.0, -1.0, 1.0, 1.0));
+        assert_eq!(key("G").rotation, rotation);
+    }
 }
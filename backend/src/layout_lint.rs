@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::{layer_label, Layout};
+
+/// First LED index that refers to a layer or the whole keyboard, rather than a key
+const LED_INDEX_LAYER: u8 = 0xF0;
+
+/// Inconsistency between the data files of a layout, found by `Layout::lint`
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LayoutLint {
+    /// Key in `physical.json` has no electrical position in `layout.json`
+    MissingElectrical { key: String },
+    /// Key in `layout.json` is not in `physical.json`
+    UnknownElectrical { key: String },
+    /// More than one key has the same electrical position in `layout.json`
+    DuplicateElectrical {
+        electrical: (u8, u8),
+        keys: Vec<String>,
+    },
+    /// LED index in `leds.json` is reserved for layers
+    LedOutOfRange { key: String, index: u8 },
+    /// Key in `leds.json` is not in `physical.json`
+    UnknownLed { key: String },
+    /// Key in `physical.json` has no binding in `default.json`
+    MissingDefault { key: String },
+    /// Key in `default.json` is not in `physical.json`
+    UnknownDefault { key: String },
+    /// Key in `default.json` is bound on a different number of layers than `meta.json` has
+    DefaultLayers {
+        key: String,
+        layers: usize,
+        num_layers: u8,
+    },
+    /// Keycode in `default.json` is not in the keymap table for the firmware
+    UnknownScancode {
+        key: String,
+        layer: usize,
+        scancode_name: String,
+    },
+}
+
+impl fmt::Display for LayoutLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingElectrical { key } => {
+                write!(f, "{} has no electrical position in layout.json", key)
+            }
+            Self::UnknownElectrical { key } => {
+                write!(f, "{} in layout.json is not in physical.json", key)
+            }
+            Self::DuplicateElectrical { electrical, keys } => write!(
+                f,
+                "Electrical position {}, {} is used by {}",
+                electrical.0,
+                electrical.1,
+                keys.join(", ")
+            ),
+            Self::LedOutOfRange { key, index } => write!(
+                f,
+                "{} has LED index 0x{:02X}, which is reserved for layers",
+                key, index
+            ),
+            Self::UnknownLed { key } => write!(f, "{} in leds.json is not in physical.json", key),
+            Self::MissingDefault { key } => write!(f, "{} has no binding in default.json", key),
+            Self::UnknownDefault { key } => {
+                write!(f, "{} in default.json is not in physical.json", key)
+            }
+            Self::DefaultLayers {
+                key,
+                layers,
+                num_layers,
+            } => write!(
+                f,
+                "{} has {} layers in default.json, instead of {}",
+                key, layers, num_layers
+            ),
+            Self::UnknownScancode {
+                key,
+                layer,
+                scancode_name,
+            } => write!(
+                f,
+                "{} is bound to unknown keycode {} on layer {}",
+                key,
+                scancode_name,
+                layer_label(*layer)
+            ),
+        }
+    }
+}
+
+impl Layout {
+    /// Cross-check the data files of this layout
+    pub fn lint(&self) -> Vec<LayoutLint> {
+        let mut lints = Vec::new();
+        let physical = self
+            .physical
+            .keys
+            .iter()
+            .map(|x| x.logical_name())
+            .collect::<BTreeSet<_>>();
+
+        let mut electrical_keys = BTreeMap::<_, Vec<_>>::new();
+        for key in &physical {
+            match self.layout.get(key) {
+                Some(electrical) => electrical_keys.entry(*electrical).or_default().push(key),
+                None => lints.push(LayoutLint::MissingElectrical { key: key.clone() }),
+            }
+        }
+        for (electrical, keys) in electrical_keys {
+            if keys.len() > 1 {
+                lints.push(LayoutLint::DuplicateElectrical {
+                    electrical,
+                    keys: keys.into_iter().cloned().collect(),
+                });
+            }
+        }
+        for key in self.layout.keys().filter(|x| !physical.contains(*x)) {
+            lints.push(LayoutLint::UnknownElectrical { key: key.clone() });
+        }
+
+        for (key, indices) in &self.leds {
+            if !physical.contains(key) {
+                lints.push(LayoutLint::UnknownLed { key: key.clone() });
+            }
+            for index in indices.iter().filter(|x| **x >= LED_INDEX_LAYER) {
+                lints.push(LayoutLint::LedOutOfRange {
+                    key: key.clone(),
+                    index: *index,
+                });
+            }
+        }
+
+        let num_layers = self.meta.num_layers;
+        for key in &physical {
+            let scancodes = match self.default.map.get(key) {
+                Some(scancodes) => scancodes,
+                None => {
+                    lints.push(LayoutLint::MissingDefault { key: key.clone() });
+                    continue;
+                }
+            };
+            if scancodes.len() != usize::from(num_layers) {
+                lints.push(LayoutLint::DefaultLayers {
+                    key: key.clone(),
+                    layers: scancodes.len(),
+                    num_layers,
+                });
+            }
+            for (layer, scancode_name) in scancodes.iter().enumerate() {
+                if self.scancode_from_name(scancode_name).is_none() {
+                    lints.push(LayoutLint::UnknownScancode {
+                        key: key.clone(),
+                        layer,
+                        scancode_name: scancode_name.clone(),
+                    });
+                }
+            }
+        }
+        for key in self.default.map.keys().filter(|x| !physical.contains(*x)) {
+            lints.push(LayoutLint::UnknownDefault { key: key.clone() });
+        }
+
+        lints.sort();
+        lints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layouts;
+
+    #[test]
+    fn layout_lint() {
+        for i in layouts() {
+            for version in ["0.7.103", "0.19.12", "dummy"] {
+                let layout = Layout::from_board(i, version).unwrap();
+                assert_eq!(layout.lint(), Vec::new(), "{} {}", i, version);
+            }
+        }
+
+        let mut layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        layout.layout.insert("K01".to_string(), (0, 0));
+        layout.leds.insert("K00".to_string(), vec![0xF0]);
+        layout.default.map.remove("K02");
+        layout.default.map.insert(
+            "K03".to_string(),
+            vec!["A".to_string(), "NOT_A_KEY".to_string()],
+        );
+        assert_eq!(
+            layout.lint(),
+            vec![
+                LayoutLint::DuplicateElectrical {
+                    electrical: (0, 0),
+                    keys: vec!["K00".to_string(), "K01".to_string()]
+                },
+                LayoutLint::LedOutOfRange {
+                    key: "K00".to_string(),
+                    index: 0xF0
+                },
+                LayoutLint::MissingDefault {
+                    key: "K02".to_string()
+                },
+                LayoutLint::DefaultLayers {
+                    key: "K03".to_string(),
+                    layers: 2,
+                    num_layers: 4
+                },
+                LayoutLint::UnknownScancode {
+                    key: "K03".to_string(),
+                    layer: 1,
+                    scancode_name: "NOT_A_KEY".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -24,22 +24,41 @@ extern crate log;
 mod backend;
 mod benchmark;
 mod board;
+mod bootloader;
+mod capabilities;
+mod chatter;
 mod color;
 mod daemon;
 mod deref_cell;
+mod dirs;
+mod firmware_version;
+mod flash;
+mod fwupd;
 mod key;
+mod key_tester;
 mod keymap;
+mod keymap_lint;
 mod layer;
 mod layout;
+mod layout_lint;
 mod localize;
 mod matrix;
 mod mode;
 mod nelson;
+mod nelson_report;
+mod qmk;
+mod qmk_info;
 mod rect;
+mod test_plan;
+mod usage;
+mod via_definition;
 
 pub use crate::daemon::BoardId;
 use crate::daemon::*;
 pub use crate::{
-    backend::*, benchmark::*, board::*, color::*, deref_cell::*, key::*, keymap::*, layer::*,
-    layout::*, localize::*, matrix::*, mode::*, nelson::*, rect::*,
+    backend::*, benchmark::*, board::*, capabilities::*, chatter::*, color::*, deref_cell::*,
+    dirs::*, firmware_version::*, flash::*, fwupd::*, key::*, key_tester::*, keymap::*,
+    keymap_lint::*, layer::*, layout::*, layout_lint::*, localize::*, matrix::*, mode::*,
+    nelson::*, nelson_report::*, qmk::*, qmk_info::*, rect::*, test_plan::*, usage::*,
+    via_definition::*,
 };
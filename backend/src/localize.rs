@@ -30,6 +30,11 @@ macro_rules! fl {
     }};
 }
 
+/// Label of a layer index in messages, numbered from 1 to match the layer names in the GUI
+pub(crate) fn layer_label(layer: usize) -> String {
+    (layer + 1).to_string()
+}
+
 // Get the `Localizer` to be used for localizing this library.
 pub fn localizer() -> Box<dyn Localizer> {
     Box::from(DefaultLocalizer::new(&*LANGUAGE_LOADER, &Localizations))
@@ -43,4 +43,61 @@ impl Matrix {
             }
         }
     }
+
+    /// Set every position that is set in `other`, resizing if this matrix is empty
+    pub fn union(&mut self, other: &Matrix) {
+        if self.rows == 0 || self.cols == 0 {
+            *self = other.clone();
+            return;
+        }
+        for row in 0..other.rows {
+            for col in 0..other.cols {
+                if other.get(row, col).unwrap() {
+                    self.set(row, col, true);
+                }
+            }
+        }
+    }
+
+    /// Positions that changed since `previous`, with their new state
+    ///
+    /// Positions outside of `previous` are treated as released.
+    pub fn changes<'a>(
+        &'a self,
+        previous: &'a Matrix,
+    ) -> impl Iterator<Item = (usize, usize, bool)> + 'a {
+        (0..self.rows).flat_map(move |row| {
+            (0..self.cols).filter_map(move |col| {
+                let pressed = self.get(row, col).unwrap();
+                if previous.get(row, col).unwrap_or(false) != pressed {
+                    Some((row, col, pressed))
+                } else {
+                    None
+                }
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matrix_changes() {
+        let mut previous = Matrix::new(2, 5, vec![0; 2].into_boxed_slice());
+        previous.set(0, 1, true);
+        previous.set(1, 4, true);
+
+        let mut matrix = previous.clone();
+        matrix.set(0, 1, false);
+        matrix.set(1, 2, true);
+
+        let changes = matrix.changes(&previous).collect::<Vec<_>>();
+        assert_eq!(changes, vec![(0, 1, false), (1, 2, true)]);
+
+        // First snapshot, compared to an empty default matrix
+        let changes = matrix.changes(&Matrix::default()).collect::<Vec<_>>();
+        assert_eq!(changes, vec![(1, 2, true), (1, 4, true)]);
+    }
 }
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
 
-use crate::Matrix;
+use crate::{Layout, Matrix};
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum NelsonKind {
@@ -10,6 +12,246 @@ pub enum NelsonKind {
     Bouncing,
 }
 
+/// Settle delay used by the standard Nelson sequence
+pub const NELSON_SETTLE_MS: u64 = 200;
+
+/// One step of a Nelson test sequence
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NelsonStep {
+    /// Close the Nelson, pressing every key, then wait for `settle_ms`
+    Close { settle_ms: u64 },
+    /// Open the Nelson, releasing every key, then wait for `settle_ms`
+    Open { settle_ms: u64 },
+    /// Wait without changing the Nelson
+    Wait { ms: u64 },
+    /// Keys not currently pressed are missing
+    CheckMissing,
+    /// Keys currently pressed are bouncing
+    CheckBouncing,
+    /// Keys currently pressed are sticking
+    CheckSticking,
+}
+
+/// Sequence of steps run by a Nelson test
+///
+/// Results of each repeat are combined, so a key failing any check in any repeat is reported.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct NelsonConfig {
+    /// Times to run `steps`
+    #[serde(default = "NelsonConfig::default_repeats")]
+    pub repeats: u32,
+    pub steps: Vec<NelsonStep>,
+}
+
+impl NelsonConfig {
+    fn default_repeats() -> u32 {
+        1
+    }
+
+    /// The standard sequence for `kind`, with `settle_ms` after closing and opening
+    pub fn new(kind: NelsonKind, settle_ms: u64) -> Self {
+        let check = match kind {
+            NelsonKind::Normal => NelsonStep::CheckMissing,
+            NelsonKind::Bouncing => NelsonStep::CheckBouncing,
+        };
+        Self {
+            repeats: 1,
+            steps: vec![
+                NelsonStep::Close { settle_ms },
+                check,
+                NelsonStep::Open { settle_ms },
+                NelsonStep::CheckSticking,
+            ],
+        }
+    }
+
+    /// Short settle delays, for screening many boards
+    pub fn fast() -> Self {
+        Self::new(NelsonKind::Normal, 50)
+    }
+
+    /// Hold keys down and check them twice, then check for sticking keys twice after release
+    pub fn thorough() -> Self {
+        Self {
+            repeats: 3,
+            steps: vec![
+                NelsonStep::Close { settle_ms: 500 },
+                NelsonStep::CheckMissing,
+                NelsonStep::Wait { ms: 500 },
+                NelsonStep::CheckMissing,
+                NelsonStep::Open { settle_ms: 500 },
+                NelsonStep::CheckSticking,
+                NelsonStep::Wait { ms: 1000 },
+                NelsonStep::CheckSticking,
+            ],
+        }
+    }
+
+    /// Parse sequence from json file
+    pub fn from_reader<R: Read>(rdr: R) -> serde_json::Result<Self> {
+        let config: Self = serde_json::from_reader(rdr)?;
+        config.validate().map_err(serde::de::Error::custom)?;
+        Ok(config)
+    }
+
+    /// Check that the sequence runs at least once, and checks the keys
+    pub fn validate(&self) -> Result<(), String> {
+        if self.repeats == 0 {
+            return Err("Nelson sequence has 0 repeats".to_string());
+        }
+        if self.steps.is_empty() {
+            return Err("Nelson sequence has no steps".to_string());
+        }
+        if !self.steps.iter().any(NelsonStep::is_check) {
+            return Err("Nelson sequence has no check steps".to_string());
+        }
+        Ok(())
+    }
+
+    /// Run the sequence on `fixture`, starting and ending with the Nelson open
+    pub(crate) fn run<F: NelsonFixture>(&self, fixture: &mut F) -> Result<Nelson, String> {
+        self.validate()?;
+
+        let mut missing = Matrix::default();
+        let mut bouncing = Matrix::default();
+        let mut sticking = Matrix::default();
+
+        // Check if Nelson is already closed
+        if fixture.closed()? {
+            info!("Open Nelson");
+            fixture.set_closed(false)?;
+
+            info!("Sleep {} ms", NELSON_SETTLE_MS);
+            fixture.sleep(NELSON_SETTLE_MS);
+        }
+
+        for repeat in 1..=self.repeats {
+            info!("Nelson repeat {}/{}", repeat, self.repeats);
+            for step in self.steps.iter() {
+                match step {
+                    NelsonStep::Close { settle_ms } => {
+                        info!("Close Nelson");
+                        fixture.set_closed(true)?;
+
+                        info!("Sleep {} ms", settle_ms);
+                        fixture.sleep(*settle_ms);
+                    }
+                    NelsonStep::Open { settle_ms } => {
+                        info!("Open Nelson");
+                        fixture.set_closed(false)?;
+
+                        info!("Sleep {} ms", settle_ms);
+                        fixture.sleep(*settle_ms);
+                    }
+                    NelsonStep::Wait { ms } => {
+                        info!("Sleep {} ms", ms);
+                        fixture.sleep(*ms);
+                    }
+                    NelsonStep::CheckMissing => {
+                        // Missing must be inverted, since missing keys are not pressed
+                        let mut matrix = fixture.matrix()?;
+                        for row in 0..matrix.rows() {
+                            for col in 0..matrix.cols() {
+                                let value = matrix.get(row, col).unwrap_or(false);
+                                matrix.set(row, col, !value);
+                            }
+                        }
+                        missing.union(&matrix);
+                    }
+                    NelsonStep::CheckBouncing => bouncing.union(&fixture.matrix()?),
+                    NelsonStep::CheckSticking => sticking.union(&fixture.matrix()?),
+                }
+            }
+        }
+
+        // Don't leave keys pressed if the sequence ended closed
+        if fixture.closed()? {
+            info!("Open Nelson");
+            fixture.set_closed(false)?;
+        }
+
+        Ok(Nelson {
+            missing,
+            bouncing,
+            sticking,
+        })
+    }
+}
+
+impl NelsonStep {
+    fn is_check(&self) -> bool {
+        matches!(
+            self,
+            Self::CheckMissing | Self::CheckBouncing | Self::CheckSticking
+        )
+    }
+}
+
+/// Nelson test fixture and the board in it, as used by `NelsonConfig::run`
+pub(crate) trait NelsonFixture {
+    /// The Nelson is closed, pressing every key
+    fn closed(&mut self) -> Result<bool, String>;
+    fn set_closed(&mut self, closed: bool) -> Result<(), String>;
+    /// Keys currently pressed on the board
+    fn matrix(&mut self) -> Result<Matrix, String>;
+    fn sleep(&mut self, ms: u64);
+}
+
+impl From<NelsonKind> for NelsonConfig {
+    fn from(kind: NelsonKind) -> Self {
+        Self::new(kind, NELSON_SETTLE_MS)
+    }
+}
+
+/// Type of problem a Nelson test can find with a key
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NelsonFailure {
+    /// Not pressed while the Nelson was closed
+    Missing,
+    /// Pressed more than once while the Nelson was closed
+    Bouncing,
+    /// Still pressed after the Nelson was opened
+    Sticking,
+}
+
+impl NelsonFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::Bouncing => "bouncing",
+            Self::Sticking => "sticking",
+        }
+    }
+}
+
+/// A failure of one key in a Nelson test
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NelsonFinding {
+    /// Logical name, like `K01`
+    pub logical_name: String,
+    /// Physical key name (what is printed on the keycap)
+    pub physical_name: String,
+    /// Electrical mapping (output, input)
+    pub electrical: (u8, u8),
+    pub failure: NelsonFailure,
+}
+
+impl fmt::Display for NelsonFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, {}, {}): {}",
+            self.physical_name.replace('\n', " "),
+            self.logical_name,
+            self.electrical.0,
+            self.electrical.1,
+            self.failure.as_str()
+        )
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Nelson {
     pub missing: Matrix,
@@ -43,4 +285,164 @@ impl Nelson {
         }
         true
     }
+
+    /// Failures of keys in `layout`, ordered by logical name then failure kind
+    ///
+    /// Matrix positions that don't map to a key are ignored, like in `success`.
+    pub fn findings(&self, layout: &Layout) -> Vec<NelsonFinding> {
+        let mut findings = Vec::new();
+        for key in layout.physical.keys.iter() {
+            let logical_name = key.logical_name();
+            let electrical = match layout.layout.get(&logical_name) {
+                Some(electrical) => *electrical,
+                None => continue,
+            };
+            for (matrix, failure) in [
+                (&self.missing, NelsonFailure::Missing),
+                (&self.bouncing, NelsonFailure::Bouncing),
+                (&self.sticking, NelsonFailure::Sticking),
+            ] {
+                if matrix
+                    .get(electrical.0 as usize, electrical.1 as usize)
+                    .unwrap_or(false)
+                {
+                    findings.push(NelsonFinding {
+                        logical_name: logical_name.clone(),
+                        physical_name: key.physical_name.clone(),
+                        electrical,
+                        failure,
+                    });
+                }
+            }
+        }
+        findings.sort_by(|a, b| (&a.logical_name, a.failure).cmp(&(&b.logical_name, b.failure)));
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixture with a 1x3 board, where key 1 is missing and key 2 sticks
+    #[derive(Default)]
+    struct MockFixture {
+        closed: bool,
+        log: Vec<String>,
+    }
+
+    impl NelsonFixture for MockFixture {
+        fn closed(&mut self) -> Result<bool, String> {
+            Ok(self.closed)
+        }
+
+        fn set_closed(&mut self, closed: bool) -> Result<(), String> {
+            self.closed = closed;
+            self.log
+                .push(if closed { "close" } else { "open" }.to_string());
+            Ok(())
+        }
+
+        fn matrix(&mut self) -> Result<Matrix, String> {
+            self.log.push("matrix".to_string());
+            let pressed = if self.closed { 0b101 } else { 0b100 };
+            Ok(Matrix::new(1, 3, vec![pressed].into_boxed_slice()))
+        }
+
+        fn sleep(&mut self, ms: u64) {
+            self.log.push(format!("sleep {}", ms));
+        }
+    }
+
+    fn keys(matrix: &Matrix) -> Vec<bool> {
+        (0..3).map(|col| matrix.get(0, col).unwrap()).collect()
+    }
+
+    #[test]
+    fn nelson_config_parse() {
+        let config = NelsonConfig::from_reader(
+            r#"{"steps": [{"close": {"settle_ms": 100}}, "check_missing"]}"#.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(config.repeats, 1);
+        assert_eq!(
+            config.steps,
+            vec![
+                NelsonStep::Close { settle_ms: 100 },
+                NelsonStep::CheckMissing
+            ]
+        );
+
+        for (json, err) in [
+            (r#"{"repeats": 0, "steps": ["check_missing"]}"#, "0 repeats"),
+            (r#"{"steps": []}"#, "no steps"),
+            (r#"{"steps": [{"wait": {"ms": 10}}]}"#, "no check steps"),
+            (r#"{"steps": ["check_all"]}"#, "unknown variant"),
+        ] {
+            let res = NelsonConfig::from_reader(json.as_bytes());
+            let msg = res.unwrap_err().to_string();
+            assert!(msg.contains(err), "{}: {}", json, msg);
+        }
+
+        for config in [
+            NelsonConfig::from(NelsonKind::Normal),
+            NelsonConfig::from(NelsonKind::Bouncing),
+            NelsonConfig::fast(),
+            NelsonConfig::thorough(),
+        ] {
+            config.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn nelson_config_run() {
+        let mut fixture = MockFixture {
+            closed: true,
+            ..MockFixture::default()
+        };
+        let nelson = NelsonConfig::new(NelsonKind::Normal, 10)
+            .run(&mut fixture)
+            .unwrap();
+        assert_eq!(
+            fixture.log,
+            vec![
+                "open",
+                "sleep 200",
+                "close",
+                "sleep 10",
+                "matrix",
+                "open",
+                "sleep 10",
+                "matrix"
+            ]
+        );
+        assert!(!fixture.closed);
+        assert_eq!(keys(&nelson.missing), vec![false, true, false]);
+        assert_eq!(keys(&nelson.sticking), vec![false, false, true]);
+        assert_eq!(nelson.bouncing, Matrix::default());
+
+        // Repeats run every step again, and a sequence ending closed opens the Nelson
+        let config = NelsonConfig {
+            repeats: 2,
+            steps: vec![
+                NelsonStep::Close { settle_ms: 5 },
+                NelsonStep::CheckBouncing,
+            ],
+        };
+        let mut fixture = MockFixture::default();
+        let nelson = config.run(&mut fixture).unwrap();
+        assert_eq!(
+            fixture.log,
+            vec!["close", "sleep 5", "matrix", "close", "sleep 5", "matrix", "open"]
+        );
+        assert_eq!(keys(&nelson.bouncing), vec![true, false, true]);
+
+        let config = NelsonConfig {
+            repeats: 0,
+            ..config
+        };
+        let mut fixture = MockFixture::default();
+        assert!(config.run(&mut fixture).is_err());
+        assert!(fixture.log.is_empty());
+    }
 }
@@ -0,0 +1,306 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Layout, Nelson, NelsonConfig, NelsonFinding};
+
+/// Key covered by a Nelson test
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NelsonKey {
+    pub logical_name: String,
+    pub physical_name: String,
+    pub electrical: (u8, u8),
+}
+
+/// Result of one Nelson test run
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NelsonRun {
+    pub config: NelsonConfig,
+    /// Seconds since the Unix epoch when the run finished
+    pub timestamp: u64,
+    /// Length of the run, in seconds
+    pub duration: f64,
+    /// Error that prevented the test from running
+    pub error: Option<String>,
+    pub findings: Vec<NelsonFinding>,
+}
+
+impl NelsonRun {
+    pub fn success(&self) -> bool {
+        self.error.is_none() && self.findings.is_empty()
+    }
+}
+
+/// Series of Nelson test runs on a board, for export to QA tracking
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NelsonReport {
+    pub model: String,
+    pub version: String,
+    pub keys: Vec<NelsonKey>,
+    pub runs: Vec<NelsonRun>,
+}
+
+impl NelsonReport {
+    pub fn new(model: &str, version: &str, layout: &Layout) -> Self {
+        let keys = layout
+            .physical
+            .keys
+            .iter()
+            .filter_map(|key| {
+                let logical_name = key.logical_name();
+                let electrical = *layout.layout.get(&logical_name)?;
+                Some(NelsonKey {
+                    logical_name,
+                    physical_name: key.physical_name.clone(),
+                    electrical,
+                })
+            })
+            .collect();
+        Self {
+            model: model.to_string(),
+            version: version.to_string(),
+            keys,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Add the result of a run to the report, and return it
+    pub fn push(
+        &mut self,
+        config: &NelsonConfig,
+        layout: &Layout,
+        result: Result<&Nelson, &str>,
+        duration: Duration,
+    ) -> &NelsonRun {
+        let (error, findings) = match result {
+            Ok(nelson) => (None, nelson.findings(layout)),
+            Err(err) => (Some(err.to_string()), Vec::new()),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        self.runs.push(NelsonRun {
+            config: config.clone(),
+            timestamp,
+            duration: duration.as_secs_f64(),
+            error,
+            findings,
+        });
+        self.runs.last().unwrap()
+    }
+
+    pub fn success(&self) -> bool {
+        self.runs.iter().all(NelsonRun::success)
+    }
+
+    pub fn to_json_writer_pretty<W: Write>(&self, wtr: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(wtr, self)
+    }
+
+    /// Write report as JUnit XML, with a test suite for each run and a test case for each key
+    pub fn to_junit_writer<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        let mut xml = String::new();
+        self.write_junit(&mut xml).unwrap();
+        wtr.write_all(xml.as_bytes())
+    }
+
+    fn write_junit(&self, xml: &mut String) -> std::fmt::Result {
+        let suite_failures = |run: &NelsonRun| {
+            self.keys
+                .iter()
+                .filter(|key| {
+                    run.findings
+                        .iter()
+                        .any(|x| x.logical_name == key.logical_name)
+                })
+                .count()
+        };
+
+        let tests = self
+            .runs
+            .iter()
+            .map(|run| self.run_tests(run))
+            .sum::<usize>();
+        let failures = self.runs.iter().map(suite_failures).sum::<usize>();
+        let errors = self.runs.iter().filter(|x| x.error.is_some()).count();
+        let time = self.runs.iter().map(|x| x.duration).sum::<f64>();
+
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            xml,
+            r#"<testsuites name="{}" tests="{}" failures="{}" errors="{}" time="{:.3}">"#,
+            escape(&format!("Nelson {}", self.model)),
+            tests,
+            failures,
+            errors,
+            time
+        )?;
+        for (i, run) in self.runs.iter().enumerate() {
+            writeln!(
+                xml,
+                r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" time="{:.3}">"#,
+                escape(&format!("{} run {}", self.model, i + 1)),
+                self.run_tests(run),
+                suite_failures(run),
+                run.error.is_some() as usize,
+                run.duration
+            )?;
+            writeln!(xml, "    <properties>")?;
+            writeln!(
+                xml,
+                r#"      <property name="version" value="{}"/>"#,
+                escape(&self.version)
+            )?;
+            writeln!(
+                xml,
+                r#"      <property name="sequence" value="{}"/>"#,
+                escape(&serde_json::to_string(&run.config).unwrap())
+            )?;
+            // JUnit timestamps have no time zone, so record Unix time as a property instead
+            writeln!(
+                xml,
+                r#"      <property name="timestamp" value="{}"/>"#,
+                run.timestamp
+            )?;
+            writeln!(xml, "    </properties>")?;
+
+            if let Some(error) = &run.error {
+                writeln!(
+                    xml,
+                    r#"    <testcase classname="{}" name="nelson">"#,
+                    escape(&self.model)
+                )?;
+                writeln!(xml, r#"      <error message="{}"/>"#, escape(error))?;
+                writeln!(xml, "    </testcase>")?;
+            } else {
+                for key in self.keys.iter() {
+                    write!(
+                        xml,
+                        r#"    <testcase classname="{}" name="{}""#,
+                        escape(&self.model),
+                        escape(&format!(
+                            "{} ({})",
+                            key.logical_name,
+                            key.physical_name.replace('\n', " ")
+                        ))
+                    )?;
+                    let findings = run
+                        .findings
+                        .iter()
+                        .filter(|x| x.logical_name == key.logical_name)
+                        .collect::<Vec<_>>();
+                    if findings.is_empty() {
+                        writeln!(xml, "/>")?;
+                        continue;
+                    }
+                    writeln!(xml, ">")?;
+                    for finding in findings {
+                        writeln!(
+                            xml,
+                            r#"      <failure type="{}" message="{}"/>"#,
+                            finding.failure.as_str(),
+                            escape(&finding.to_string())
+                        )?;
+                    }
+                    writeln!(xml, "    </testcase>")?;
+                }
+            }
+            writeln!(xml, "  </testsuite>")?;
+        }
+        writeln!(xml, "</testsuites>")
+    }
+
+    fn run_tests(&self, run: &NelsonRun) -> usize {
+        if run.error.is_some() {
+            1
+        } else {
+            self.keys.len()
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Matrix, NelsonFailure, NelsonKind};
+
+    #[test]
+    fn nelson_report() {
+        let layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        let (rows, cols) = layout
+            .layout()
+            .values()
+            .fold((0, 0), |(rows, cols), (r, c)| {
+                (rows.max(*r as usize + 1), cols.max(*c as usize + 1))
+            });
+        let empty = || {
+            Matrix::new(
+                rows,
+                cols,
+                vec![0; (rows * cols + 7) / 8].into_boxed_slice(),
+            )
+        };
+
+        let esc = layout.layout()["K00"];
+        let mut missing = empty();
+        missing.set(esc.0 as usize, esc.1 as usize, true);
+        let nelson = Nelson {
+            missing,
+            bouncing: empty(),
+            sticking: empty(),
+        };
+
+        let findings = nelson.findings(&layout);
+        assert_eq!(
+            findings,
+            vec![NelsonFinding {
+                logical_name: "K00".to_string(),
+                physical_name: "Esc".to_string(),
+                electrical: esc,
+                failure: NelsonFailure::Missing,
+            }]
+        );
+
+        let mut report = NelsonReport::new("system76/launch_2", "0.19.12", &layout);
+        let config = NelsonConfig::from(NelsonKind::Normal);
+        report.push(&config, &layout, Ok(&nelson), Duration::from_secs(1));
+        report.push(&config, &layout, Err("Failed <to> close"), Duration::ZERO);
+        assert!(!report.success());
+
+        let mut json = Vec::new();
+        report.to_json_writer_pretty(&mut json).unwrap();
+        let parsed: NelsonReport = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed, report);
+
+        let mut xml = Vec::new();
+        report.to_junit_writer(&mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        let keys = report.keys.len();
+        assert!(xml.contains(&format!(
+            r#"<testsuites name="Nelson system76/launch_2" tests="{}" failures="1" errors="1""#,
+            keys + 1
+        )));
+        assert!(xml.contains(r#"<failure type="missing" message="Esc (K00, "#));
+        assert!(xml.contains(r#"<error message="Failed &lt;to&gt; close"/>"#));
+        assert_eq!(xml.matches("<testcase ").count(), keys + 1);
+    }
+}
@@ -0,0 +1,735 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::{fl, layer_label, KeyMap, Layout, LT_RE, MT_RE};
+
+/// QMK keycode names, without their `KC_` or `QK_` prefix, that are named differently here
+///
+/// This is the `QMK_MAPPING` table of `layouts.py`, which applies it when generating `qmk.json`.
+const QMK_MAPPING: &[(&str, &str)] = &[
+    ("APPLICATION", "APP"),
+    ("AUDIO_MUTE", "MUTE"),
+    ("AUDIO_VOL_DOWN", "VOLUME_DOWN"),
+    ("AUDIO_VOL_UP", "VOLUME_UP"),
+    ("AGIN", "AGAIN"),
+    ("VOLD", "VOLUME_DOWN"),
+    ("VOLU", "VOLUME_UP"),
+    ("BSLASH", "BACKSLASH"),
+    ("BSLS", "BACKSLASH"),
+    ("SLSH", "SLASH"),
+    ("BSPACE", "BKSP"),
+    ("BACKSPACE", "BKSP"),
+    ("BSPC", "BKSP"),
+    ("BOOT", "RESET"),
+    ("BOOTLOADER", "RESET"),
+    ("SPC", "SPACE"),
+    ("SLCT", "SELECT"),
+    ("CAPSLOCK", "CAPS"),
+    ("CAPS_LOCK", "CAPS"),
+    ("LCAP", "LOCKING_CAPS_LOCK"),
+    ("LEAD", "LEADER"),
+    ("DELETE", "DEL"),
+    ("DOT", "PERIOD"),
+    ("EQUAL", "EQUALS"),
+    ("EQL", "EQUALS"),
+    ("ESCAPE", "ESC"),
+    ("EXSL", "EXSEL"),
+    ("GRAVE", "TICK"),
+    ("GESC", "GRAVE_ESCAPE"),
+    ("GRV", "TICK"),
+    ("KP_0", "NUM_0"),
+    ("KP_1", "NUM_1"),
+    ("KP_2", "NUM_2"),
+    ("KP_3", "NUM_3"),
+    ("KP_4", "NUM_4"),
+    ("KP_5", "NUM_5"),
+    ("KP_6", "NUM_6"),
+    ("KP_7", "NUM_7"),
+    ("KP_8", "NUM_8"),
+    ("KP_9", "NUM_9"),
+    ("P0", "NUM_0"),
+    ("P1", "NUM_1"),
+    ("P2", "NUM_2"),
+    ("P3", "NUM_3"),
+    ("P4", "NUM_4"),
+    ("P5", "NUM_5"),
+    ("P6", "NUM_6"),
+    ("P7", "NUM_7"),
+    ("P8", "NUM_8"),
+    ("P9", "NUM_9"),
+    ("KP_ASTERISK", "NUM_ASTERISK"),
+    ("PAST", "NUM_ASTERISK"),
+    ("INS", "INSERT"),
+    ("KP_COMMA", "NUM_COMMA"),
+    ("PCMM", "NUM_COMMA"),
+    ("COMM", "COMMA"),
+    ("CNCL", "CANCEL"),
+    ("CLR", "CLEAR"),
+    ("CLAG", "CLEAR_AGAIN"),
+    ("CRSL", "CRSEL"),
+    ("CALC", "CALCULATOR"),
+    ("KP_DOT", "NUM_PERIOD"),
+    ("PDOT", "NUM_PERIOD"),
+    ("KP_ENTER", "NUM_ENTER"),
+    ("ENT", "ENTER"),
+    ("EXEC", "EXECUTE"),
+    ("PENT", "NUM_ENTER"),
+    ("KP_EQUAL", "NUM_EQUALS"),
+    ("PEQL", "NUM_EQUALS"),
+    ("KP_MINUS", "NUM_MINUS"),
+    ("PMNS", "NUM_MINUS"),
+    ("MINS", "MINUS"),
+    ("KP_PLUS", "NUM_PLUS"),
+    ("PPLS", "NUM_PLUS"),
+    ("KP_SLASH", "NUM_SLASH"),
+    ("PSLS", "NUM_SLASH"),
+    ("LALT", "LEFT_ALT"),
+    ("ALGL", "LEFT_ALT"),
+    ("LOPT", "LEFT_ALT"),
+    ("LBRACKET", "BRACE_OPEN"),
+    ("LEFT_BRACKET", "BRACE_OPEN"),
+    ("LBRC", "BRACE_OPEN"),
+    ("LCTRL", "LEFT_CTRL"),
+    ("LCTL", "LEFT_CTRL"),
+    ("LGUI", "LEFT_SUPER"),
+    ("LWIN", "LEFT_SUPER"),
+    ("LCMD", "LEFT_SUPER"),
+    ("LEFT_GUI", "LEFT_SUPER"),
+    ("LSHIFT", "LEFT_SHIFT"),
+    ("LSFT", "LEFT_SHIFT"),
+    ("NO", "NONE"),
+    ("BASIC", "NONE"),
+    ("MEDIA_NEXT_TRACK", "MEDIA_NEXT"),
+    ("MNXT", "MEDIA_NEXT"),
+    ("MEDIA_PLAY_PAUSE", "PLAY_PAUSE"),
+    ("MPLY", "PLAY_PAUSE"),
+    ("MFFD", "MEDIA_FAST_FORWARD"),
+    ("MRWD", "MEDIA_REWIND"),
+    ("PAUS", "PAUSE"),
+    ("BRK", "PAUSE"),
+    ("BRMU", "PAUSE"),
+    ("ERAS", "ALTERNATE_ERASE"),
+    ("ASST", "ASSISTANT"),
+    ("MEDIA_PREV_TRACK", "MEDIA_PREV"),
+    ("MPRV", "MEDIA_PREV"),
+    ("MSTP", "MEDIA_STOP"),
+    ("MSEL", "MEDIA_SELECT"),
+    ("MS_U", "MS_UP"),
+    ("MS_D", "MS_DOWN"),
+    ("MS_L", "MS_LEFT"),
+    ("MS_R", "MS_RIGHT"),
+    ("EJCT", "MEDIA_EJECT"),
+    ("MYCM", "MY_COMPUTER"),
+    ("COMPUTER", "MY_COMPUTER"),
+    ("CPNL", "CONTROL_PANEL"),
+    ("WSCH", "WWW_SEARCH"),
+    ("WHOM", "WWW_HOME"),
+    ("WBAK", "WWW_BACK"),
+    ("WFWD", "WWW_FORWARD"),
+    ("WSTP", "WWW_STOP"),
+    ("WREF", "WWW_REFRESH"),
+    ("WFAV", "WWW_FAVORITES"),
+    ("WH_U", "MS_WH_UP"),
+    ("WH_D", "MS_WH_DOWN"),
+    ("WH_L", "MS_WH_LEFT"),
+    ("WH_R", "MS_WH_RIGHT"),
+    ("ACL0", "MS_ACCEL0"),
+    ("ACL1", "MS_ACCEL1"),
+    ("ACL2", "MS_ACCEL2"),
+    ("NUMLOCK", "NUM_LOCK"),
+    ("NUM", "NUM_LOCK"),
+    ("LNUM", "LOCKING_NUM_LOCK"),
+    ("NUHS", "NONUS_HASH"),
+    ("NUBS", "NONUS_BACKSLASH"),
+    ("PGDOWN", "PGDN"),
+    ("PAGE_DOWN", "PGDN"),
+    ("PAGE_UP", "PGUP"),
+    ("PSCREEN", "PRINT_SCREEN"),
+    ("PSCR", "PRINT_SCREEN"),
+    ("PRIR", "PRIOR"),
+    ("PWR", "SYSTEM_POWER"),
+    ("INTERNATIONAL_1", "INT1"),
+    ("INTERNATIONAL_2", "INT2"),
+    ("INTERNATIONAL_3", "INT3"),
+    ("INTERNATIONAL_4", "INT4"),
+    ("INTERNATIONAL_5", "INT5"),
+    ("INTERNATIONAL_6", "INT6"),
+    ("INTERNATIONAL_7", "INT7"),
+    ("INTERNATIONAL_8", "INT8"),
+    ("INTERNATIONAL_9", "INT9"),
+    ("LNG1", "LANGUAGE_1"),
+    ("LNG2", "LANGUAGE_2"),
+    ("LNG3", "LANGUAGE_3"),
+    ("LNG4", "LANGUAGE_4"),
+    ("LNG5", "LANGUAGE_5"),
+    ("LNG6", "LANGUAGE_6"),
+    ("LNG7", "LANGUAGE_7"),
+    ("LNG8", "LANGUAGE_8"),
+    ("LNG9", "LANGUAGE_9"),
+    ("PSTE", "PASTE"),
+    ("PROGRAMMABLE_BUTTON_1", "PROGRAMMABLE_BUTTON"),
+    ("QUOT", "QUOTE"),
+    ("QUANTUM", "RESET"),
+    ("RALT", "RIGHT_ALT"),
+    ("ALGR", "RIGHT_ALT"),
+    ("ROPT", "RIGHT_ALT"),
+    ("RBRACKET", "BRACE_CLOSE"),
+    ("RIGHT_BRACKET", "BRACE_CLOSE"),
+    ("RBRC", "BRACE_CLOSE"),
+    ("RCTRL", "RIGHT_CTRL"),
+    ("RCTL", "RIGHT_CTRL"),
+    ("RGB_TOG", "KBD_TOGGLE"),
+    ("RGB_VAD", "KBD_DOWN"),
+    ("RGB_VAI", "KBD_UP"),
+    ("RGB_MOD", "RGB_MODE_FORWARD"),
+    ("RGB_RMOD", "RGB_MODE_REVERSE"),
+    ("RGB_M_P", "RGB_MODE_PLAIN"),
+    ("RGB_M_B", "RGB_MODE_BREATHE"),
+    ("RGB_M_R", "RGB_MODE_RAINBOW"),
+    ("RGB_M_SW", "RGB_MODE_SWIRL"),
+    ("RGB_M_SN", "RGB_MODE_SNAKE"),
+    ("RGB_M_K", "RGB_MODE_KNIGHT"),
+    ("RGB_M_X", "RGB_MODE_XMAS"),
+    ("RGB_M_G", "RGB_MODE_GRADIENT"),
+    ("RGB_M_T", "RGB_MODE_RGBTEST"),
+    ("RGB_MODE_TEST", "RGB_MODE_RGBTEST"),
+    ("RGB_M_TW", "RGB_MODE_TWINKLE"),
+    ("LIGHTING", "BACKLIGHT_ON"),
+    ("BRIU", "BRIGHTNESS_UP"),
+    ("BRID", "BRIGHTNESS_DOWN"),
+    ("RGHT", "RIGHT"),
+    ("RGUI", "RIGHT_SUPER"),
+    ("RWIN", "RIGHT_SUPER"),
+    ("RCMD", "RIGHT_SUPER"),
+    ("RIGHT_GUI", "RIGHT_SUPER"),
+    ("RSHIFT", "RIGHT_SHIFT"),
+    ("RSFT", "RIGHT_SHIFT"),
+    ("RETN", "RETURN"),
+    ("RBT", "REBOOT"),
+    ("SCOLON", "SEMICOLON"),
+    ("SCLN", "SEMICOLON"),
+    ("SCROLLLOCK", "SCROLL_LOCK"),
+    ("LSCR", "LOCKING_SCROLL_LOCK"),
+    ("SCRL", "SCROLL_LOCK"),
+    ("BRMD", "SCROLL_LOCK"),
+    ("JOYSTICK_BUTTON_0", "JOYSTICK"),
+    ("BTN1", "MS_BTN1"),
+    ("BTN2", "MS_BTN2"),
+    ("BTN3", "MS_BTN3"),
+    ("BTN4", "MS_BTN4"),
+    ("BTN5", "MS_BTN5"),
+    ("BTN6", "MS_BTN6"),
+    ("BTN7", "MS_BTN7"),
+    ("BTN8", "MS_BTN8"),
+    ("MIDI", "MIDI_ON"),
+    ("AUDIO", "AUDIO_ON"),
+    ("MACRO", "MACRO_0"),
+    ("SYSTEM_SLEEP", "SUSPEND"),
+    ("SLEP", "SUSPEND"),
+    ("SYRQ", "SYSTEM_REQUEST"),
+    ("WAKE", "SYSTEM_WAKE"),
+    ("SEPR", "SEPARATOR"),
+    ("TRANSPARENT", "ROLL_OVER"),
+    ("TRNS", "ROLL_OVER"),
+    ("TG(0)", "LAYER_TOGGLE_1"),
+    ("TOGGLE_LAYER", "LAYER_TOGGLE_1"),
+    ("TG(1)", "LAYER_TOGGLE_2"),
+    ("TG(2)", "LAYER_TOGGLE_3"),
+    ("TG(3)", "LAYER_TOGGLE_4"),
+    ("TO(0)", "LAYER_SWITCH_1"),
+    ("TO", "LAYER_SWITCH_1"),
+    ("TO(1)", "LAYER_SWITCH_2"),
+    ("TO(2)", "LAYER_SWITCH_3"),
+    ("TO(3)", "LAYER_SWITCH_4"),
+    ("MO(0)", "LAYER_ACCESS_1"),
+    ("MOMENTARY", "LAYER_ACCESS_1"),
+    ("MO(1)", "FN"),
+    ("MO(2)", "LAYER_ACCESS_3"),
+    ("MO(3)", "LAYER_ACCESS_4"),
+    ("_______", "ROLL_OVER"),
+];
+
+static QMK_NAMES: Lazy<HashMap<&str, &str>> = Lazy::new(|| QMK_MAPPING.iter().copied().collect());
+
+/// Preferred QMK alias of each name: the shortest, or the first listed of equal length
+static QMK_ALIASES: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
+    let mut aliases = HashMap::<&str, &str>::new();
+    for (alias, name) in QMK_MAPPING {
+        if alias.contains('(') || alias.starts_with('_') {
+            continue;
+        }
+        let preferred = aliases.entry(name).or_insert(alias);
+        if alias.len() < preferred.len() {
+            *preferred = alias;
+        }
+    }
+    aliases
+});
+
+/// Mod-tap modifiers, as named here and in QMK
+const QMK_MODS: &[(&str, &str)] = &[
+    ("LEFT_CTRL", "MOD_LCTL"),
+    ("LEFT_SHIFT", "MOD_LSFT"),
+    ("LEFT_ALT", "MOD_LALT"),
+    ("LEFT_SUPER", "MOD_LGUI"),
+    ("RIGHT_CTRL", "MOD_RCTL"),
+    ("RIGHT_SHIFT", "MOD_RSFT"),
+    ("RIGHT_ALT", "MOD_RALT"),
+    ("RIGHT_SUPER", "MOD_RGUI"),
+];
+
+/// Name used in keymaps here for a QMK keycode, like `BKSP` for `KC_BSPC`
+///
+/// Keycodes without an alias are returned without their `KC_` or `QK_` prefix. This doesn't check
+/// that the result is a valid name.
+pub fn qmk_keycode_name(keycode: &str) -> String {
+    let keycode = keycode.trim();
+    if keycode == "XXXXXXX" {
+        return "NONE".to_string();
+    }
+    let name = keycode
+        .strip_prefix("KC_")
+        .or_else(|| keycode.strip_prefix("QK_"))
+        .unwrap_or(keycode);
+    QMK_NAMES.get(name).unwrap_or(&name).to_string()
+}
+
+/// QMK function-style keycode, like `MO(1)`, for a layer key
+fn qmk_layer_keycode(name: &str) -> Option<String> {
+    if name == "FN" {
+        return Some("MO(1)".to_string());
+    }
+    for (prefix, function) in [
+        ("LAYER_ACCESS_", "MO"),
+        ("LAYER_TOGGLE_", "TG"),
+        ("LAYER_SWITCH_", "TO"),
+    ] {
+        if let Some(num) = name.strip_prefix(prefix) {
+            // Layers are numbered from 0 in QMK
+            let layer = num.parse::<u8>().ok()?.checked_sub(1)?;
+            return Some(format!("{}({})", function, layer));
+        }
+    }
+    None
+}
+
+impl Layout {
+    /// QMK keycode for a name accepted by `scancode_from_name`, like `KC_BSPC` for `BKSP`
+    ///
+    /// Only meaningful for QMK layouts, where the name's scancode determines its prefix.
+    pub fn qmk_keycode(&self, name: &str) -> Option<String> {
+        let scancode = self.scancode_from_name(name)?;
+        let name = self.scancode_to_name(scancode)?;
+        if let Some(captures) = MT_RE.captures(&name) {
+            let mod_ = QMK_MODS.iter().find(|(x, _)| *x == &captures[1])?.1;
+            return Some(format!("MT({}, {})", mod_, self.qmk_keycode(&captures[2])?));
+        }
+        if let Some(captures) = LT_RE.captures(&name) {
+            let keycode = self.qmk_keycode(&captures[2])?;
+            return Some(format!("LT({}, {})", &captures[1], keycode));
+        }
+        match name.as_str() {
+            "ROLL_OVER" => return Some("KC_TRNS".to_string()),
+            "NONE" => return Some("KC_NO".to_string()),
+            "RESET" => return Some("QK_BOOT".to_string()),
+            _ => {}
+        }
+        if let Some(keycode) = qmk_layer_keycode(&name) {
+            return Some(keycode);
+        }
+
+        let alias = QMK_ALIASES.get(name.as_str()).copied().unwrap_or(&name);
+        // Basic keycodes are `KC_`, and lighting ones have no prefix
+        let prefix = if scancode <= 0xFF {
+            "KC_"
+        } else if alias.starts_with("RGB_") {
+            ""
+        } else {
+            "QK_"
+        };
+        Some(format!("{}{}", prefix, alias))
+    }
+}
+
+/// Binding of a `QmkKeymap` that `QmkKeymap::to_keymap` can't translate exactly
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QmkKeymapIssue {
+    /// Keymap names a different keyboard than the board
+    Keyboard { keyboard: String, model: String },
+    /// Layer has more or fewer keys than the layout
+    KeyCount {
+        layer: usize,
+        keys: usize,
+        expected: usize,
+    },
+    /// Keymap has more layers than the keyboard
+    ExtraLayers { layers: usize, num_layers: u8 },
+    /// Keycode has no equivalent here
+    UnknownKeycode {
+        key: String,
+        layer: usize,
+        keycode: String,
+    },
+}
+
+impl fmt::Display for QmkKeymapIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            Self::Keyboard { keyboard, model } => fl!(
+                "qmk-issue-keyboard",
+                keyboard = keyboard.as_str(),
+                model = model.as_str()
+            ),
+            Self::KeyCount {
+                layer,
+                keys,
+                expected,
+            } => fl!(
+                "qmk-issue-key-count",
+                layer = layer_label(*layer),
+                keys = keys.to_string(),
+                expected = expected.to_string()
+            ),
+            Self::ExtraLayers { layers, num_layers } => fl!(
+                "qmk-issue-extra-layers",
+                layers = layers.to_string(),
+                num_layers = num_layers.to_string()
+            ),
+            Self::UnknownKeycode {
+                key,
+                layer,
+                keycode,
+            } => fl!(
+                "qmk-issue-unknown-keycode",
+                key = key.as_str(),
+                layer = layer_label(*layer),
+                keycode = keycode.as_str()
+            ),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// QMK keymap, as in the `keymap.json` of QMK Configurator or `qmk c2json`
+///
+/// Each layer lists keycodes in the order of the keyboard's layout macro.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QmkKeymap {
+    #[serde(default)]
+    pub keyboard: String,
+    #[serde(default)]
+    pub keymap: String,
+    /// Layout macro, like `LAYOUT`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    pub layers: Vec<Vec<String>>,
+}
+
+impl QmkKeymap {
+    /// Translate `keymap` to QMK keycodes, with keys in the order of `physical.json`
+    ///
+    /// Keys missing from `keymap` are `KC_NO`. Bindings without a QMK keycode are an error, as
+    /// is firmware older than QMK 0.19, which doesn't have the keycode names used here.
+    pub fn from_keymap(keymap: &KeyMap, layout: &Layout) -> Result<Self, String> {
+        if !layout.meta.is_qmk {
+            return Err(format!("{} does not use QMK firmware", keymap.model));
+        }
+        if layout.use_legacy_scancodes {
+            return Err(format!(
+                "{} has firmware older than QMK 0.19, which can't be exported",
+                keymap.model
+            ));
+        }
+
+        let mut layers = vec![Vec::new(); usize::from(layout.meta.num_layers)];
+        let mut unknown = Vec::new();
+        for key in &layout.physical.keys {
+            let logical_name = key.logical_name();
+            let scancodes = keymap.map.get(&logical_name);
+            for (layer, keycodes) in layers.iter_mut().enumerate() {
+                let scancode_name = match scancodes.and_then(|x| x.get(layer)) {
+                    Some(scancode_name) => scancode_name,
+                    None => {
+                        keycodes.push("KC_NO".to_string());
+                        continue;
+                    }
+                };
+                match layout.qmk_keycode(scancode_name) {
+                    Some(keycode) => keycodes.push(keycode),
+                    None => unknown.push(format!(
+                        "{} on layer {} ({})",
+                        logical_name,
+                        layer_label(layer),
+                        scancode_name
+                    )),
+                }
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(format!("No QMK keycode for {}", unknown.join(", ")));
+        }
+
+        Ok(Self {
+            keyboard: keymap.model.clone(),
+            keymap: "configurator".to_string(),
+            layout: Some("LAYOUT".to_string()),
+            layers,
+        })
+    }
+
+    /// Translate to a keymap for `layout`, matching keycodes to keys in the order of
+    /// `physical.json`
+    ///
+    /// Keycodes that don't fit are reported, and replaced by `NONE`, or `ROLL_OVER` above the
+    /// first layer if missing. The keymap has no LED settings, so importing it only changes
+    /// bindings.
+    pub fn to_keymap(&self, layout: &Layout) -> (KeyMap, Vec<QmkKeymapIssue>) {
+        let num_layers = layout.meta.num_layers;
+        let keys = &layout.physical.keys;
+        let mut issues = Vec::new();
+
+        // QMK keyboard names may add a revision, like `system76/launch_2/rev1`
+        let model = &layout.default.model;
+        if !self.keyboard.is_empty()
+            && self.keyboard != *model
+            && !self.keyboard.starts_with(&format!("{}/", model))
+        {
+            issues.push(QmkKeymapIssue::Keyboard {
+                keyboard: self.keyboard.clone(),
+                model: model.clone(),
+            });
+        }
+        if self.layers.len() > usize::from(num_layers) {
+            issues.push(QmkKeymapIssue::ExtraLayers {
+                layers: self.layers.len(),
+                num_layers,
+            });
+        }
+        for (layer, keycodes) in self.layers.iter().enumerate() {
+            if layer < usize::from(num_layers) && keycodes.len() != keys.len() {
+                issues.push(QmkKeymapIssue::KeyCount {
+                    layer,
+                    keys: keycodes.len(),
+                    expected: keys.len(),
+                });
+            }
+        }
+
+        let mut map = BTreeMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            let logical_name = key.logical_name();
+            let mut scancodes = Vec::new();
+            for layer in 0..usize::from(num_layers) {
+                let keycode = match self.layers.get(layer).and_then(|x| x.get(i)) {
+                    Some(keycode) => keycode,
+                    None if layer == 0 => {
+                        scancodes.push("NONE".to_string());
+                        continue;
+                    }
+                    None => {
+                        scancodes.push("ROLL_OVER".to_string());
+                        continue;
+                    }
+                };
+                match layout.canonical_scancode_name(keycode) {
+                    Some(scancode_name) => scancodes.push(scancode_name),
+                    None => {
+                        issues.push(QmkKeymapIssue::UnknownKeycode {
+                            key: logical_name.clone(),
+                            layer,
+                            keycode: keycode.clone(),
+                        });
+                        scancodes.push("NONE".to_string());
+                    }
+                }
+            }
+            map.insert(logical_name, scancodes);
+        }
+
+        let keymap = KeyMap {
+            model: layout.default.model.clone(),
+            version: 1,
+            map,
+            key_leds: BTreeMap::new(),
+            layers: Vec::new(),
+        };
+        (keymap, issues)
+    }
+
+    /// Write as `keymap.json`, pretty printed
+    pub fn to_json_pretty(&self) -> String {
+        let mut json = serde_json::to_string_pretty(self).unwrap();
+        json.push('\n');
+        json
+    }
+
+    /// Generate a `keymap.c`, with a line for each row of keys in `layout`
+    pub fn to_keymap_c(&self, layout: &Layout) -> String {
+        let layout_macro = self.layout.as_deref().unwrap_or("LAYOUT");
+        let mut c = String::new();
+        c.push_str("#include QMK_KEYBOARD_H\n\n");
+        c.push_str("const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {\n");
+        for (layer, keycodes) in self.layers.iter().enumerate() {
+            let mut rows = Vec::<Vec<&str>>::new();
+            let mut row = None;
+            for (key, keycode) in layout.physical.keys.iter().zip(keycodes) {
+                if row != Some(key.logical.0) {
+                    row = Some(key.logical.0);
+                    rows.push(Vec::new());
+                }
+                rows.last_mut().unwrap().push(keycode);
+            }
+            let rows = rows
+                .iter()
+                .map(|x| format!("        {}", x.join(", ")))
+                .collect::<Vec<_>>();
+            c.push_str(&format!(
+                "    [{}] = {}(\n{}\n    ),\n",
+                layer,
+                layout_macro,
+                rows.join(",\n")
+            ));
+        }
+        c.push_str("};\n");
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qmk_keycode_names() {
+        assert_eq!(qmk_keycode_name("KC_BSPC"), "BKSP");
+        assert_eq!(qmk_keycode_name("KC_GRV"), "TICK");
+        assert_eq!(qmk_keycode_name("KC_A"), "A");
+        assert_eq!(qmk_keycode_name("KC_ENTER"), "ENTER");
+        assert_eq!(qmk_keycode_name("QK_BOOT"), "RESET");
+        assert_eq!(qmk_keycode_name("_______"), "ROLL_OVER");
+        assert_eq!(qmk_keycode_name("XXXXXXX"), "NONE");
+        assert_eq!(qmk_keycode_name("MO(1)"), "FN");
+        assert_eq!(qmk_keycode_name("VOLUME_UP"), "VOLUME_UP");
+    }
+
+    #[test]
+    fn qmk_keymap_export() {
+        let layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        let keycode = |name| layout.qmk_keycode(name);
+        assert_eq!(keycode("BKSP").as_deref(), Some("KC_BSPC"));
+        assert_eq!(keycode("TICK").as_deref(), Some("KC_GRV"));
+        assert_eq!(keycode("ENTER").as_deref(), Some("KC_ENT"));
+        assert_eq!(keycode("LEFT_CTRL").as_deref(), Some("KC_LCTL"));
+        assert_eq!(keycode("SCROLL_LOCK").as_deref(), Some("KC_SCRL"));
+        assert_eq!(keycode("ROLL_OVER").as_deref(), Some("KC_TRNS"));
+        assert_eq!(keycode("RESET").as_deref(), Some("QK_BOOT"));
+        assert_eq!(keycode("FN").as_deref(), Some("MO(1)"));
+        assert_eq!(keycode("LAYER_SWITCH_1").as_deref(), Some("TO(0)"));
+        assert_eq!(keycode("KBD_TOGGLE").as_deref(), Some("RGB_TOG"));
+        assert_eq!(keycode("RGB_HUI").as_deref(), Some("RGB_HUI"));
+        assert_eq!(keycode("GRAVE_ESCAPE").as_deref(), Some("QK_GESC"));
+        assert_eq!(
+            keycode("MT(LEFT_SHIFT, A)").as_deref(),
+            Some("MT(MOD_LSFT, KC_A)")
+        );
+        assert_eq!(keycode("NOT_A_KEY"), None);
+
+        let qmk = QmkKeymap::from_keymap(&layout.default, &layout).unwrap();
+        assert_eq!(qmk.layers.len(), 4);
+        assert_eq!(qmk.layers[0][0], "KC_ESCAPE");
+        assert_eq!(qmk.layers[1][0], "QK_BOOT");
+        assert_eq!(qmk.layers[2][0], "KC_TRNS");
+
+        // Every keycode exported parses back to the same binding
+        for (key, keycodes) in layout.physical.keys.iter().zip(&qmk.layers[0]) {
+            assert_eq!(
+                layout.canonical_scancode_name(keycodes),
+                Some(layout.default.map[&key.logical_name()][0].clone()),
+                "{}",
+                keycodes
+            );
+        }
+
+        let keymap_c = qmk.to_keymap_c(&layout);
+        assert!(keymap_c.contains("    [0] = LAYOUT(\n        KC_ESCAPE, KC_F1, "));
+        assert_eq!(keymap_c.matches("LAYOUT(").count(), 4);
+
+        let mut keymap = layout.default.clone();
+        keymap.map.get_mut("K00").unwrap()[0] = "NOT_A_KEY".to_string();
+        let err = QmkKeymap::from_keymap(&keymap, &layout).unwrap_err();
+        assert_eq!(err, "No QMK keycode for K00 on layer 1 (NOT_A_KEY)");
+
+        let layout = Layout::from_board("system76/launch_2", "0.7.103").unwrap();
+        let err = QmkKeymap::from_keymap(&layout.default, &layout).unwrap_err();
+        assert!(err.contains("older than QMK 0.19"), "{}", err);
+
+        let layout = Layout::from_board("system76/darp6", "0.19.12").unwrap();
+        assert!(QmkKeymap::from_keymap(&layout.default, &layout).is_err());
+    }
+
+    #[test]
+    fn qmk_keymap_import() {
+        let layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        assert_eq!(
+            layout.qmk_keycode("LT(2, SPACE)").as_deref(),
+            Some("LT(2, KC_SPC)")
+        );
+
+        // Exported keymap imports unchanged
+        let qmk = QmkKeymap::from_keymap(&layout.default, &layout).unwrap();
+        let (keymap, issues) = qmk.to_keymap(&layout);
+        assert_eq!(issues, Vec::new());
+        assert_eq!(keymap.model, "system76/launch_2");
+        assert_eq!(keymap.map, layout.default.map);
+
+        let mut qmk: QmkKeymap = serde_json::from_str(&qmk.to_json_pretty()).unwrap();
+        qmk.layers[0][0] = "MT(MOD_LCTL, KC_ESC)".to_string();
+        qmk.layers[0][1] = "LT(1, KC_F1)".to_string();
+        qmk.layers[0][2] = "LCTL(KC_C)".to_string();
+        qmk.layers[0][3] = "LT(1, QK_BOOT)".to_string();
+        qmk.layers[1][0] = "TG(2)".to_string();
+        qmk.layers[1].pop();
+        qmk.layers.truncate(2);
+        qmk.keyboard = "acme/macropad".to_string();
+        let (keymap, issues) = qmk.to_keymap(&layout);
+        assert_eq!(
+            keymap.map["K00"],
+            vec![
+                "MT(LEFT_CTRL, ESC)",
+                "LAYER_TOGGLE_3",
+                "ROLL_OVER",
+                "ROLL_OVER"
+            ]
+        );
+        assert_eq!(keymap.map["K01"][0], "LT(1, F1)");
+        assert_eq!(keymap.map["K02"][0], "NONE");
+        assert_eq!(
+            issues,
+            vec![
+                QmkKeymapIssue::Keyboard {
+                    keyboard: "acme/macropad".to_string(),
+                    model: "system76/launch_2".to_string()
+                },
+                QmkKeymapIssue::KeyCount {
+                    layer: 1,
+                    keys: qmk.layers[0].len() - 1,
+                    expected: qmk.layers[0].len()
+                },
+                QmkKeymapIssue::UnknownKeycode {
+                    key: "K02".to_string(),
+                    layer: 0,
+                    keycode: "LCTL(KC_C)".to_string()
+                },
+                QmkKeymapIssue::UnknownKeycode {
+                    key: "K03".to_string(),
+                    layer: 0,
+                    keycode: "LT(1, QK_BOOT)".to_string()
+                },
+            ]
+        );
+    }
+}
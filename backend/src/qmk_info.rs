@@ -0,0 +1,413 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use crate::{layer_label, qmk_keycode_name, Hs, KeyMap, KeyMapLayer, PhysicalLayout, QmkKeymap};
+
+fn one() -> f64 {
+    1.0
+}
+
+/// Subset of a QMK keyboard's `info.json`
+#[derive(Deserialize)]
+struct QmkInfo {
+    keyboard_name: Option<String>,
+    manufacturer: Option<String>,
+    layouts: BTreeMap<String, QmkLayout>,
+    #[serde(default)]
+    layout_aliases: HashMap<String, String>,
+    #[serde(default)]
+    features: HashMap<String, bool>,
+    rgb_matrix: Option<QmkRgbMatrix>,
+}
+
+#[derive(Deserialize)]
+struct QmkLayout {
+    layout: Vec<QmkKey>,
+}
+
+#[derive(Deserialize)]
+struct QmkKey {
+    matrix: (u8, u8),
+    x: f64,
+    y: f64,
+    #[serde(default = "one")]
+    w: f64,
+    #[serde(default = "one")]
+    h: f64,
+    #[serde(default)]
+    r: f64,
+    #[serde(default)]
+    rx: f64,
+    #[serde(default)]
+    ry: f64,
+    label: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QmkRgbMatrix {
+    #[serde(default)]
+    layout: Vec<QmkLed>,
+}
+
+#[derive(Deserialize)]
+struct QmkLed {
+    matrix: Option<(u8, u8)>,
+}
+
+/// Data files of a layout, as read by `Layout::from_dir`
+#[derive(Clone, Debug)]
+pub struct LayoutFiles {
+    /// Board name, like `acme/custom`
+    pub board: String,
+    /// Keyboard name in `meta.json`, which is the same as `board` for imported layouts
+    pub keyboard: String,
+    pub meta_json: String,
+    pub default_json: String,
+    pub layout_json: String,
+    pub leds_json: String,
+    pub physical_json: String,
+}
+
+impl LayoutFiles {
+    /// Write files to `dir`, structured like the `layouts` directory of this repository
+    pub fn write_dir<P: AsRef<Path>>(&self, dir: P) -> Result<(), String> {
+        let board_dir = dir.as_ref().join(&self.board);
+        let keyboard_dir = dir.as_ref().join("keyboards").join(&self.keyboard);
+        let files = [
+            (&board_dir, "meta.json", &self.meta_json),
+            (&board_dir, "default.json", &self.default_json),
+            (&keyboard_dir, "layout.json", &self.layout_json),
+            (&keyboard_dir, "leds.json", &self.leds_json),
+            (&keyboard_dir, "physical.json", &self.physical_json),
+        ];
+        for (dir, name, data) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(dir)
+                .and_then(|_| fs::write(&path, data))
+                .map_err(|err| format!("Failed to write '{}': {}", path.display(), err))?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn to_json<T: serde::Serialize>(value: &T) -> String {
+    let mut json = serde_json::to_string_pretty(value).unwrap();
+    json.push('\n');
+    json
+}
+
+/// Generate a KLE layout placing `keys` at the positions given in `info.json`
+///
+/// Each run of keys at the same height and rotation becomes a row. Offsets are relative to where
+/// `PhysicalLayout` would place the key otherwise.
+fn kle_from_keys(name: &str, author: &str, keys: &[QmkKey]) -> Value {
+    let mut rows = vec![json!({ "name": name, "author": author })];
+    let mut row = Vec::new();
+    let mut row_y = None;
+    let mut rotation = (0.0, 0.0, 0.0);
+    let mut x = 0.0;
+    let mut y = 0.0;
+
+    for key in keys {
+        let mut meta = Map::new();
+        let key_rotation = (key.r, key.rx, key.ry);
+        if row_y != Some(key.y) || key_rotation != rotation || key.x < x {
+            if row_y.is_some() {
+                rows.push(Value::Array(std::mem::take(&mut row)));
+                x = rotation.1;
+                y += 1.0;
+            }
+            if key_rotation != rotation {
+                rotation = key_rotation;
+                meta.insert("r".to_string(), json!(key.r));
+                meta.insert("rx".to_string(), json!(key.rx));
+                meta.insert("ry".to_string(), json!(key.ry));
+                x = key.rx;
+                y = key.ry;
+            }
+            if key.y != y {
+                meta.insert("y".to_string(), json!(key.y - y));
+                y = key.y;
+            }
+            row_y = Some(key.y);
+        }
+        if key.x != x {
+            meta.insert("x".to_string(), json!(key.x - x));
+        }
+        if key.w != 1.0 {
+            meta.insert("w".to_string(), json!(key.w));
+        }
+        if key.h != 1.0 {
+            meta.insert("h".to_string(), json!(key.h));
+        }
+        if !meta.is_empty() {
+            row.push(Value::Object(meta));
+        }
+        row.push(json!(key.label.as_deref().unwrap_or("")));
+        x = key.x + key.w;
+    }
+    if !row.is_empty() {
+        rows.push(Value::Array(row));
+    }
+    Value::Array(rows)
+}
+
+/// Generate the layout of `board` from a QMK keyboard's `info.json` and `keymap.json`
+///
+/// The keymap's `layout`, or `LAYOUT` if it doesn't name one, selects the layout macro of
+/// `info.json`. Key positions are taken from `kle_json` if given, which must list the same keys
+/// in the same order as the layout macro, or else from `info.json`. QMK keycode aliases are
+/// translated with `qmk_keycode_name`.
+pub fn import_qmk_info(
+    board: &str,
+    info_json: &str,
+    keymap_json: &str,
+    kle_json: Option<&str>,
+) -> Result<LayoutFiles, String> {
+    let info: QmkInfo = serde_json::from_str(info_json)
+        .map_err(|err| format!("Failed to parse info.json: {}", err))?;
+    let keymap: QmkKeymap = serde_json::from_str(keymap_json)
+        .map_err(|err| format!("Failed to parse keymap.json: {}", err))?;
+
+    let layout_name = keymap.layout.as_deref().unwrap_or("LAYOUT");
+    let layout_name = info
+        .layout_aliases
+        .get(layout_name)
+        .map_or(layout_name, String::as_str);
+    let keys = match info.layouts.get(layout_name) {
+        Some(layout) => &layout.layout,
+        None if keymap.layout.is_none() && info.layouts.len() == 1 => {
+            &info.layouts.values().next().unwrap().layout
+        }
+        None => {
+            return Err(format!(
+                "No layout '{}' in info.json, which has: {}",
+                layout_name,
+                info.layouts.keys().cloned().collect::<Vec<_>>().join(", ")
+            ))
+        }
+    };
+
+    let display_name = info
+        .keyboard_name
+        .clone()
+        .unwrap_or_else(|| board.to_string());
+    let physical_json = match kle_json {
+        Some(kle_json) => kle_json.to_string(),
+        None => to_json(&kle_from_keys(
+            &display_name,
+            info.manufacturer.as_deref().unwrap_or_default(),
+            keys,
+        )),
+    };
+    let physical = PhysicalLayout::from_str(&physical_json)
+        .map_err(|err| format!("Failed to parse physical.json: {}", err))?;
+    if physical.keys.len() != keys.len() {
+        return Err(format!(
+            "physical.json has {} keys, but {} in info.json has {}",
+            physical.keys.len(),
+            layout_name,
+            keys.len()
+        ));
+    }
+    if let Some(key) = physical
+        .keys
+        .iter()
+        .find(|x| x.logical.0 >= 36 || x.logical.1 >= 36)
+    {
+        return Err(format!(
+            "Key '{}' is past the 36 rows or columns supported in physical.json",
+            key.physical_name
+        ));
+    }
+    let names = physical
+        .keys
+        .iter()
+        .map(|x| x.logical_name())
+        .collect::<Vec<_>>();
+
+    let layout = names
+        .iter()
+        .zip(keys)
+        .map(|(name, key)| (name.clone(), key.matrix))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut leds = BTreeMap::<_, Vec<u8>>::new();
+    let rgb_layout = info.rgb_matrix.iter().flat_map(|x| &x.layout);
+    for (index, led) in rgb_layout.enumerate() {
+        let position = keys.iter().position(|x| Some(x.matrix) == led.matrix);
+        if let (Some(position), Ok(index)) = (position, u8::try_from(index)) {
+            leds.entry(names[position].clone()).or_default().push(index);
+        }
+    }
+
+    if keymap.layers.is_empty() || keymap.layers.len() > usize::from(u8::MAX) {
+        return Err(format!("keymap.json has {} layers", keymap.layers.len()));
+    }
+    let mut map = BTreeMap::new();
+    for (position, name) in names.iter().enumerate() {
+        let mut scancodes = Vec::new();
+        for (layer, keycodes) in keymap.layers.iter().enumerate() {
+            let keycode = keycodes.get(position).ok_or_else(|| {
+                format!(
+                    "Layer {} of keymap.json has {} keys, but {} in info.json has {}",
+                    layer_label(layer),
+                    keycodes.len(),
+                    layout_name,
+                    keys.len()
+                )
+            })?;
+            scancodes.push(qmk_keycode_name(keycode));
+        }
+        map.insert(name.clone(), scancodes);
+    }
+
+    // Per-key LED modes and per-layer lighting need an RGB matrix, while rgblight and backlight
+    // only give color or brightness
+    let feature = |name: &str| info.features.get(name).copied().unwrap_or(false);
+    let has_mode = info.rgb_matrix.is_some() || feature("rgb_matrix");
+    let has_color = has_mode || feature("rgblight");
+    let has_brightness = has_color || feature("backlight");
+    let layers = (0..keymap.layers.len())
+        .map(|layer| KeyMapLayer {
+            mode: match (has_mode, layer) {
+                (false, _) => None,
+                (true, 0) => Some((7, 127)),
+                (true, _) => Some((13, 127)),
+            },
+            brightness: if has_mode { 176 } else { 0 },
+            color: if has_mode {
+                Hs::from_ints(142, 255)
+            } else {
+                Hs::from_ints(0, 0)
+            },
+        })
+        .collect();
+    let key_leds = if has_mode {
+        names.iter().map(|x| (x.clone(), None)).collect()
+    } else {
+        BTreeMap::new()
+    };
+    let default = KeyMap {
+        model: board.to_string(),
+        version: 1,
+        map,
+        key_leds,
+        layers,
+    };
+
+    let meta = json!({
+        "display_name": display_name,
+        "has_mode": has_mode,
+        "has_per_layer": has_mode,
+        "has_brightness": has_brightness,
+        "has_color": has_color,
+        "has_mod_tap": true,
+        "is_qmk": true,
+        "num_layers": keymap.layers.len(),
+        "pressed_color": "#202020",
+        "keyboard": board,
+    });
+
+    Ok(LayoutFiles {
+        board: board.to_string(),
+        keyboard: board.to_string(),
+        meta_json: to_json(&meta),
+        default_json: to_json(&default),
+        layout_json: to_json(&layout),
+        leds_json: to_json(&leds),
+        physical_json,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Layout, Rect};
+    use std::env;
+
+    const INFO_JSON: &str = r#"{
+        "keyboard_name": "Acme Macropad",
+        "manufacturer": "Acme",
+        "features": {"rgb_matrix": true},
+        "layouts": {
+            "LAYOUT": {"layout": [
+                {"matrix": [0, 0], "x": 0, "y": 0, "label": "Esc"},
+                {"matrix": [0, 1], "x": 1.5, "y": 0, "w": 2},
+                {"matrix": [1, 1], "x": 0, "y": 1.25, "w": 1.5},
+                {"matrix": [1, 0], "x": 2, "y": 1, "h": 2},
+                {"matrix": [2, 0], "x": 4, "y": 0, "r": 15, "rx": 4, "ry": 0}
+            ]}
+        },
+        "rgb_matrix": {"layout": [
+            {"matrix": [0, 0], "x": 0, "y": 0, "flags": 4},
+            {"x": 100, "y": 0, "flags": 2},
+            {"matrix": [1, 0], "x": 50, "y": 32, "flags": 4}
+        ]}
+    }"#;
+
+    #[test]
+    fn qmk_info_import() {
+        let keymap_json = r#"{
+            "layout": "LAYOUT",
+            "layers": [
+                ["KC_ESC", "KC_BSPC", "KC_LCTL", "MO(1)", "KC_GRV"],
+                ["QK_BOOT", "_______", "_______", "_______", "KC_VOLU"]
+            ]
+        }"#;
+        let files = import_qmk_info("acme/macropad", INFO_JSON, keymap_json, None).unwrap();
+
+        let physical = PhysicalLayout::from_str(&files.physical_json).unwrap();
+        let rects = physical.keys.iter().map(|x| x.physical).collect::<Vec<_>>();
+        assert_eq!(
+            rects,
+            vec![
+                Rect::new(0.0, 0.0, 1.0, 1.0),
+                Rect::new(1.5, 0.0, 2.0, 1.0),
+                Rect::new(0.0, -1.25, 1.5, 1.0),
+                Rect::new(2.0, -1.0, 1.0, 2.0),
+                Rect::new(4.0, 0.0, 1.0, 1.0),
+            ]
+        );
+        assert_eq!(physical.keys[4].rotation.angle, 15.0);
+
+        let dir = env::temp_dir().join(format!("qmk-info-import-{}", std::process::id()));
+        files.write_dir(&dir).unwrap();
+        let layout = Layout::from_dir("acme/macropad", &dir, "0.19.12").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(layout.lint(), Vec::new());
+        assert_eq!(layout.meta.display_name, "Acme Macropad");
+        assert_eq!(layout.meta.num_layers, 2);
+        assert_eq!(layout.layout()["K10"], (1, 1));
+        assert_eq!(layout.leds["K00"], vec![0]);
+        assert_eq!(layout.leds["K20"], vec![2]);
+        assert_eq!(layout.default.map["K00"], vec!["ESC", "RESET"]);
+        assert_eq!(layout.default.map["K01"], vec!["BKSP", "ROLL_OVER"]);
+        assert_eq!(layout.default.map["K20"], vec!["FN", "ROLL_OVER"]);
+        assert_eq!(layout.default.map["K30"], vec!["TICK", "VOLUME_UP"]);
+
+        // Positions from a KLE file, which must have the same keys
+        let kle_json = r#"[["Esc", "Bksp"], ["Ctrl", "Fn", "Tick"]]"#;
+        let files =
+            import_qmk_info("acme/macropad", INFO_JSON, keymap_json, Some(kle_json)).unwrap();
+        assert_eq!(files.physical_json, kle_json);
+        assert!(files.layout_json.contains("\"K12\""));
+        let err = import_qmk_info("acme/macropad", INFO_JSON, keymap_json, Some(r#"[["A"]]"#))
+            .unwrap_err();
+        assert!(err.contains("has 1 keys"), "{}", err);
+
+        let err = import_qmk_info(
+            "acme/macropad",
+            INFO_JSON,
+            r#"{"layout": "LAYOUT_iso", "layers": []}"#,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.contains("No layout 'LAYOUT_iso'"), "{}", err);
+    }
+}
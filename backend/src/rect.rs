@@ -1,4 +1,4 @@
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Rect {
     pub x: f64,
     pub y: f64,
@@ -16,3 +16,12 @@ impl Rect {
         (self.x..=self.x + self.w).contains(&x) && (self.y..=self.y + self.h).contains(&y)
     }
 }
+
+/// Rotation of a key about a point, in the same coordinates as its `Rect`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rotation {
+    /// Degrees clockwise, as displayed
+    pub angle: f64,
+    pub x: f64,
+    pub y: f64,
+}
@@ -0,0 +1,505 @@
+use futures_timer::Delay;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    data_dir, Backend, Benchmark, BenchmarkConfig, Board, NelsonConfig, NelsonKind, NelsonReport,
+};
+
+/// Rate the matrix is polled at during a Selma test
+const SELMA_MATRIX_GET_RATE: Duration = Duration::from_millis(50);
+
+fn default_runs() -> u32 {
+    1
+}
+
+fn default_nelson_config() -> NelsonConfig {
+    NelsonKind::Normal.into()
+}
+
+/// One test in a `TestPlan`
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TestStep {
+    /// Benchmark drives plugged into each hub port, retrying failed ports up to `runs` times
+    Benchmark {
+        #[serde(default)]
+        config: BenchmarkConfig,
+        #[serde(default = "default_runs")]
+        runs: u32,
+    },
+    /// Run the Nelson test fixture `runs` times, stopping at the first failure
+    Nelson {
+        #[serde(default = "default_nelson_config")]
+        config: NelsonConfig,
+        #[serde(default = "default_runs")]
+        runs: u32,
+    },
+    /// Fail if any key registers a press within `duration_secs`, with input disabled
+    Selma { duration_secs: u64 },
+}
+
+impl TestStep {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Benchmark { .. } => "benchmark",
+            Self::Nelson { .. } => "nelson",
+            Self::Selma { .. } => "selma",
+        }
+    }
+}
+
+/// Sequence of tests run on each unit at a test station
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TestPlan {
+    pub steps: Vec<TestStep>,
+}
+
+impl Default for TestPlan {
+    /// The tests of the launch test page: a USB test, a single Nelson run, and 100 more
+    fn default() -> Self {
+        Self {
+            steps: vec![
+                TestStep::Benchmark {
+                    config: BenchmarkConfig::default(),
+                    runs: 5,
+                },
+                TestStep::Nelson {
+                    config: default_nelson_config(),
+                    runs: 1,
+                },
+                TestStep::Nelson {
+                    config: default_nelson_config(),
+                    runs: 100,
+                },
+            ],
+        }
+    }
+}
+
+impl TestPlan {
+    pub fn from_reader<R: Read>(rdr: R) -> serde_json::Result<Self> {
+        let plan: Self = serde_json::from_reader(rdr)?;
+        for step in &plan.steps {
+            if let TestStep::Nelson { config, .. } = step {
+                config.validate().map_err(serde::de::Error::custom)?;
+            }
+        }
+        Ok(plan)
+    }
+}
+
+/// Outcome of one step of a test plan
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StepResult {
+    pub step: TestStep,
+    pub success: bool,
+    /// Lines describing what was tested, and any failures
+    pub details: Vec<String>,
+    /// Length of the step, in seconds
+    pub duration: f64,
+    /// Result of each port, for a benchmark step that ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub benchmark: Option<Benchmark>,
+    /// Runs of a Nelson step, with the keys that failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nelson: Option<NelsonReport>,
+}
+
+/// Results of running a test plan on one unit
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct UnitResult {
+    pub model: String,
+    pub version: String,
+    /// Serial number entered by the operator
+    pub serial: String,
+    /// Seconds since the Unix epoch when the test finished
+    pub timestamp: u64,
+    pub steps: Vec<StepResult>,
+}
+
+impl UnitResult {
+    pub fn success(&self) -> bool {
+        self.steps.iter().all(|x| x.success)
+    }
+
+    /// Human readable pass/fail summary
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} {} ({}): {}\n",
+            self.model,
+            self.serial,
+            self.version,
+            if self.success() { "PASS" } else { "FAIL" }
+        );
+        for step in self.steps.iter() {
+            summary.push_str(&format!(
+                "  {}: {} ({:.1} s)\n",
+                step.step.name(),
+                if step.success { "pass" } else { "FAIL" },
+                step.duration
+            ));
+            for line in step.details.iter() {
+                summary.push_str(&format!("    {}\n", line));
+            }
+        }
+        summary
+    }
+}
+
+/// Flag to end Selma steps early, like the Stop button of the testing page
+#[derive(Clone, Debug, Default)]
+pub struct TestStop(Arc<AtomicBool>);
+
+impl TestStop {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Append-only log of unit results, stored as one JSON object per line
+pub struct TestLog(PathBuf);
+
+impl TestLog {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self(path.into())
+    }
+
+    /// Log in the application data directory
+    pub fn default_path() -> Option<PathBuf> {
+        Some(data_dir()?.join("test-log.jsonl"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn append(&self, result: &UnitResult) -> io::Result<()> {
+        if let Some(parent) = self.0.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_vec(result)?;
+        line.push(b'\n');
+        // A single write, so concurrent writers can't interleave partial lines
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.0)?;
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    pub fn entries(&self) -> io::Result<Vec<UnitResult>> {
+        let file = match fs::File::open(&self.0) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Run each step of `plan` on `board`, continuing after failures so every problem is reported
+///
+/// Selma steps end before their duration once `stop` is set.
+pub async fn run_test_plan(
+    backend: &Backend,
+    board: &Board,
+    plan: &TestPlan,
+    serial: &str,
+    stop: &TestStop,
+) -> UnitResult {
+    let mut steps = Vec::new();
+    for (i, step) in plan.steps.iter().enumerate() {
+        info!("Step {}/{}: {}", i + 1, plan.steps.len(), step.name());
+        let start = Instant::now();
+        let mut result = StepResult {
+            step: step.clone(),
+            success: true,
+            details: Vec::new(),
+            duration: 0.0,
+            benchmark: None,
+            nelson: None,
+        };
+        match step {
+            TestStep::Benchmark { config, runs } => {
+                run_benchmark(board, config, *runs, &mut result).await
+            }
+            TestStep::Nelson { config, runs } => {
+                run_nelson(board, config, *runs, &mut result).await
+            }
+            TestStep::Selma { duration_secs } => {
+                let duration = Duration::from_secs(*duration_secs);
+                run_selma(backend, board, duration, stop, &mut result).await
+            }
+        }
+        result.duration = start.elapsed().as_secs_f64();
+        steps.push(result);
+    }
+
+    UnitResult {
+        model: board.model().to_string(),
+        version: board.version().to_string(),
+        serial: serial.to_string(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs()),
+        steps,
+    }
+}
+
+async fn run_benchmark(
+    board: &Board,
+    config: &BenchmarkConfig,
+    runs: u32,
+    result: &mut StepResult,
+) {
+    // Only Launch Lite is known to have no hub, so other boards without a port map fail
+    if board.usb_hub().is_none() {
+        if board.is_lite() {
+            result.details = vec!["Skipped, board has no USB hub".to_string()];
+        } else {
+            result.success = false;
+            result.details = vec!["Layout has no USB hub port map".to_string()];
+        }
+        return;
+    }
+
+    let mut results = Benchmark::default();
+    for run in 1..=runs {
+        match board.benchmark(config).await {
+            Ok(benchmark) => results.merge(benchmark),
+            Err(err) => {
+                result.success = false;
+                result.details = vec![format!("Run {}/{} failed: {}", run, runs, err)];
+                return;
+            }
+        }
+        if results.success() {
+            break;
+        }
+        Delay::new(Duration::from_secs(1)).await;
+    }
+
+    result.success = results.success();
+    result.details = results
+        .port_results
+        .iter()
+        .map(|(port_desc, result)| match result {
+            Ok(ok) => format!("{}: {}", port_desc, ok),
+            Err(err) => format!("{}: {}", port_desc, err),
+        })
+        .collect();
+    result.benchmark = Some(results);
+}
+
+async fn run_nelson(board: &Board, config: &NelsonConfig, runs: u32, result: &mut StepResult) {
+    if let Err(err) = board.set_no_input(true).await {
+        error!("Error setting no input mode: {}", err);
+    }
+
+    let mut report = NelsonReport::new(board.model(), board.version(), board.layout());
+    result.details = vec![format!("{} runs successful", runs)];
+    for run in 1..=runs {
+        info!("Nelson run {}/{}", run, runs);
+        let start = Instant::now();
+        let res = board.nelson(config).await;
+        let nelson_run = report.push(
+            config,
+            board.layout(),
+            res.as_ref().map_err(String::as_str),
+            start.elapsed(),
+        );
+        if let Some(err) = &nelson_run.error {
+            result.details = vec![format!("Run {}/{} failed to run: {}", run, runs, err)];
+        } else if !nelson_run.findings.is_empty() {
+            result.details = vec![format!("Run {}/{} failed", run, runs)];
+            let findings = nelson_run.findings.iter().map(|x| x.to_string());
+            result.details.extend(findings);
+        }
+        if !nelson_run.success() {
+            result.success = false;
+            break;
+        }
+    }
+    result.nelson = Some(report);
+
+    if let Err(err) = board.set_no_input(false).await {
+        error!("Error setting no input mode: {}", err);
+    }
+}
+
+async fn run_selma(
+    backend: &Backend,
+    board: &Board,
+    duration: Duration,
+    stop: &TestStop,
+    result: &mut StepResult,
+) {
+    if let Err(err) = board.set_no_input(true).await {
+        error!("Error setting no input mode: {}", err);
+    }
+    let matrix_get_rate = backend.matrix_get_rate();
+    backend.set_matrix_get_rate(Some(SELMA_MATRIX_GET_RATE));
+
+    let mut spurious = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < duration && !stop.is_stopped() {
+        Delay::new(SELMA_MATRIX_GET_RATE).await;
+        for key in board.keys() {
+            let name = key.logical_name.clone();
+            if key.pressed() && !spurious.contains(&name) {
+                spurious.push(name);
+            }
+        }
+    }
+
+    backend.set_matrix_get_rate(matrix_get_rate);
+    if let Err(err) = board.set_no_input(false).await {
+        error!("Error setting no input mode: {}", err);
+    }
+
+    result.success = spurious.is_empty();
+    result.details = spurious
+        .into_iter()
+        .map(|x| format!("Spurious keypress: {}", x))
+        .collect();
+    if stop.is_stopped() {
+        let elapsed = start.elapsed().as_secs();
+        result
+            .details
+            .insert(0, format!("Stopped after {} seconds", elapsed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Event;
+    use futures::{executor::block_on, StreamExt};
+
+    /// Run `step` on a dummy `board`
+    fn run_dummy(board: &str, step: TestStep, stop: &TestStop) -> StepResult {
+        let (backend, mut events) = Backend::new_dummy(vec![board.to_string()]).unwrap();
+        backend.refresh();
+        block_on(async {
+            let board = loop {
+                if let Some(Event::BoardAdded(board)) = events.next().await {
+                    break board;
+                }
+            };
+            let plan = TestPlan { steps: vec![step] };
+            let mut result = run_test_plan(&backend, &board, &plan, "A1", stop).await;
+            result.steps.remove(0)
+        })
+    }
+
+    #[test]
+    fn benchmark_without_usb_hub() {
+        let step = TestStep::Benchmark {
+            config: BenchmarkConfig::default(),
+            runs: 1,
+        };
+        let stop = TestStop::default();
+
+        let result = run_dummy("system76/launch_lite_1", step.clone(), &stop);
+        assert!(result.success);
+        assert_eq!(result.details, vec!["Skipped, board has no USB hub"]);
+
+        let result = run_dummy("system76/launch_alpha_1", step, &stop);
+        assert!(!result.success);
+        assert_eq!(result.details, vec!["Layout has no USB hub port map"]);
+    }
+
+    #[test]
+    fn selma_stop() {
+        let stop = TestStop::default();
+        stop.stop();
+        let step = TestStep::Selma {
+            duration_secs: 3600,
+        };
+        let result = run_dummy("system76/launch_2", step, &stop);
+        assert!(result.success);
+        assert_eq!(result.details, vec!["Stopped after 0 seconds"]);
+    }
+
+    #[test]
+    fn test_plan_and_log() {
+        let plan = TestPlan::from_reader(
+            r#"{"steps": [
+                {"type": "benchmark"},
+                {"type": "nelson", "runs": 10},
+                {"type": "selma", "duration_secs": 30}
+            ]}"#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            plan.steps[0],
+            TestStep::Benchmark {
+                config: BenchmarkConfig::default(),
+                runs: 1
+            }
+        );
+        assert_eq!(
+            plan.steps[1],
+            TestStep::Nelson {
+                config: NelsonKind::Normal.into(),
+                runs: 10
+            }
+        );
+
+        let err = TestPlan::from_reader(
+            r#"{"steps": [{"type": "nelson", "config": {"steps": []}}]}"#.as_bytes(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("no steps"), "{}", err);
+
+        let result = |serial: &str, success| UnitResult {
+            model: "system76/launch_2".to_string(),
+            version: "0.19.12".to_string(),
+            serial: serial.to_string(),
+            timestamp: 0,
+            steps: vec![StepResult {
+                step: plan.steps[2].clone(),
+                success,
+                details: Vec::new(),
+                duration: 30.0,
+                benchmark: None,
+                nelson: None,
+            }],
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("keyboard-test-log-{}.jsonl", std::process::id()));
+        let log = TestLog::new(&path);
+        assert_eq!(log.entries().unwrap(), Vec::new());
+        log.append(&result("A1", true)).unwrap();
+        log.append(&result("A2", false)).unwrap();
+        let entries = log.entries().unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(entries, vec![result("A1", true), result("A2", false)]);
+        assert!(entries[0].success());
+        assert!(entries[1]
+            .summary()
+            .starts_with("system76/launch_2 A2 (0.19.12): FAIL\n  selma: FAIL"));
+    }
+}
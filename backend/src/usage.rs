@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{data_dir, KeyEvent, Layout};
+
+/// Quote a CSV field if it contains a separator, quote, or line break
+pub(crate) fn csv_escape(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Press count for one key, as exported to CSV or JSON
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyUsageEntry {
+    pub logical_name: String,
+    pub physical_name: String,
+    pub count: u64,
+}
+
+#[derive(Serialize)]
+struct KeyUsageExport<'a> {
+    model: &'a str,
+    keys: Vec<KeyUsageEntry>,
+}
+
+/// Number of presses for each key of a board model, collected from matrix polling
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub model: String,
+    /// Press counts, by logical name
+    pub counts: BTreeMap<String, u64>,
+}
+
+impl KeyUsage {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Path counts for `model` are stored at
+    pub fn path(model: &str) -> Option<PathBuf> {
+        let file_name = format!("{}.json", model.replace('/', "_"));
+        Some(data_dir()?.join("usage").join(file_name))
+    }
+
+    /// Load stored counts for `model`, or empty counts if none have been saved
+    pub fn load(model: &str) -> Result<Self, String> {
+        let path = Self::path(model).ok_or("Failed to find data directory")?;
+        match Self::load_from(&path) {
+            Ok(usage) => Ok(usage),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::new(model)),
+            Err(err) => Err(format!("Failed to load '{}': {}", path.display(), err)),
+        }
+    }
+
+    fn load_from(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path(&self.model).ok_or("Failed to find data directory")?;
+        self.save_to(&path)
+            .map_err(|err| format!("Failed to save '{}': {}", path.display(), err))
+    }
+
+    fn save_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first, so a crash can't truncate the counts
+        let tmp_path = path.with_extension("json.tmp");
+        serde_json::to_writer(File::create(&tmp_path)?, self)?;
+        fs::rename(tmp_path, path)
+    }
+
+    /// Count a key press. Key releases are ignored.
+    pub fn record(&mut self, event: &KeyEvent) {
+        if event.pressed {
+            *self.counts.entry(event.logical_name.clone()).or_insert(0) += 1;
+        }
+    }
+
+    pub fn count(&self, logical_name: &str) -> u64 {
+        self.counts.get(logical_name).copied().unwrap_or(0)
+    }
+
+    /// Highest press count of any key
+    pub fn max(&self) -> u64 {
+        self.counts.values().copied().max().unwrap_or(0)
+    }
+
+    /// Counts for every key in the layout, including keys never pressed
+    pub fn entries(&self, layout: &Layout) -> Vec<KeyUsageEntry> {
+        layout
+            .physical
+            .keys
+            .iter()
+            .map(|key| {
+                let logical_name = key.logical_name();
+                KeyUsageEntry {
+                    count: self.count(&logical_name),
+                    logical_name,
+                    physical_name: key.physical_name.clone(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_csv_writer<W: Write>(&self, layout: &Layout, mut wtr: W) -> io::Result<()> {
+        writeln!(wtr, "logical_name,physical_name,count")?;
+        for entry in self.entries(layout) {
+            writeln!(
+                wtr,
+                "{},{},{}",
+                csv_escape(&entry.logical_name),
+                csv_escape(&entry.physical_name),
+                entry.count
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn to_json_writer_pretty<W: Write>(
+        &self,
+        layout: &Layout,
+        wtr: W,
+    ) -> serde_json::Result<()> {
+        let export = KeyUsageExport {
+            model: &self.model,
+            keys: self.entries(layout),
+        };
+        serde_json::to_writer_pretty(wtr, &export)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn usage_record_and_export() {
+        let layout = Layout::from_board("system76/launch_2", "0.19.12").unwrap();
+        let now = Instant::now();
+        let event = |logical_name, pressed| KeyEvent::mock(logical_name, pressed, now);
+        let mut usage = KeyUsage::new("system76/launch_2");
+        usage.record(&event("K00", true));
+        usage.record(&event("K00", false));
+        usage.record(&event("K00", true));
+        usage.record(&event("K01", true));
+        assert_eq!(usage.count("K00"), 2);
+        assert_eq!(usage.max(), 2);
+
+        let mut csv = Vec::new();
+        usage.to_csv_writer(&layout, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("logical_name,physical_name,count\nK00,Esc,2\n"));
+        assert!(csv.contains("K01,F1,1\n"));
+        // Multi-line keycap names are quoted
+        assert!(csv.contains(",\"!\n1\","));
+
+        let path = std::env::temp_dir().join(format!("keyboard-usage-{}.json", std::process::id()));
+        usage.save_to(&path).unwrap();
+        assert_eq!(KeyUsage::load_from(&path).unwrap(), usage);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{qmk_info::to_json, KeyMap, LayoutFiles, PhysicalLayout};
+
+/// Subset of a VIA keyboard definition, in the v2 or v3 format
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ViaDefinition {
+    name: String,
+    vendor_id: String,
+    product_id: String,
+    layouts: ViaLayouts,
+    /// Lighting of v2 definitions, as a name or an object that `extends` one
+    lighting: Option<Value>,
+    /// Menus of v3 definitions, which include the lighting
+    #[serde(default)]
+    menus: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct ViaLayouts {
+    keymap: Vec<Value>,
+}
+
+impl ViaDefinition {
+    fn parse(definition_json: &str) -> Result<Self, String> {
+        serde_json::from_str(definition_json)
+            .map_err(|err| format!("Failed to parse VIA definition: {}", err))
+    }
+
+    fn board(&self) -> Result<String, String> {
+        let id = |name, value: &str| {
+            u16::from_str_radix(value.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("Invalid {} '{}' in VIA definition", name, value))
+        };
+        Ok(via_board(
+            id("vendorId", &self.vendor_id)?,
+            id("productId", &self.product_id)?,
+        ))
+    }
+
+    /// Names of the built-in VIA lighting menus, like `qmk_rgblight`
+    fn lighting(&self) -> Vec<&str> {
+        let lighting = self.lighting.iter().chain(&self.menus);
+        lighting
+            .filter_map(|x| x.as_str().or_else(|| x.get("extends")?.as_str()))
+            .collect()
+    }
+}
+
+/// Board name used for a VIA keyboard, from its USB vendor and product ID
+pub fn via_board(vendor_id: u16, product_id: u16) -> String {
+    format!("via/{:04x}_{:04x}", vendor_id, product_id)
+}
+
+/// Board name of the keyboard described by a VIA definition
+pub fn via_definition_board(definition_json: &str) -> Result<String, String> {
+    ViaDefinition::parse(definition_json)?.board()
+}
+
+/// Parse a legend like `1,2`, as VIA uses for matrix positions and layout options
+fn parse_pair(legend: &str) -> Option<(u8, u8)> {
+    let (a, b) = legend.split_once(',')?;
+    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+}
+
+/// Generate the layout of a VIA keyboard from its definition
+///
+/// The board is named by `via_board`. VIA labels each key of the KLE layout with its matrix
+/// position, and keys for layout options other than the first with their option group and
+/// choice, which are turned into decals. Definitions have no default keymap, so `default.json`
+/// binds no keys. They don't give the number of layers either, so this uses the QMK default of
+/// 4, which `Board` replaces with the count reported by the keyboard.
+pub fn import_via_definition(definition_json: &str) -> Result<LayoutFiles, String> {
+    let definition = ViaDefinition::parse(definition_json)?;
+    let board = definition.board()?;
+
+    let physical =
+        PhysicalLayout::from_str(&serde_json::to_string(&definition.layouts.keymap).unwrap())
+            .map_err(|err| format!("Failed to parse keymap of VIA definition: {}", err))?;
+    let hidden = physical
+        .keys
+        .iter()
+        .enumerate()
+        .filter(|(_, key)| parse_pair(&key.legends[8]).map_or(false, |(_, choice)| choice != 0))
+        .map(|(i, _)| i)
+        .collect::<BTreeSet<_>>();
+
+    // Mark hidden keys as decals, which keeps the position of the keys after them
+    let mut rows = vec![json!({ "name": definition.name, "author": "" })];
+    let mut index = 0;
+    for entry in &definition.layouts.keymap {
+        let row = match entry.as_array() {
+            Some(row) => row,
+            None => continue,
+        };
+        let mut decal = false;
+        let mut new_row = Vec::new();
+        for item in row {
+            match item {
+                Value::String(_) => {
+                    if !decal {
+                        if hidden.contains(&index) {
+                            new_row.push(json!({ "d": true }));
+                        }
+                        index += 1;
+                    }
+                    decal = false;
+                }
+                _ => decal = item.get("d").and_then(Value::as_bool).unwrap_or(false),
+            }
+            new_row.push(item.clone());
+        }
+        rows.push(Value::Array(new_row));
+    }
+    let physical_json = to_json(&rows);
+
+    let physical = PhysicalLayout::from_str(&physical_json)
+        .map_err(|err| format!("Failed to parse physical.json: {}", err))?;
+    let mut layout = BTreeMap::new();
+    for key in &physical.keys {
+        if key.logical.0 >= 36 || key.logical.1 >= 36 {
+            return Err(format!(
+                "Key '{}' is past the 36 rows or columns supported in physical.json",
+                key.physical_name
+            ));
+        }
+        let matrix = parse_pair(&key.legends[0]).ok_or_else(|| {
+            format!(
+                "Key '{}' in VIA definition has no matrix position",
+                key.physical_name
+            )
+        })?;
+        layout.insert(key.logical_name(), matrix);
+    }
+
+    let lighting = definition.lighting();
+    let has_color = lighting.iter().any(|x| x.contains("rgb"));
+    let has_brightness = has_color || lighting.iter().any(|x| x.contains("backlight"));
+
+    let default = KeyMap {
+        model: board.clone(),
+        version: 1,
+        map: BTreeMap::new(),
+        key_leds: BTreeMap::new(),
+        layers: Vec::new(),
+    };
+
+    let meta = json!({
+        "display_name": definition.name,
+        "has_brightness": has_brightness,
+        "has_color": has_color,
+        "has_mod_tap": true,
+        "is_qmk": true,
+        "num_layers": 4,
+        "pressed_color": "#202020",
+        "keyboard": board,
+    });
+
+    Ok(LayoutFiles {
+        board: board.clone(),
+        keyboard: board,
+        meta_json: to_json(&meta),
+        default_json: to_json(&default),
+        layout_json: to_json(&layout),
+        leds_json: to_json(&BTreeMap::<String, Vec<u8>>::new()),
+        physical_json,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Layout;
+
+    const DEFINITION_JSON: &str = r#"{
+        "name": "Acme Pad",
+        "vendorId": "0xFEED",
+        "productId": "0x6060",
+        "matrix": {"rows": 2, "cols": 3},
+        "lighting": {"extends": "qmk_rgblight"},
+        "layouts": {
+            "labels": ["Split Backspace"],
+            "keymap": [
+                ["0,0", "0,1", {"w": 2}, "0,2\n\n\n0,0", "0,1\n\n\n0,1", "0,2\n\n\n0,1"],
+                [{"a": 7, "d": true}, "Logo", {"a": 4, "w": 1.5}, "1,0", "1,2"]
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn via_definition_import() {
+        assert_eq!(
+            via_definition_board(DEFINITION_JSON).unwrap(),
+            "via/feed_6060"
+        );
+        let files = import_via_definition(DEFINITION_JSON).unwrap();
+        assert_eq!(files.board, "via/feed_6060");
+
+        let layout = Layout::from_via_definition(DEFINITION_JSON, "0.19.0").unwrap();
+        assert_eq!(layout.meta.display_name, "Acme Pad");
+        assert!(layout.meta.is_qmk && layout.meta.has_color && !layout.meta.has_mode);
+        assert_eq!(layout.meta.num_layers, 4);
+
+        // Keys of the second split backspace option are decals
+        let keys = layout
+            .physical
+            .keys
+            .iter()
+            .map(|x| {
+                (
+                    x.logical_name(),
+                    layout.layout()[&x.logical_name()],
+                    x.physical.x,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                ("K00".to_string(), (0, 0), 0.0),
+                ("K01".to_string(), (0, 1), 1.0),
+                ("K02".to_string(), (0, 2), 2.0),
+                ("K10".to_string(), (1, 0), 1.0),
+                ("K11".to_string(), (1, 2), 2.5),
+            ]
+        );
+        assert!(layout.default.map.is_empty());
+
+        let err = import_via_definition(&DEFINITION_JSON.replace("0,0\"", "A\"")).unwrap_err();
+        assert!(err.contains("no matrix position"), "{}", err);
+    }
+}
@@ -7,16 +7,31 @@ board-fake = {$model}, fake
 
 button-cancel = Cancel
 button-configure = Configure Keyboard
+button-continue = Continue
 button-disable = Disable
+button-export = Export
 button-import = Import
+button-refresh = Refresh
 button-test = Test
 button-start = Start
 button-stop = Stop
 
+diagnostics-not-running = Press Start, then type on every key.
+diagnostics-no-problems = {$keys} keys tested, no problems found.
+diagnostics-problems = {$keys} keys tested, {$problems} with problems:
+diagnostics-stopped = Stopped.
+
 error-disable-key = Failed to disable key
+error-export-key-test = Failed to export key test results
 error-export-keymap = Failed to export keymap
+error-export-nelson = Failed to export Nelson report
+error-export-qmk = Failed to export QMK keymap
+error-export-usage = Failed to export key usage
+error-flash = Failed to flash firmware
+error-fwupd-update = Failed to update firmware
 error-import-keymap = Failed to import keymap
 error-key-led = Failed to key LED
+error-load-sequence = Failed to load Nelson sequence
 error-open-file = Failed to open file
 error-save-leds = Failed to save LEDs
 error-set-keyboard-brightness = Error setting brightness
@@ -27,6 +42,7 @@ error-set-layer-color = Failed to set layer color
 error-set-layer-mode = Failed to set layer mode
 error-unsupported-keymap = Unsupported keymap file
 error-unsupported-keymap-desc = Keymap file appears to be from newer Configurator version.
+error-usb-hub-status = Failed to read USB hub status
 
 firmware-version = Firmware version {$version} does not support keymap configuration.
 
@@ -35,7 +51,16 @@ keyboard-color = Color:
 
 key-color = Key Color:
 
+key-tester-export = Export Key Test Results
+key-tester-not-running = Press Start, then press every key once.
+key-tester-passed = Passed.
+key-tester-status = {$tested} of {$total} keys tested.
+key-tester-stuck = Stuck:
+key-tester-untested = Not yet pressed:
+
 keymap-for-board = Keymap is for board '{$model}'
+keymap-parse-error = Not a Configurator keymap: {$error}
+keymap-warnings = Keymap may be hard to use or recover from
 
 layer-all-brightness = Brightness (all layers):
 layer-animation-speed = Layer Animation Speed:
@@ -44,10 +69,16 @@ layer-color-pattern = Layer Color Pattern:
 layer-saturation = Layer Saturation:
 
 layout-export = Export Layout
+layout-export-qmk = Export QMK Keymap
 layout-import = Import Layout
 layout-reset = Reset Layout
 layout-invert-f-keys = Invert F Keys
 
+flash-step = Flashing firmware, step {$index} of {$total}: {$step}
+flash-step-erase = Erasing
+flash-step-flash = Writing
+flash-step-launch = Starting keyboard
+flash-step-validate = Checking firmware file
 flash-to-launch-heavy = Flash to Launch Heavy 1
 flash-to-launch-2 = Flash to Launch 2
 flash-to-launch-1 = Flash to Launch 1
@@ -55,6 +86,8 @@ flash-to-launch-lite-1 = Flash to Launch Lite 1
 
 loading = Keyboard(s) detected. Loading...
 loading-keyboard = Loading keymap and LEDs for {$keyboard}
+firmware-update = Update Firmware to {$version}
+firmware-update-progress = Updating keyboard firmware: {$percentage}%
 firmware-update-required = Keyboard Firmware Update Required!
 
 page-electrical = Electrical
@@ -66,6 +99,9 @@ page-layer4 = Layer 4
 page-leds = LEDs
 page-logical = Logical
 
+qmk-keymap-issues = Some QMK keycodes could not be imported exactly
+qmk-keymap-parse-error = Not a QMK keymap.json: {$error}
+
 no-boards = No keyboard detected
 no-boards-msg = Make sure your built-in keyboard has up to date
  System76 Open Firmware.
@@ -74,6 +110,10 @@ no-boards-msg = Make sure your built-in keyboard has up to date
 
 show-help-overlay = Keyboard Shortcuts
 
+stack-diagnostics = Diagnostics
+stack-diagnostics-desc = Check for worn switches. While running, keys that register repeated presses too quickly (chatter) or stay pressed are listed and highlighted.
+stack-key-tester = Key Tester
+stack-key-tester-desc = Check that every key works. While running, keys turn green once pressed, and keys held down for more than a few seconds turn red. Keys not yet pressed are listed below.
 stack-keymap = Keymap
 stack-keymap-desc =
  Select a key on the keymap to change its settings. Shift + click to select more than one click. Your settings are automatically saved to firmware.
@@ -83,11 +123,30 @@ stack-leds-desc = Select a key on the keymap to change its settings. Choose per
 stack-leds-desc-builtin = LED settings will reset after reboot. More functionality is coming in the future.
 
 stack-testing = Testing
+stack-usb-hub = USB Hub
+stack-usb-hub-desc = Devices plugged into the keyboard's USB ports, and the speed each is connected at. A USB 3 device connected at High Speed (480 Mbps) may have a worn cable or a USB 2 only cable.
 
+test-export-nelson = Export Nelson Report
 test-check-pins = Check pins (missing)
 test-check-key = Check key (sticking)
+test-duration = Duration (seconds)
 test-number-of-runs = Number of runs
 test-replace-switch = Replace switch
+test-sequence = Sequence
+test-sequence-fast = Fast screening
+test-sequence-load = Load Sequence
+test-sequence-standard = Standard
+test-sequence-thorough = Thorough
 test-spurious-keypress = Spurious keypress
+test-usb-write = Test writes to a scratch file on mounted drives
 
+untitled-key-test = Key Test
 untitled-layout = Untitled Layout
+untitled-usage = Key Usage
+
+usage-export = Export Key Usage
+usage-record = Record Key Usage
+usage-show-heatmap = Show Usage Heatmap
+
+usb-hub-not-found = No USB hub found. Check that the keyboard is connected with a USB cable, not through a KVM or dock that hides its hub.
+usb-hub-port-empty = Nothing connected
@@ -1,3 +1,9 @@
+keymap-lint-unreachable-layer = No key activates layer { $layer }
+keymap-lint-no-escape = Layer { $layer } can be locked on, but has no key to return to layer 1
+keymap-lint-no-reset = No key enters the bootloader (RESET)
+keymap-lint-duplicate-modifier = { $scancode_name } is bound to multiple keys on layer { $layer }: { $keys }
+keymap-lint-empty-row = Row { $row } on layer { $layer } only has NONE keys
+
 mode-disabled = Disabled
 mode-solid-color = Per Layer Solid Color
 mode-per-key = Per Key Solid
@@ -14,4 +20,9 @@ mode-raindrops = Elements
 mode-splash = Splashdown
 mode-multisplash = Meteor Shower
 
-no-board = No board
\ No newline at end of file
+no-board = No board
+
+qmk-issue-keyboard = Keymap is for { $keyboard }, not { $model }
+qmk-issue-key-count = Layer { $layer } has { $keys } keys instead of { $expected }
+qmk-issue-extra-layers = Keymap has { $layers } layers, but the keyboard only has { $num_layers }
+qmk-issue-unknown-keycode = { $key } on layer { $layer } is bound to unsupported keycode { $keycode }
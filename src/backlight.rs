@@ -9,7 +9,7 @@ use gtk::{
 use once_cell::sync::Lazy;
 use std::cell::{Cell, RefCell};
 
-use backend::{Board, DerefCell, Hs, Mode};
+use backend::{Board, Capability, DerefCell, Hs, Mode};
 use widgets::{KeyboardColor, KeyboardColorIndex, SelectedKeys};
 
 #[derive(Default)]
@@ -225,7 +225,7 @@ glib::wrapper! {
 impl Backlight {
     pub fn new(board: Board) -> Self {
         let max_brightness = board.max_brightness() as f64;
-        let has_led_save = board.has_led_save();
+        let has_led_save = board.capabilities().has(Capability::LedSave);
 
         let obj: Self = glib::Object::new();
         obj.inner().board.set(board.clone());
@@ -242,7 +242,7 @@ impl Backlight {
             obj.header_func(row, before)
         ))));
 
-        if !obj.board().layout().meta.has_per_layer {
+        if !obj.board().capabilities().has(Capability::PerLayerLeds) {
             obj.inner()
                 .brightness_label
                 .set_label(&fl!("keyboard-brightness"));
@@ -291,17 +291,18 @@ impl Backlight {
 
     fn filter_func(&self, row: &gtk::ListBoxRow) -> bool {
         let inner = self.inner();
-        let layout = inner.board.layout();
+        let capabilities = inner.board.capabilities();
+        let has_mode = capabilities.has(Capability::LedModes);
         if row == &*inner.mode_row {
-            layout.meta.has_mode
+            has_mode
         } else if row == &*inner.speed_row {
-            layout.meta.has_mode && self.mode().has_speed
+            has_mode && self.mode().has_speed
         } else if row == &*inner.color_row {
-            layout.meta.has_color && (!layout.meta.has_mode || self.mode().has_hue)
+            capabilities.has(Capability::LedColor) && (!has_mode || self.mode().has_hue)
         } else if row == &*inner.saturation_row {
             !self.mode().has_hue && !self.mode().is_disabled()
         } else if row == &*inner.brightness_row {
-            layout.meta.has_brightness && (!layout.meta.has_mode || !self.mode().is_disabled())
+            capabilities.has(Capability::LedBrightness) && (!has_mode || !self.mode().is_disabled())
         } else {
             true
         }
@@ -319,7 +320,7 @@ impl Backlight {
             self.inner()
                 .keyboard_color
                 .set_index(KeyboardColorIndex::Layer(self.inner().layer.get()));
-            if self.board().layout().meta.has_per_layer {
+            if self.board().capabilities().has(Capability::PerLayerLeds) {
                 self.inner().color_label.set_label(&fl!("layer-color"));
             } else {
                 self.inner().color_label.set_label(&fl!("keyboard-color"));
@@ -363,7 +364,7 @@ impl Backlight {
     }
 
     pub fn set_layer(&self, mut layer: usize) {
-        if !self.board().layout().meta.has_per_layer {
+        if !self.board().capabilities().has(Capability::PerLayerLeds) {
             layer = 0;
         }
 
@@ -421,7 +422,7 @@ impl Backlight {
     }
 
     fn led_save(&self) {
-        if self.board().has_led_save() {
+        if self.board().capabilities().has(Capability::LedSave) {
             let board = self.board().clone();
             glib::MainContext::default().spawn_local(async move {
                 if let Err(err) = board.led_save().await {
@@ -1,10 +1,14 @@
 use cascade::cascade;
-use gtk::{gdk, gio, glib, prelude::*, subclass::prelude::*};
+use gtk::{
+    gdk, gio,
+    glib::{self, clone},
+    prelude::*,
+    subclass::prelude::*,
+};
 use std::cell::Cell;
-use std::process::Command;
 
 use crate::{about_dialog, fl, MainWindow, Page};
-use backend::DerefCell;
+use backend::{default_firmware_path, Bootloaded, DerefCell};
 
 #[derive(Default)]
 pub struct ConfiguratorAppInner {
@@ -82,42 +86,39 @@ impl ApplicationImpl for ConfiguratorAppInner {
             ..connect_activate(|_, _| about_dialog::show_about_dialog());
         };
 
-        let flash_1 = cascade! {
-            gio::SimpleAction::new("flash-to-launch-1", None);
-            ..connect_activate(|_, _| {
-                let _ = Command::new("dfu-programmer").args(["atmega32u4", "erase"]).status();
-                let _ = Command::new("dfu-programmer").args(["atmega32u4", "flash", "/var/lib/system76-keyboard-configurator/system76_launch_1_default.hex", "--suppress-bootloader-mem"]).status();
-                let _ = Command::new("dfu-programmer").args(["atmega32u4", "start"]).status();
-            });
-        };
-        let flash_2 = cascade! {
-            gio::SimpleAction::new("flash-to-launch-2", None);
-            ..connect_activate(|_, _| {
-                let _ = Command::new("dfu-programmer").args(["at90usb646", "flash", "/var/lib/system76-keyboard-configurator/system76_launch_2_default.hex", "--suppress-bootloader-mem"]).status();
-                let _ = Command::new("dfu-programmer").args(["at90usb646", "reset"]).status();
-            });
-        };
-        let flash_lite_1 = cascade! {
-            gio::SimpleAction::new("flash-to-launch-lite-1", None);
-            ..connect_activate(|_, _| {
-                let _ = Command::new("dfu-programmer").args(["at90usb646", "flash", "/var/lib/system76-keyboard-configurator/system76_launch_lite_1_default.hex", "--suppress-bootloader-mem"]).status();
-                let _ = Command::new("dfu-programmer").args(["at90usb646", "reset"]).status();
-            });
-        };
-        let flash_heavy_1 = cascade! {
-            gio::SimpleAction::new("flash-to-launch-heavy-1", None);
-            ..connect_activate(|_, _| {
-                let _ = Command::new("dfu-programmer").args(["at90usb646", "flash", "/var/lib/system76-keyboard-configurator/system76_launch_heavy_1_default.hex", "--suppress-bootloader-mem"]).status();
-                let _ = Command::new("dfu-programmer").args(["at90usb646", "reset"]).status();
-            });
-        };
-
         let app = self.obj();
         app.add_action(&about_action);
-        app.add_action(&flash_heavy_1);
-        app.add_action(&flash_2);
-        app.add_action(&flash_1);
-        app.add_action(&flash_lite_1);
+        for (name, target, board) in [
+            (
+                "flash-to-launch-1",
+                Bootloaded::AtMega32u4,
+                "system76/launch_1",
+            ),
+            (
+                "flash-to-launch-2",
+                Bootloaded::At90usb646,
+                "system76/launch_2",
+            ),
+            (
+                "flash-to-launch-lite-1",
+                Bootloaded::At90usb646Lite,
+                "system76/launch_lite_1",
+            ),
+            (
+                "flash-to-launch-heavy-1",
+                Bootloaded::At90usb646,
+                "system76/launch_heavy_1",
+            ),
+        ] {
+            app.add_action(&cascade! {
+                gio::SimpleAction::new(name, None);
+                ..connect_activate(clone!(@weak app => move |_, _| {
+                    if let Some(window) = app.active_window().and_then(|x| x.downcast::<MainWindow>().ok()) {
+                        window.flash_firmware(target, &default_firmware_path(board));
+                    }
+                }));
+            });
+        }
         app.set_accels_for_action("kbd.import", &["<Primary>o"]);
         app.set_accels_for_action("kbd.export", &["<Primary>e"]);
         for (i, _) in Page::iter_all().enumerate() {
@@ -0,0 +1,219 @@
+use crate::{fl, Keyboard, TestingColors};
+use backend::{ChatterConfig, ChatterDetector, DerefCell, KeyEvent, Rgb};
+use cascade::cascade;
+use gtk::{
+    glib::{self, clone},
+    prelude::*,
+    subclass::prelude::*,
+};
+use once_cell::sync::Lazy;
+use std::{
+    cell::{Cell, RefCell},
+    time::{Duration, Instant},
+};
+
+/// Matrix polling rate while diagnostics are running, fast enough to see switch chatter
+pub const DIAGNOSTICS_MATRIX_RATE: Duration = Duration::from_millis(5);
+
+#[derive(Default)]
+pub struct DiagnosticsInner {
+    keyboard: DerefCell<glib::WeakRef<Keyboard>>,
+    start_button: DerefCell<gtk::Button>,
+    stop_button: DerefCell<gtk::Button>,
+    status_label: DerefCell<gtk::Label>,
+    results_list: DerefCell<gtk::ListBox>,
+    detector: RefCell<ChatterDetector>,
+    running: Cell<bool>,
+    colors: RefCell<TestingColors>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DiagnosticsInner {
+    const NAME: &'static str = "S76Diagnostics";
+    type ParentType = gtk::Box;
+    type Type = Diagnostics;
+}
+
+impl ObjectImpl for DiagnosticsInner {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let start_button = gtk::Button::with_label(&fl!("button-start"));
+        let stop_button = cascade! {
+            gtk::Button::with_label(&fl!("button-stop"));
+            ..set_sensitive(false);
+        };
+        let status_label = gtk::Label::new(Some(&fl!("diagnostics-not-running")));
+        let results_list = cascade! {
+            gtk::ListBox::new();
+            ..set_valign(gtk::Align::Start);
+            ..set_selection_mode(gtk::SelectionMode::None);
+            ..style_context().add_class("frame");
+            ..set_no_show_all(true);
+        };
+
+        cascade! {
+            self.obj();
+            ..set_orientation(gtk::Orientation::Vertical);
+            ..set_spacing(18);
+            ..set_halign(gtk::Align::Center);
+            ..add(&cascade! {
+                gtk::Label::new(Some(&fl!("stack-diagnostics-desc")));
+                ..set_line_wrap(true);
+                ..set_max_width_chars(100);
+                ..set_halign(gtk::Align::Center);
+            });
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..set_halign(gtk::Align::Center);
+                ..add(&start_button);
+                ..add(&stop_button);
+            });
+            ..add(&status_label);
+            ..add(&results_list);
+            ..show_all();
+        };
+
+        self.start_button.set(start_button);
+        self.stop_button.set(stop_button);
+        self.status_label.set(status_label);
+        self.results_list.set(results_list);
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![glib::ParamSpecBoxed::builder::<TestingColors>("colors")
+                .read_only()
+                .build()]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "colors" => self.colors.borrow().to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl WidgetImpl for DiagnosticsInner {}
+impl ContainerImpl for DiagnosticsInner {}
+impl BoxImpl for DiagnosticsInner {}
+
+glib::wrapper! {
+    pub struct Diagnostics(ObjectSubclass<DiagnosticsInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl Diagnostics {
+    pub fn new(keyboard: &Keyboard) -> Self {
+        let obj: Self = glib::Object::new();
+        obj.inner().keyboard.set(keyboard.downgrade());
+        obj.inner()
+            .start_button
+            .connect_clicked(clone!(@weak obj => move |_| {
+                glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+                    obj.run().await;
+                }));
+            }));
+        obj.inner()
+            .stop_button
+            .connect_clicked(clone!(@weak obj => move |_| obj.inner().running.set(false)));
+        obj
+    }
+
+    fn inner(&self) -> &DiagnosticsInner {
+        DiagnosticsInner::from_obj(self)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.inner().running.get()
+    }
+
+    pub fn handle_key_event(&self, event: &KeyEvent) {
+        if self.is_running() {
+            self.inner().detector.borrow_mut().handle_event(event);
+            self.update_report();
+        }
+    }
+
+    async fn run(&self) {
+        let inner = self.inner();
+
+        *inner.detector.borrow_mut() = ChatterDetector::new(ChatterConfig::default());
+        inner.running.set(true);
+        inner.start_button.set_sensitive(false);
+        inner.stop_button.set_sensitive(true);
+        self.update_report();
+
+        if let Some(keyboard) = inner.keyboard.upgrade() {
+            keyboard.update_matrix_get_rate();
+        }
+
+        // Refresh periodically, so held keys are reported as stuck
+        while self.is_running() {
+            glib::timeout_future(Duration::from_secs(1)).await;
+            self.update_report();
+        }
+
+        if let Some(keyboard) = inner.keyboard.upgrade() {
+            keyboard.update_matrix_get_rate();
+        }
+
+        inner.start_button.set_sensitive(true);
+        inner.stop_button.set_sensitive(false);
+        self.update_report();
+    }
+
+    fn update_report(&self) {
+        let inner = self.inner();
+        let report = inner.detector.borrow().report(Instant::now());
+
+        let status = if report.success() {
+            fl!(
+                "diagnostics-no-problems",
+                keys = report.keys_tested.to_string()
+            )
+        } else {
+            fl!(
+                "diagnostics-problems",
+                keys = report.keys_tested.to_string(),
+                problems = report.keys.len().to_string()
+            )
+        };
+        if self.is_running() {
+            inner.status_label.set_text(&status);
+        } else {
+            inner
+                .status_label
+                .set_text(&format!("{} {}", fl!("diagnostics-stopped"), status));
+        }
+
+        inner
+            .results_list
+            .foreach(|row| inner.results_list.remove(row));
+        let mut colors = TestingColors::default();
+        for key in report.keys.iter() {
+            inner.results_list.add(&cascade! {
+                gtk::Label::new(Some(&key.to_string()));
+                ..set_halign(gtk::Align::Start);
+                ..set_margin(8);
+                ..show();
+            });
+            // Matches the colors used for Nelson results
+            let color = if key.is_stuck() {
+                Rgb::new(0, 255, 0)
+            } else {
+                Rgb::new(0, 0, 255)
+            };
+            let (row, col) = key.electrical;
+            colors.0.insert((row as usize, col as usize), color);
+        }
+        inner.results_list.set_visible(!report.keys.is_empty());
+
+        inner.colors.replace(colors);
+        self.notify("colors");
+    }
+}
@@ -1,5 +1,6 @@
+use crate::fl;
 use cascade::cascade;
-use gtk::prelude::*;
+use gtk::{glib, prelude::*};
 use std::fmt::Display;
 
 pub fn show_error_dialog<W: IsA<gtk::Window>, E: Display>(parent: &W, title: &str, err: E) {
@@ -23,3 +24,31 @@ pub fn show_error_dialog<W: IsA<gtk::Window>, E: Display>(parent: &W, title: &st
 
     dialog.show();
 }
+
+/// Show a list of warnings, and return `true` if the user chooses to continue anyway
+pub async fn show_warning_dialog<W: IsA<gtk::Window>>(
+    parent: &W,
+    title: &str,
+    warnings: &[String],
+) -> bool {
+    let label = cascade! {
+        gtk::Label::new(Some(&format!("<b>{}</b>:\n{}", title, glib::markup_escape_text(&warnings.join("\n")))));
+        ..set_use_markup(true);
+        ..show();
+    };
+
+    let dialog = cascade! {
+        gtk::Dialog::with_buttons(Some(title), Some(parent), gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR, &[(&fl!("button-cancel"), gtk::ResponseType::Cancel), (&fl!("button-continue"), gtk::ResponseType::Accept)]);
+    };
+
+    let header = dialog.header_bar().unwrap();
+    header.set_show_close_button(false);
+
+    let content = dialog.content_area();
+    content.add(&label);
+    content.set_margin(24);
+
+    let response = dialog.run_future().await;
+    dialog.close();
+    response == gtk::ResponseType::Accept
+}
@@ -0,0 +1,160 @@
+use backend::{run_test_plan, Board, Event, TestLog, TestPlan, TestStop};
+use futures::{FutureExt, StreamExt};
+use gtk::glib;
+use std::{
+    fs::File,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::daemon;
+
+/// Seconds to wait for a keyboard to be connected
+const BOARD_TIMEOUT: u32 = 30;
+
+const USAGE: &str = "\
+Usage: system76-keyboard-configurator --headless-test [OPTIONS]
+
+Run a factory test plan on the connected keyboard, without a window.
+
+Options:
+  --plan FILE     Test plan JSON (default: USB test, 1 Nelson run, then 100 more)
+  --serial SERIAL Serial number of the unit (prompted for if not given)
+  --log FILE      Append-only results log (default: test-log.jsonl in the data directory)";
+
+struct Options {
+    plan: Option<PathBuf>,
+    serial: Option<String>,
+    log: Option<PathBuf>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            plan: None,
+            serial: None,
+            log: None,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("{} requires a value", arg))
+            };
+            match arg.as_str() {
+                "--headless-test" => {}
+                "--plan" => options.plan = Some(value()?.into()),
+                "--serial" => options.serial = Some(value()?),
+                "--log" => options.log = Some(value()?.into()),
+                _ => return Err(format!("Unrecognized argument '{}'", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn prompt_serial() -> io::Result<String> {
+    loop {
+        print!("Serial number: ");
+        io::stdout().flush()?;
+        let mut serial = String::new();
+        if io::stdin().lock().read_line(&mut serial)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no serial number entered",
+            ));
+        }
+        let serial = serial.trim();
+        if !serial.is_empty() {
+            return Ok(serial.to_string());
+        }
+    }
+}
+
+async fn wait_for_board(backend: &backend::Backend, events: &mut backend::Events) -> Option<Board> {
+    for _ in 0..BOARD_TIMEOUT {
+        backend.refresh();
+        glib::timeout_future(Duration::from_secs(1)).await;
+        while let Some(Some(event)) = events.next().now_or_never() {
+            if let Event::BoardAdded(board) = event {
+                return Some(board);
+            }
+        }
+    }
+    None
+}
+
+/// Run a test plan on the connected keyboard, printing a summary and appending to the log
+pub fn run_headless_test(args: &[String]) -> glib::ExitCode {
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
+    let plan = match &options.plan {
+        Some(path) => {
+            let res = File::open(path)
+                .map_err(|err| err.to_string())
+                .and_then(|file| TestPlan::from_reader(file).map_err(|err| err.to_string()));
+            match res {
+                Ok(plan) => plan,
+                Err(err) => {
+                    eprintln!("Failed to load test plan '{}': {}", path.display(), err);
+                    return glib::ExitCode::FAILURE;
+                }
+            }
+        }
+        None => TestPlan::default(),
+    };
+
+    let log = match options.log.or_else(TestLog::default_path) {
+        Some(path) => TestLog::new(path),
+        None => {
+            eprintln!("Failed to find data directory for test log");
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
+    let serial = match options.serial.map_or_else(prompt_serial, Ok) {
+        Ok(serial) => serial,
+        Err(err) => {
+            eprintln!("Failed to read serial number: {}", err);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
+    let (backend, mut events) = daemon();
+    glib::MainContext::default().block_on(async move {
+        let board = match wait_for_board(&backend, &mut events).await {
+            Some(board) => board,
+            None => {
+                eprintln!("No keyboard found after {} seconds", BOARD_TIMEOUT);
+                return glib::ExitCode::FAILURE;
+            }
+        };
+
+        let result = run_test_plan(&backend, &board, &plan, &serial, &TestStop::default()).await;
+        print!("{}", result.summary());
+
+        if let Err(err) = log.append(&result) {
+            eprintln!(
+                "Failed to append to test log '{}': {}",
+                log.path().display(),
+                err
+            );
+            return glib::ExitCode::FAILURE;
+        }
+        println!("Logged to {}", log.path().display());
+
+        if result.success() {
+            glib::ExitCode::SUCCESS
+        } else {
+            glib::ExitCode::FAILURE
+        }
+    })
+}
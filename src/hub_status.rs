@@ -0,0 +1,130 @@
+use crate::fl;
+use backend::{Board, DerefCell};
+use cascade::cascade;
+use gtk::{
+    glib::{self, clone},
+    prelude::*,
+    subclass::prelude::*,
+};
+
+#[derive(Default)]
+pub struct HubStatusInner {
+    board: DerefCell<Board>,
+    status_list: DerefCell<gtk::ListBox>,
+    error_label: DerefCell<gtk::Label>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for HubStatusInner {
+    const NAME: &'static str = "S76HubStatus";
+    type ParentType = gtk::Box;
+    type Type = HubStatus;
+}
+
+impl ObjectImpl for HubStatusInner {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let refresh_button = cascade! {
+            gtk::Button::with_label(&fl!("button-refresh"));
+            ..set_halign(gtk::Align::Center);
+            ..connect_clicked(clone!(@weak self as self_ => move |_| self_.obj().refresh()));
+        };
+        let status_list = cascade! {
+            gtk::ListBox::new();
+            ..set_valign(gtk::Align::Start);
+            ..set_selection_mode(gtk::SelectionMode::None);
+            ..style_context().add_class("frame");
+        };
+        let error_label = cascade! {
+            gtk::Label::new(None);
+            ..set_line_wrap(true);
+            ..set_no_show_all(true);
+        };
+
+        cascade! {
+            self.obj();
+            ..set_orientation(gtk::Orientation::Vertical);
+            ..set_spacing(18);
+            ..set_halign(gtk::Align::Center);
+            ..add(&cascade! {
+                gtk::Label::new(Some(&fl!("stack-usb-hub-desc")));
+                ..set_line_wrap(true);
+                ..set_max_width_chars(100);
+                ..set_halign(gtk::Align::Center);
+            });
+            ..add(&refresh_button);
+            ..add(&error_label);
+            ..add(&status_list);
+            // Devices may have been plugged in or removed while on another page
+            ..connect_map(|obj| obj.refresh());
+            ..show_all();
+        };
+
+        self.status_list.set(status_list);
+        self.error_label.set(error_label);
+    }
+}
+
+impl WidgetImpl for HubStatusInner {}
+impl ContainerImpl for HubStatusInner {}
+impl BoxImpl for HubStatusInner {}
+
+glib::wrapper! {
+    pub struct HubStatus(ObjectSubclass<HubStatusInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl HubStatus {
+    pub fn new(board: &Board) -> Self {
+        let obj: Self = glib::Object::new();
+        obj.inner().board.set(board.clone());
+        obj
+    }
+
+    fn inner(&self) -> &HubStatusInner {
+        HubStatusInner::from_obj(self)
+    }
+
+    fn refresh(&self) {
+        let inner = self.inner();
+        inner
+            .status_list
+            .foreach(|row| inner.status_list.remove(row));
+
+        let status = match inner.board.usb_hub_status() {
+            Ok(status) => status,
+            Err(err) => {
+                error!("Failed to read USB hub status: {}", err);
+                inner
+                    .error_label
+                    .set_text(&format!("{}: {}", fl!("error-usb-hub-status"), err));
+                inner.error_label.show();
+                return;
+            }
+        };
+        inner.error_label.hide();
+
+        if status.ports.is_empty() {
+            inner.error_label.set_text(&fl!("usb-hub-not-found"));
+            inner.error_label.show();
+        }
+
+        for port in status.ports.iter() {
+            let device = match &port.device {
+                Some(device) => device.to_string(),
+                None => fl!("usb-hub-port-empty"),
+            };
+            inner.status_list.add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 24);
+                ..set_margin(8);
+                ..add(&cascade! {
+                    gtk::Label::new(Some(&port.port_desc));
+                    ..set_halign(gtk::Align::Start);
+                });
+                ..pack_end(&gtk::Label::new(Some(&device)), false, false, 0);
+                ..show_all();
+            });
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{fl, show_error_dialog, Keyboard, TestingColors};
+use backend::{Board, DerefCell, KeyEvent, KeyTestReport, KeyTester, Rgb, KEY_TEST_STUCK_AFTER};
+use cascade::cascade;
+use gtk::{
+    glib::{self, clone},
+    prelude::*,
+    subclass::prelude::*,
+};
+use once_cell::sync::Lazy;
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    time::{Duration, Instant},
+};
+
+/// Matrix polling rate while the key tester is running
+pub const KEY_TESTER_MATRIX_RATE: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+pub struct KeyTesterPageInner {
+    keyboard: DerefCell<glib::WeakRef<Keyboard>>,
+    start_button: DerefCell<gtk::Button>,
+    stop_button: DerefCell<gtk::Button>,
+    export_button: DerefCell<gtk::Button>,
+    status_label: DerefCell<gtk::Label>,
+    stuck_label: DerefCell<gtk::Label>,
+    untested_label: DerefCell<gtk::Label>,
+    tester: DerefCell<RefCell<KeyTester>>,
+    report: RefCell<KeyTestReport>,
+    running: Cell<bool>,
+    colors: RefCell<TestingColors>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for KeyTesterPageInner {
+    const NAME: &'static str = "S76KeyTesterPage";
+    type ParentType = gtk::Box;
+    type Type = KeyTesterPage;
+}
+
+impl ObjectImpl for KeyTesterPageInner {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let start_button = gtk::Button::with_label(&fl!("button-start"));
+        let stop_button = cascade! {
+            gtk::Button::with_label(&fl!("button-stop"));
+            ..set_sensitive(false);
+        };
+        let export_button = cascade! {
+            gtk::Button::with_label(&fl!("button-export"));
+            ..set_sensitive(false);
+        };
+        let status_label = gtk::Label::new(Some(&fl!("key-tester-not-running")));
+        let stuck_label = cascade! {
+            gtk::Label::new(None);
+            ..set_line_wrap(true);
+            ..set_max_width_chars(100);
+            ..set_no_show_all(true);
+        };
+        let untested_label = cascade! {
+            gtk::Label::new(None);
+            ..set_line_wrap(true);
+            ..set_max_width_chars(100);
+            ..set_no_show_all(true);
+        };
+
+        cascade! {
+            self.obj();
+            ..set_orientation(gtk::Orientation::Vertical);
+            ..set_spacing(18);
+            ..set_halign(gtk::Align::Center);
+            ..add(&cascade! {
+                gtk::Label::new(Some(&fl!("stack-key-tester-desc")));
+                ..set_line_wrap(true);
+                ..set_max_width_chars(100);
+                ..set_halign(gtk::Align::Center);
+            });
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..set_halign(gtk::Align::Center);
+                ..add(&start_button);
+                ..add(&stop_button);
+                ..add(&export_button);
+            });
+            ..add(&status_label);
+            ..add(&stuck_label);
+            ..add(&untested_label);
+            ..show_all();
+        };
+
+        self.start_button.set(start_button);
+        self.stop_button.set(stop_button);
+        self.export_button.set(export_button);
+        self.status_label.set(status_label);
+        self.stuck_label.set(stuck_label);
+        self.untested_label.set(untested_label);
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![glib::ParamSpecBoxed::builder::<TestingColors>("colors")
+                .read_only()
+                .build()]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "colors" => self.colors.borrow().to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl WidgetImpl for KeyTesterPageInner {}
+impl ContainerImpl for KeyTesterPageInner {}
+impl BoxImpl for KeyTesterPageInner {}
+
+glib::wrapper! {
+    pub struct KeyTesterPage(ObjectSubclass<KeyTesterPageInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl KeyTesterPage {
+    pub fn new(keyboard: &Keyboard, board: &Board) -> Self {
+        let obj: Self = glib::Object::new();
+        obj.inner().keyboard.set(keyboard.downgrade());
+        obj.inner().tester.set(RefCell::new(KeyTester::new(
+            board.model(),
+            board.layout(),
+            KEY_TEST_STUCK_AFTER,
+        )));
+        obj.inner()
+            .start_button
+            .connect_clicked(clone!(@weak obj => move |_| {
+                glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+                    obj.run().await;
+                }));
+            }));
+        obj.inner()
+            .stop_button
+            .connect_clicked(clone!(@weak obj => move |_| obj.inner().running.set(false)));
+        obj.inner()
+            .export_button
+            .connect_clicked(clone!(@weak obj => move |_| obj.export()));
+        obj
+    }
+
+    fn inner(&self) -> &KeyTesterPageInner {
+        KeyTesterPageInner::from_obj(self)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.inner().running.get()
+    }
+
+    pub fn handle_key_event(&self, event: &KeyEvent) {
+        if self.is_running() {
+            self.inner().tester.borrow_mut().handle_event(event);
+            self.update_report();
+        }
+    }
+
+    async fn run(&self) {
+        let inner = self.inner();
+        let keyboard = match inner.keyboard.upgrade() {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+
+        inner.tester.borrow_mut().reset();
+        inner.running.set(true);
+        inner.start_button.set_sensitive(false);
+        inner.stop_button.set_sensitive(true);
+        inner.export_button.set_sensitive(false);
+        keyboard.update_matrix_get_rate();
+
+        // Keys held before starting produce no press event, but may already be stuck.
+        // Wait for the faster matrix polling to refresh `Key::pressed` first.
+        glib::timeout_future(KEY_TESTER_MATRIX_RATE * 2).await;
+        let now = Instant::now();
+        for key in keyboard.board().keys() {
+            if key.pressed() {
+                inner.tester.borrow_mut().set_held(&key.logical_name, now);
+            }
+        }
+        self.update_report();
+
+        // Refresh periodically, so held keys are reported as stuck
+        while self.is_running() {
+            glib::timeout_future(Duration::from_secs(1)).await;
+            self.update_report();
+        }
+
+        keyboard.update_matrix_get_rate();
+
+        inner.start_button.set_sensitive(true);
+        inner.stop_button.set_sensitive(false);
+        inner.export_button.set_sensitive(true);
+        self.update_report();
+    }
+
+    fn update_report(&self) {
+        let inner = self.inner();
+        let report = inner.tester.borrow().report(Instant::now());
+
+        let status = fl!(
+            "key-tester-status",
+            tested = report.tested().to_string(),
+            total = report.keys.len().to_string()
+        );
+        if self.is_running() {
+            inner.status_label.set_text(&status);
+        } else if report.success() {
+            inner
+                .status_label
+                .set_text(&format!("{} {}", fl!("key-tester-passed"), status));
+        } else {
+            inner
+                .status_label
+                .set_text(&format!("{} {}", fl!("diagnostics-stopped"), status));
+        }
+
+        let names = |keys: Vec<String>| keys.join(", ");
+        let stuck = names(
+            report
+                .stuck()
+                .map(|key| key.physical_name.replace('\n', " "))
+                .collect(),
+        );
+        inner
+            .stuck_label
+            .set_text(&format!("{} {}", fl!("key-tester-stuck"), stuck));
+        inner.stuck_label.set_visible(!stuck.is_empty());
+        let untested = names(
+            report
+                .untested()
+                .map(|key| key.physical_name.replace('\n', " "))
+                .collect(),
+        );
+        inner
+            .untested_label
+            .set_text(&format!("{} {}", fl!("key-tester-untested"), untested));
+        inner.untested_label.set_visible(!untested.is_empty());
+
+        let mut colors = TestingColors::default();
+        for key in report.keys.iter() {
+            let color = if key.is_stuck() {
+                Rgb::new(255, 0, 0)
+            } else if key.is_tested() {
+                Rgb::new(0, 255, 0)
+            } else {
+                continue;
+            };
+            let (row, col) = key.electrical;
+            colors.0.insert((row as usize, col as usize), color);
+        }
+
+        inner.report.replace(report);
+        inner.colors.replace(colors);
+        self.notify("colors");
+    }
+
+    fn export(&self) {
+        let chooser = cascade! {
+            gtk::FileChooserNative::new(Some(&fl!("key-tester-export")), None::<&gtk::Window>, gtk::FileChooserAction::Save, Some(&fl!("button-export")), Some(&fl!("button-cancel")));
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("csv"));
+                ..add_pattern("*.csv");
+            });
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("json"));
+                ..add_pattern("*.json");
+            });
+            ..set_current_name(&format!("{}.csv", fl!("untitled-key-test")));
+            ..set_do_overwrite_confirmation(true);
+        };
+
+        if chooser.run() == gtk::ResponseType::Accept {
+            let path = chooser.filename().unwrap();
+            let report = self.inner().report.borrow();
+
+            let res = File::create(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|file| {
+                    if path.extension().map_or(false, |ext| ext == "json") {
+                        report
+                            .to_json_writer_pretty(file)
+                            .map_err(|err| err.to_string())
+                    } else {
+                        report.to_csv_writer(file).map_err(|err| err.to_string())
+                    }
+                });
+            if let Err(err) = res {
+                let window = self
+                    .toplevel()
+                    .and_then(|x| x.downcast::<gtk::Window>().ok());
+                if let Some(window) = window {
+                    show_error_dialog(&window, &fl!("error-export-key-test"), err);
+                }
+            }
+        }
+    }
+}
@@ -10,19 +10,27 @@ use gtk::{
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
-    fs::File,
+    fs::{self, File},
     pin::Pin,
     str,
+    time::Duration,
 };
 
-use crate::{show_error_dialog, Backlight, KeyboardLayer, MainWindow, Page, Picker, Testing};
-use backend::{Board, BoardEvent, DerefCell, KeyMap, Layout, Mode};
+use crate::{
+    heatmap_colors, show_error_dialog, show_warning_dialog, Backlight, Diagnostics, HubStatus,
+    KeyTesterPage, KeyboardLayer, MainWindow, Page, Picker, Testing, DIAGNOSTICS_MATRIX_RATE,
+    KEY_TESTER_MATRIX_RATE,
+};
+use backend::{
+    Board, BoardEvent, Capability, DerefCell, KeyEvent, KeyMap, KeyUsage, Layout, Mode, QmkKeymap,
+};
 use widgets::SelectedKeys;
 
 #[derive(Default)]
 pub struct KeyboardInner {
     action_group: DerefCell<gio::SimpleActionGroup>,
     invert_f_action: DerefCell<gio::SimpleAction>,
+    export_qmk_action: DerefCell<gio::SimpleAction>,
     board: DerefCell<Board>,
     page: Cell<Page>,
     picker: RefCell<WeakRef<Picker>>,
@@ -32,8 +40,18 @@ pub struct KeyboardInner {
     picker_box: DerefCell<gtk::Box>,
     backlight: DerefCell<Backlight>,
     testing: DerefCell<Option<Testing>>,
+    diagnostics: DerefCell<Option<Diagnostics>>,
+    key_tester: DerefCell<Option<KeyTesterPage>>,
+    usage: RefCell<KeyUsage>,
+    usage_unsaved: Cell<u32>,
+    record_usage_action: DerefCell<gio::SimpleAction>,
+    show_heatmap_action: DerefCell<gio::SimpleAction>,
 }
 
+/// Presses to count before writing usage to disk
+const USAGE_SAVE_INTERVAL: u32 = 100;
+const USAGE_MATRIX_RATE: Duration = Duration::from_millis(50);
+
 #[glib::object_subclass]
 impl ObjectSubclass for KeyboardInner {
     const NAME: &'static str = "S76Keyboard";
@@ -104,6 +122,29 @@ impl ObjectImpl for KeyboardInner {
             ));
         };
 
+        let export_qmk_action = cascade! {
+            gio::SimpleAction::new("export-qmk", None);
+            ..connect_activate(clone!(@weak keyboard => move |_, _|
+                keyboard.export_qmk();
+            ));
+        };
+
+        let record_usage_action = cascade! {
+            gio::SimpleAction::new_stateful("record-usage", None, false.to_variant());
+            ..connect_change_state(clone!(@weak keyboard => move |action, state| {
+                action.set_state(state.unwrap().clone());
+                keyboard.record_usage_changed();
+            }));
+        };
+
+        let show_heatmap_action = cascade! {
+            gio::SimpleAction::new_stateful("show-heatmap", None, false.to_variant());
+            ..connect_change_state(clone!(@weak keyboard => move |action, state| {
+                action.set_state(state.unwrap().clone());
+                keyboard.update_heatmap();
+            }));
+        };
+
         let action_group = cascade! {
             gio::SimpleActionGroup::new();
             ..add_action(&cascade! {
@@ -118,6 +159,7 @@ impl ObjectImpl for KeyboardInner {
                     keyboard.export();
                 ));
             });
+            ..add_action(&export_qmk_action);
             ..add_action(&cascade! {
                 gio::SimpleAction::new("reset", None);
                 ..connect_activate(clone!(@weak keyboard => move |_, _|
@@ -127,10 +169,21 @@ impl ObjectImpl for KeyboardInner {
                 ));
             });
             ..add_action(&invert_f_action);
+            ..add_action(&record_usage_action);
+            ..add_action(&show_heatmap_action);
+            ..add_action(&cascade! {
+                gio::SimpleAction::new("export-usage", None);
+                ..connect_activate(clone!(@weak keyboard => move |_, _|
+                    keyboard.export_usage();
+                ));
+            });
         };
 
         self.action_group.set(action_group);
         self.invert_f_action.set(invert_f_action);
+        self.export_qmk_action.set(export_qmk_action);
+        self.record_usage_action.set(record_usage_action);
+        self.show_heatmap_action.set(show_heatmap_action);
         self.layer_stack.set(layer_stack);
         self.stack.set(stack);
         self.picker_box.set(picker_box);
@@ -159,6 +212,10 @@ impl ObjectImpl for KeyboardInner {
             _ => unimplemented!(),
         }
     }
+
+    fn dispose(&self) {
+        self.obj().save_usage();
+    }
 }
 
 impl WidgetImpl for KeyboardInner {}
@@ -177,7 +234,11 @@ impl Keyboard {
         keyboard
             .inner()
             .invert_f_action
-            .set_enabled(!board.layout().meta.no_fn_f);
+            .set_enabled(board.capabilities().has(Capability::InvertFKeys));
+        keyboard
+            .inner()
+            .export_qmk_action
+            .set_enabled(board.capabilities().has(Capability::QmkExport));
 
         let stack = &keyboard.inner().stack;
 
@@ -213,7 +274,7 @@ impl Keyboard {
             ..connect_local("notify::is-per-key", false, clone!(@weak keyboard => @default-panic, move |_| { keyboard.update_selectable(); None }));
         };
 
-        let leds_desc = if board.layout().meta.has_per_layer {
+        let leds_desc = if board.capabilities().has(Capability::PerLayerLeds) {
             fl!("stack-leds-desc")
         } else {
             fl!("stack-leds-desc-builtin")
@@ -222,7 +283,7 @@ impl Keyboard {
         keyboard
             .bind_property("selected", &backlight, "selected")
             .build();
-        if board.layout().meta.has_brightness {
+        if board.capabilities().has(Capability::LedBrightness) {
             stack.add_titled(
                 &cascade! {
                     gtk::Box::new(gtk::Orientation::Vertical, 32);
@@ -239,6 +300,28 @@ impl Keyboard {
             );
         }
 
+        if board.capabilities().has(Capability::Matrix) {
+            let diagnostics = Diagnostics::new(&keyboard);
+            stack.add_titled(&diagnostics, "diagnostics", &fl!("stack-diagnostics"));
+            keyboard.inner().diagnostics.set(Some(diagnostics));
+            let key_tester = KeyTesterPage::new(&keyboard, &board);
+            stack.add_titled(&key_tester, "key-tester", &fl!("stack-key-tester"));
+            keyboard.inner().key_tester.set(Some(key_tester));
+        } else {
+            keyboard.inner().diagnostics.set(None);
+            keyboard.inner().key_tester.set(None);
+        }
+
+        if board.capabilities().has(Capability::UsbHub) {
+            stack.add_titled(&HubStatus::new(&board), "usb-hub", &fl!("stack-usb-hub"));
+        }
+
+        let usage = KeyUsage::load(board.model()).unwrap_or_else(|err| {
+            error!("{}", err);
+            KeyUsage::new(board.model())
+        });
+        keyboard.inner().usage.replace(usage);
+
         keyboard.inner().board.set(board);
         keyboard.inner().backlight.set(backlight);
 
@@ -256,6 +339,15 @@ impl Keyboard {
         match event {
             BoardEvent::KeymapChanged => self.queue_draw(),
             BoardEvent::LedsChanged => {}
+            BoardEvent::Key(event) => {
+                if let Some(diagnostics) = self.inner().diagnostics.as_ref() {
+                    diagnostics.handle_key_event(&event);
+                }
+                if let Some(key_tester) = self.inner().key_tester.as_ref() {
+                    key_tester.handle_key_event(&event);
+                }
+                self.record_usage(&event);
+            }
             BoardEvent::MatrixChanged => {
                 self.queue_draw();
                 if let Some(testing) = self.inner().testing.as_ref() {
@@ -322,6 +414,21 @@ impl Keyboard {
         self.board().export_keymap()
     }
 
+    /// Warn about problems `keymap` would introduce, returning `false` if the user cancels
+    pub async fn confirm_keymap(&self, keymap: &KeyMap) -> bool {
+        let current = self.board().lint_keymap();
+        let warnings = keymap
+            .lint(self.layout())
+            .into_iter()
+            .filter(|lint| !current.contains(lint))
+            .map(|lint| lint.to_string())
+            .collect::<Vec<_>>();
+        if warnings.is_empty() {
+            return true;
+        }
+        show_warning_dialog(&self.window().unwrap(), &fl!("keymap-warnings"), &warnings).await
+    }
+
     pub async fn import_keymap(&self, keymap: KeyMap) {
         // TODO: Ideally don't want this function to be O(Keys^2)
         // TODO: Make sure it doesn't panic with invalid json with invalid indexes?
@@ -335,6 +442,10 @@ impl Keyboard {
             return;
         }
 
+        if !self.confirm_keymap(&keymap).await {
+            return;
+        }
+
         let _loader = self.toplevel().and_then(|x| {
             Some(
                 x.downcast_ref::<MainWindow>()?
@@ -413,23 +524,51 @@ impl Keyboard {
 
         if chooser.run() == gtk::ResponseType::Accept {
             let path = chooser.filename().unwrap();
-            match File::open(&path) {
-                Ok(file) => match KeyMap::from_reader(file) {
+            match fs::read_to_string(path) {
+                Ok(json) => match KeyMap::try_from(json.as_str()) {
                     Ok(keymap) => {
                         let self_ = self.clone();
                         glib::MainContext::default().spawn_local(async move {
                             self_.import_keymap(keymap).await;
                         });
                     }
-                    Err(err) => {
-                        show_error_dialog(&self.window().unwrap(), "Failed to import keymap", err)
-                    }
+                    // Also accept the `keymap.json` of QMK Configurator
+                    Err(err) => match serde_json::from_str::<QmkKeymap>(&json) {
+                        Ok(qmk) => {
+                            let self_ = self.clone();
+                            glib::MainContext::default().spawn_local(async move {
+                                self_.import_qmk_keymap(qmk).await;
+                            });
+                        }
+                        Err(qmk_err) => show_error_dialog(
+                            &self.window().unwrap(),
+                            "Failed to import keymap",
+                            format!(
+                                "{}\n{}",
+                                fl!("keymap-parse-error", error = err.to_string()),
+                                fl!("qmk-keymap-parse-error", error = qmk_err.to_string())
+                            ),
+                        ),
+                    },
                 },
                 Err(err) => show_error_dialog(&self.window().unwrap(), "Failed to open file", err),
             }
         }
     }
 
+    async fn import_qmk_keymap(&self, qmk: QmkKeymap) {
+        let (keymap, issues) = qmk.to_keymap(self.layout());
+        if !issues.is_empty() {
+            let issues = issues.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            if !show_warning_dialog(&self.window().unwrap(), &fl!("qmk-keymap-issues"), &issues)
+                .await
+            {
+                return;
+            }
+        }
+        self.import_keymap(keymap).await;
+    }
+
     fn export(&self) {
         let filter = cascade! {
             gtk::FileFilter::new();
@@ -470,6 +609,186 @@ impl Keyboard {
         }
     }
 
+    fn export_qmk(&self) {
+        let chooser = cascade! {
+            gtk::FileChooserNative::new(Some(&fl!("layout-export-qmk")), None::<&gtk::Window>, gtk::FileChooserAction::Save, Some(&fl!("button-export")), Some(&fl!("button-cancel")));
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("keymap.c"));
+                ..add_pattern("*.c");
+            });
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("json"));
+                ..add_pattern("*.json");
+            });
+            ..set_current_name("keymap.c");
+            ..set_do_overwrite_confirmation(true);
+        };
+
+        if chooser.run() != gtk::ResponseType::Accept {
+            return;
+        }
+        if let Some(path) = chooser.filename() {
+            let res =
+                QmkKeymap::from_keymap(&self.export_keymap(), self.layout()).and_then(|qmk| {
+                    let data = if path.extension().map_or(false, |ext| ext == "json") {
+                        qmk.to_json_pretty()
+                    } else {
+                        qmk.to_keymap_c(self.layout())
+                    };
+                    fs::write(&path, data).map_err(|err| err.to_string())
+                });
+            if let Err(err) = res {
+                show_error_dialog(&self.window().unwrap(), &fl!("error-export-qmk"), err);
+            }
+        }
+    }
+
+    /// Matrix polling rate needed by this keyboard, even when the window is inactive
+    pub fn matrix_get_rate(&self) -> Option<Duration> {
+        if self
+            .inner()
+            .diagnostics
+            .as_ref()
+            .map_or(false, |x| x.is_running())
+        {
+            Some(DIAGNOSTICS_MATRIX_RATE)
+        } else if self
+            .inner()
+            .key_tester
+            .as_ref()
+            .map_or(false, |x| x.is_running())
+        {
+            Some(KEY_TESTER_MATRIX_RATE)
+        } else if self.is_recording_usage() {
+            Some(USAGE_MATRIX_RATE)
+        } else {
+            None
+        }
+    }
+
+    pub fn update_matrix_get_rate(&self) {
+        if let Some(window) = self
+            .toplevel()
+            .and_then(|x| x.downcast::<MainWindow>().ok())
+        {
+            window.update_matrix_get_rate();
+        }
+    }
+
+    fn is_recording_usage(&self) -> bool {
+        self.inner()
+            .record_usage_action
+            .state()
+            .and_then(|state| state.get::<bool>())
+            .unwrap_or(false)
+    }
+
+    fn is_showing_heatmap(&self) -> bool {
+        self.inner()
+            .show_heatmap_action
+            .state()
+            .and_then(|state| state.get::<bool>())
+            .unwrap_or(false)
+    }
+
+    fn record_usage_changed(&self) {
+        if !self.is_recording_usage() {
+            self.save_usage();
+        }
+        // Matrix polling has to continue while the window is inactive
+        if let Some(window) = self
+            .toplevel()
+            .and_then(|x| x.downcast::<MainWindow>().ok())
+        {
+            window.update_matrix_get_rate();
+        }
+    }
+
+    fn record_usage(&self, event: &KeyEvent) {
+        if !self.is_recording_usage() || !event.pressed {
+            return;
+        }
+
+        self.inner().usage.borrow_mut().record(event);
+
+        let unsaved = self.inner().usage_unsaved.get() + 1;
+        if unsaved >= USAGE_SAVE_INTERVAL {
+            self.save_usage();
+        } else {
+            self.inner().usage_unsaved.set(unsaved);
+        }
+
+        if self.is_showing_heatmap() {
+            self.update_heatmap();
+        }
+    }
+
+    fn save_usage(&self) {
+        if self.inner().usage_unsaved.replace(0) == 0 {
+            return;
+        }
+        if let Err(err) = self.inner().usage.borrow().save() {
+            error!("{}", err);
+        }
+    }
+
+    fn update_heatmap(&self) {
+        let heatmap = if self.is_showing_heatmap() {
+            Some(heatmap_colors(
+                &self.inner().usage.borrow(),
+                self.board().keys(),
+            ))
+        } else {
+            None
+        };
+        self.inner().layer_stack.foreach(|layer| {
+            let layer = layer.downcast_ref::<KeyboardLayer>().unwrap();
+            layer.set_heatmap(heatmap.clone());
+        });
+    }
+
+    fn export_usage(&self) {
+        let chooser = cascade! {
+            gtk::FileChooserNative::new(Some(&fl!("usage-export")), None::<&gtk::Window>, gtk::FileChooserAction::Save, Some(&fl!("button-export")), Some(&fl!("button-cancel")));
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("csv"));
+                ..add_pattern("*.csv");
+            });
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("json"));
+                ..add_pattern("*.json");
+            });
+            ..set_current_name(&format!("{}.csv", fl!("untitled-usage")));
+            ..set_do_overwrite_confirmation(true);
+        };
+
+        if chooser.run() == gtk::ResponseType::Accept {
+            let path = chooser.filename().unwrap();
+            let usage = self.inner().usage.borrow();
+
+            let res = File::create(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|file| {
+                    if path.extension().map_or(false, |ext| ext == "json") {
+                        usage
+                            .to_json_writer_pretty(self.layout(), file)
+                            .map_err(|err| err.to_string())
+                    } else {
+                        usage
+                            .to_csv_writer(self.layout(), file)
+                            .map_err(|err| err.to_string())
+                    }
+                });
+            if let Err(err) = res {
+                show_error_dialog(&self.window().unwrap(), &fl!("error-export-usage"), err);
+            }
+        }
+    }
+
     pub async fn reset(&self) {
         self.import_keymap(self.layout().default.clone()).await;
     }
@@ -551,6 +870,16 @@ impl Keyboard {
                     .flags(glib::BindingFlags::SYNC_CREATE)
                     .build();
             }
+            if let Some(diagnostics) = &*self.inner().diagnostics {
+                diagnostics
+                    .bind_property("colors", &keyboard_layer, "testing-colors")
+                    .build();
+            }
+            if let Some(key_tester) = &*self.inner().key_tester {
+                key_tester
+                    .bind_property("colors", &keyboard_layer, "testing-colors")
+                    .build();
+            }
             layer_stack.add_titled(&keyboard_layer, &page.name(), &page.name());
 
             self.inner().action_group.add_action(&cascade! {
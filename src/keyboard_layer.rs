@@ -3,17 +3,133 @@ use gtk::{cairo, gdk, glib, pango, prelude::*, subclass::prelude::*};
 use once_cell::unsync::OnceCell;
 use std::{
     cell::{Cell, RefCell},
+    collections::HashMap,
     f64::consts::PI,
+    rc::Rc,
 };
 
 use crate::{Page, TestingColors};
-use backend::{Board, DerefCell, Key, Rect, Rgb};
+use backend::{Board, DerefCell, Key, KeyUsage, Rect, Rgb};
 use widgets::SelectedKeys;
 
 const SCALE: f64 = 64.;
 const MARGIN: f64 = 2.;
 const RADIUS: f64 = 4.;
 const HALF_KEYBOARD_VSPACING: f64 = 16.;
+/// Space between legends and the edge of a keycap
+const LEGEND_PADDING: f64 = 4.;
+
+/// Where a key is drawn, in widget coordinates
+struct KeyShape {
+    rect: Rect,
+    /// Second rectangle, for keys like an ISO enter
+    rect2: Option<Rect>,
+    /// Clockwise rotation in radians
+    angle: f64,
+    /// Point `rect` and `rect2` are rotated about
+    origin: (f64, f64),
+}
+
+impl KeyShape {
+    fn rects(&self) -> impl Iterator<Item = &Rect> {
+        std::iter::once(&self.rect).chain(self.rect2.as_ref())
+    }
+
+    fn translate(&mut self, dx: f64, dy: f64) {
+        self.rect.x += dx;
+        self.rect.y += dy;
+        if let Some(rect2) = &mut self.rect2 {
+            rect2.x += dx;
+            rect2.y += dy;
+        }
+        self.origin.0 += dx;
+        self.origin.1 += dy;
+    }
+
+    fn rotate_point(&self, angle: f64, x: f64, y: f64) -> (f64, f64) {
+        let (sin, cos) = angle.sin_cos();
+        let (dx, dy) = (x - self.origin.0, y - self.origin.1);
+        (
+            self.origin.0 + dx * cos - dy * sin,
+            self.origin.1 + dx * sin + dy * cos,
+        )
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        let (x, y) = self.rotate_point(-self.angle, x, y);
+        self.rects().any(|rect| rect.contains(x, y))
+    }
+
+    /// Smallest unrotated rectangle containing the key
+    fn bounds(&self) -> Rect {
+        let points = self
+            .rects()
+            .flat_map(|r| {
+                [
+                    (r.x, r.y),
+                    (r.x + r.w, r.y),
+                    (r.x, r.y + r.h),
+                    (r.x + r.w, r.y + r.h),
+                ]
+            })
+            .map(|(x, y)| self.rotate_point(self.angle, x, y))
+            .collect::<Vec<_>>();
+        let min_x = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+        let min_y = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let max_x = points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+        let max_y = points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+        Rect::new(min_x, min_y, max_x - min_x, max_y - min_y)
+    }
+}
+
+fn rounded_rect(cr: &cairo::Context, rect: &Rect) {
+    let Rect { x, y, w, h } = *rect;
+    cr.new_sub_path();
+    cr.arc(x + w - RADIUS, y + RADIUS, RADIUS, -0.5 * PI, 0.);
+    cr.arc(x + w - RADIUS, y + h - RADIUS, RADIUS, 0., 0.5 * PI);
+    cr.arc(x + RADIUS, y + h - RADIUS, RADIUS, 0.5 * PI, PI);
+    cr.arc(x + RADIUS, y + RADIUS, RADIUS, PI, 1.5 * PI);
+    cr.close_path();
+}
+
+/// Blue for unused keys, through yellow, to red for the most used key
+fn heat_color(count: u64, max: u64) -> Rgb {
+    const STOPS: [Rgb; 3] = [
+        Rgb::new(0x30, 0x60, 0xc0),
+        Rgb::new(0xf0, 0xd0, 0x40),
+        Rgb::new(0xe0, 0x30, 0x20),
+    ];
+
+    // Logarithmic, so a few very common keys don't wash out the rest
+    let t = if max == 0 {
+        0.
+    } else {
+        (count as f64).ln_1p() / (max as f64).ln_1p()
+    };
+    let (a, b, t) = if t < 0.5 {
+        (STOPS[0], STOPS[1], t * 2.)
+    } else {
+        (STOPS[1], STOPS[2], (t - 0.5) * 2.)
+    };
+    let lerp = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    Rgb::new(lerp(a.r, b.r), lerp(a.g, b.g), lerp(a.b, b.b))
+}
+
+/// Heatmap colors by logical name, shared between layers
+pub type Heatmap = Rc<HashMap<String, Rgb>>;
+
+/// Color each of `keys` by its press count in `usage`
+pub fn heatmap_colors(usage: &KeyUsage, keys: &[Key]) -> Heatmap {
+    let max = usage.max();
+    let colors = keys
+        .iter()
+        .map(|k| {
+            let color = heat_color(usage.count(&k.logical_name), max);
+            (k.logical_name.clone(), color)
+        })
+        .collect();
+    Rc::new(colors)
+}
 
 #[derive(Default)]
 pub struct KeyboardLayerInner {
@@ -25,6 +141,7 @@ pub struct KeyboardLayerInner {
     wide_height: OnceCell<i32>,
     narrow_width: OnceCell<i32>,
     testing_colors: RefCell<TestingColors>,
+    heatmap: RefCell<Option<Heatmap>>,
 }
 
 #[glib::object_subclass]
@@ -83,17 +200,21 @@ impl WidgetImpl for KeyboardLayerInner {
         let selected = Rgb::new(0xfb, 0xb8, 0x6c).to_floats();
 
         let testing_colors = self.testing_colors.borrow();
+        let heatmap = self.heatmap.borrow();
 
         for (i, k) in self.obj().keys().iter().enumerate() {
-            let Rect { x, y, w, h } = self.obj().key_position(k);
+            let shape = self.obj().key_shape(k);
+            let Rect { x, y, w, h } = shape.rect;
 
-            let mut bg = if let Some(rgb) = testing_colors
+            let mut bg = if let Some(rgb) = heatmap.as_ref().and_then(|x| x.get(&k.logical_name)) {
+                *rgb
+            } else if let Some(rgb) = testing_colors
                 .0
                 .get(&(k.electrical.0 as usize, k.electrical.1 as usize))
             {
-                rgb
+                *rgb
             } else {
-                &k.background_color
+                k.background_color
             }
             .to_floats();
 
@@ -117,35 +238,84 @@ impl WidgetImpl for KeyboardLayerInner {
                 }
             }
 
-            // Rounded rectangle
-            cr.new_sub_path();
-            cr.arc(x + w - RADIUS, y + RADIUS, RADIUS, -0.5 * PI, 0.);
-            cr.arc(x + w - RADIUS, y + h - RADIUS, RADIUS, 0., 0.5 * PI);
-            cr.arc(x + RADIUS, y + h - RADIUS, RADIUS, 0.5 * PI, PI);
-            cr.arc(x + RADIUS, y + RADIUS, RADIUS, PI, 1.5 * PI);
-            cr.close_path();
+            cr.save().unwrap();
+            if shape.angle != 0. {
+                cr.translate(shape.origin.0, shape.origin.1);
+                cr.rotate(shape.angle);
+                cr.translate(-shape.origin.0, -shape.origin.1);
+            }
 
-            cr.set_source_rgba(bg.0, bg.1, bg.2, bg_alpha);
-            cr.fill_preserve().unwrap();
+            // Rounded rectangle, or the union of two for keys like an ISO enter
+            for rect in shape.rects() {
+                rounded_rect(cr, rect);
+            }
 
-            if self.selectable.get() && self.obj().selected().contains(&i) {
+            let is_selected = self.selectable.get() && self.obj().selected().contains(&i);
+            if is_selected && shape.rect2.is_some() {
+                // Fill over the stroke, so only the outline of the union is visible
                 cr.set_source_rgb(selected.0, selected.1, selected.2);
-                cr.set_line_width(4.);
-                cr.stroke().unwrap();
-            }
+                cr.set_line_width(8.);
+                cr.stroke_preserve().unwrap();
+                cr.set_source_rgba(bg.0, bg.1, bg.2, bg_alpha);
+                cr.fill().unwrap();
+            } else {
+                cr.set_source_rgba(bg.0, bg.1, bg.2, bg_alpha);
+                cr.fill_preserve().unwrap();
 
-            // Draw label
-            let text = self.obj().page().get_label(k);
-            let layout = cascade! {
-                self.obj().create_pango_layout(Some(&text));
-                ..set_width((w * pango::SCALE as f64) as i32);
-                ..set_alignment(pango::Alignment::Center);
-            };
-            let text_height = layout.pixel_size().1 as f64;
+                if is_selected {
+                    cr.set_source_rgb(selected.0, selected.1, selected.2);
+                    cr.set_line_width(4.);
+                    cr.stroke().unwrap();
+                }
+            }
             cr.new_path();
-            cr.move_to(x, y + (h - text_height) / 2.);
             cr.set_source_rgba(fg.0, fg.1, fg.2, text_alpha);
-            pangocairo::show_layout(cr, &layout);
+
+            let page = self.obj().page();
+            if page == Page::Keycaps && k.legends.iter().any(|x| !x.is_empty()) {
+                // Draw each legend where it is on the keycap
+                for (i, legend) in k.legends.iter().enumerate() {
+                    // Front legends are shown along the bottom, if nothing else is there
+                    let i = match i {
+                        9..=11 if k.legends[i - 3].is_empty() => i - 3,
+                        9..=11 => continue,
+                        _ => i,
+                    };
+                    if legend.is_empty() {
+                        continue;
+                    }
+                    let alignment = match i % 3 {
+                        0 => pango::Alignment::Left,
+                        1 => pango::Alignment::Center,
+                        _ => pango::Alignment::Right,
+                    };
+                    let layout = cascade! {
+                        self.obj().create_pango_layout(Some(legend));
+                        ..set_width(((w - LEGEND_PADDING * 2.) * pango::SCALE as f64) as i32);
+                        ..set_alignment(alignment);
+                    };
+                    let text_height = layout.pixel_size().1 as f64;
+                    let text_y = match i / 3 {
+                        0 => y + LEGEND_PADDING,
+                        1 => y + (h - text_height) / 2.,
+                        _ => y + h - text_height - LEGEND_PADDING,
+                    };
+                    cr.move_to(x + LEGEND_PADDING, text_y);
+                    pangocairo::show_layout(cr, &layout);
+                }
+            } else {
+                // Draw label
+                let text = page.get_label(k);
+                let layout = cascade! {
+                    self.obj().create_pango_layout(Some(&text));
+                    ..set_width((w * pango::SCALE as f64) as i32);
+                    ..set_alignment(pango::Alignment::Center);
+                };
+                let text_height = layout.pixel_size().1 as f64;
+                cr.move_to(x, y + (h - text_height) / 2.);
+                pangocairo::show_layout(cr, &layout);
+            }
+            cr.restore().unwrap();
         }
 
         Inhibit(false)
@@ -163,7 +333,7 @@ impl WidgetImpl for KeyboardLayerInner {
             .obj()
             .keys()
             .iter()
-            .position(|k| self.obj().key_position(k).contains(pos.0, pos.1));
+            .position(|k| self.obj().key_shape(k).contains(pos.0, pos.1));
 
         if let Some(pressed) = pressed {
             let shift = evt.state().contains(gdk::ModifierType::SHIFT_MASK);
@@ -255,6 +425,12 @@ impl KeyboardLayer {
         self.notify("selected");
     }
 
+    /// Color keys by press count, instead of their normal background color
+    pub fn set_heatmap(&self, heatmap: Option<Heatmap>) {
+        self.inner().heatmap.replace(heatmap);
+        self.queue_draw();
+    }
+
     pub fn set_selectable(&self, selectable: bool) {
         self.inner().selectable.set(selectable);
         self.queue_draw();
@@ -266,21 +442,21 @@ impl KeyboardLayer {
 
     fn wide_width(&self) -> i32 {
         self.keys_maximize(&self.inner().wide_width, |k| {
-            let pos = self.key_position_wide(k);
+            let pos = self.key_shape_wide(k).bounds();
             (pos.x + pos.w) as i32
         })
     }
 
     fn wide_height(&self) -> i32 {
         self.keys_maximize(&self.inner().wide_height, |k| {
-            let pos = self.key_position_wide(k);
+            let pos = self.key_shape_wide(k).bounds();
             (pos.y + pos.h + 4.) as i32
         })
     }
 
     fn narrow_width(&self) -> i32 {
         self.keys_maximize(&self.inner().narrow_width, |k| {
-            let mut pos = self.key_position_wide(k);
+            let mut pos = self.key_shape_wide(k).bounds();
             let width = self.wide_width() as f64 / 2.;
             if pos.x + pos.w / 2. > width {
                 pos.x -= width;
@@ -293,32 +469,44 @@ impl KeyboardLayer {
         self.wide_height() * 2 + HALF_KEYBOARD_VSPACING as i32
     }
 
-    fn key_position_wide(&self, k: &Key) -> Rect {
+    fn scale_rect(rect: &Rect) -> Rect {
         Rect {
-            x: (k.physical.x * SCALE) + MARGIN,
-            y: -(k.physical.y * SCALE) + MARGIN,
-            w: (k.physical.w * SCALE) - MARGIN * 2.,
-            h: (k.physical.h * SCALE) - MARGIN * 2.,
+            x: (rect.x * SCALE) + MARGIN,
+            y: -(rect.y * SCALE) + MARGIN,
+            w: (rect.w * SCALE) - MARGIN * 2.,
+            h: (rect.h * SCALE) - MARGIN * 2.,
+        }
+    }
+
+    fn key_shape_wide(&self, k: &Key) -> KeyShape {
+        KeyShape {
+            rect: Self::scale_rect(&k.physical),
+            rect2: k.physical2.as_ref().map(Self::scale_rect),
+            angle: k.rotation.angle.to_radians(),
+            origin: (k.rotation.x * SCALE, -(k.rotation.y * SCALE)),
         }
     }
 
-    fn key_position_narrow(&self, k: &Key) -> Rect {
-        let mut rect = self.key_position_wide(k);
+    fn key_shape_narrow(&self, k: &Key) -> KeyShape {
+        let mut shape = self.key_shape_wide(k);
+        let bounds = shape.bounds();
         let width = self.wide_width() as f64 / 2.;
-        if rect.x + rect.w / 2. > width {
-            rect.x -= (self.wide_width() - self.narrow_width()) as f64;
-            rect.y += self.wide_height() as f64 + HALF_KEYBOARD_VSPACING;
+        if bounds.x + bounds.w / 2. > width {
+            shape.translate(
+                -(self.wide_width() - self.narrow_width()) as f64,
+                self.wide_height() as f64 + HALF_KEYBOARD_VSPACING,
+            );
         }
-        rect
+        shape
     }
 
-    fn key_position(&self, k: &Key) -> Rect {
-        let (mut pos, width) = if self.allocated_width() < self.wide_width() {
-            (self.key_position_narrow(k), self.narrow_width())
+    fn key_shape(&self, k: &Key) -> KeyShape {
+        let (mut shape, width) = if self.allocated_width() < self.wide_width() {
+            (self.key_shape_narrow(k), self.narrow_width())
         } else {
-            (self.key_position_wide(k), self.wide_width())
+            (self.key_shape_wide(k), self.wide_width())
         };
-        pos.x += (self.allocated_width() - width) as f64 / 2.;
-        pos
+        shape.translate((self.allocated_width() - width) as f64 / 2., 0.);
+        shape
     }
 }
@@ -9,7 +9,11 @@ use std::env;
 mod about_dialog;
 mod backlight;
 mod configurator_app;
+mod diagnostics;
 mod error_dialog;
+mod headless_test;
+mod hub_status;
+mod key_tester;
 mod keyboard;
 mod keyboard_layer;
 mod localize;
@@ -23,8 +27,9 @@ use gtk::glib;
 
 pub use self::configurator_app::run;
 use self::{
-    backlight::*, configurator_app::*, error_dialog::*, keyboard::*, keyboard_layer::*,
-    main_window::*, page::*, picker::*, shortcuts_window::*, testing::*,
+    backlight::*, configurator_app::*, diagnostics::*, error_dialog::*, headless_test::*,
+    hub_status::*, key_tester::*, keyboard::*, keyboard_layer::*, main_window::*, page::*,
+    picker::*, shortcuts_window::*, testing::*,
 };
 
 fn main() -> glib::ExitCode {
@@ -40,6 +45,8 @@ fn main() -> glib::ExitCode {
     for arg in args.iter().skip(1) {
         if arg.as_str() == "--daemon" {
             backend::run_daemon();
+        } else if arg.as_str() == "--headless-test" {
+            return run_headless_test(&args[1..]);
         }
     }
 
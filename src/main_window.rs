@@ -9,13 +9,19 @@ use gtk::{
     subclass::prelude::*,
 };
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    path::Path,
     sync::atomic::{AtomicBool, Ordering},
     time::Duration,
 };
 
-use crate::{shortcuts_window, ConfiguratorApp, Keyboard, KeyboardLayer, Page, Picker};
-use backend::{Backend, Board, BoardId, Bootloaded, DerefCell};
+use crate::{
+    shortcuts_window, show_error_dialog, ConfiguratorApp, Keyboard, KeyboardLayer, Page, Picker,
+};
+use backend::{
+    Backend, Board, BoardId, Bootloaded, Capability, DerefCell, DfuProgrammer, FlashEvent,
+    FlashStep, Fwupd, FwupdDevice, FwupdEvent,
+};
 
 pub struct Loader(MainWindow, gtk::Box);
 
@@ -32,6 +38,14 @@ impl Drop for Loader {
     }
 }
 
+struct KeyboardRow {
+    keyboard: Keyboard,
+    row: gtk::Box,
+    configure_button: gtk::Button,
+    /// Shown when fwupd has a firmware update for the keyboard
+    update_button: gtk::Button,
+}
+
 #[derive(Default)]
 pub struct MainWindowInner {
     backend: DerefCell<Backend>,
@@ -45,8 +59,12 @@ pub struct MainWindowInner {
     load_revealer: DerefCell<gtk::Revealer>,
     picker: DerefCell<Picker>,
     stack: DerefCell<gtk::Stack>,
-    keyboards: RefCell<Vec<(Keyboard, gtk::Box)>>,
+    keyboards: RefCell<Vec<KeyboardRow>>,
+    fwupd_devices: RefCell<Vec<FwupdDevice>>,
     board_loading: RefCell<Option<Loader>>,
+    flash_loading: RefCell<Option<Loader>>,
+    /// Set when a flash or firmware update starts, before its first progress event
+    flashing: Cell<bool>,
     board_list_stack: DerefCell<gtk::Stack>,
     is_testing_mode: DerefCell<bool>,
 }
@@ -96,9 +114,16 @@ impl ObjectImpl for MainWindowInner {
                 gio::Menu::new();
                 ..append(Some(&fl!("layout-import")), Some("kbd.import"));
                 ..append(Some(&fl!("layout-export")), Some("kbd.export"));
+                ..append(Some(&fl!("layout-export-qmk")), Some("kbd.export-qmk"));
                 ..append(Some(&fl!("layout-reset")), Some("kbd.reset"));
                 ..append(Some(&fl!("layout-invert-f-keys")), Some("kbd.invert-f-keys"));
             });
+            ..append_section(None, &cascade! {
+                gio::Menu::new();
+                ..append(Some(&fl!("usage-record")), Some("kbd.record-usage"));
+                ..append(Some(&fl!("usage-show-heatmap")), Some("kbd.show-heatmap"));
+                ..append(Some(&fl!("usage-export")), Some("kbd.export-usage"));
+            });
             ..append_section(None, &cascade! {
                 gio::Menu::new();
                 ..append(Some(&fl!("show-help-overlay")), Some("win.show-help-overlay"));
@@ -242,22 +267,7 @@ impl MainWindow {
         window.handle_backend_event_stream(receiver, false);
         backend.refresh();
 
-        // Refresh key matrix only when window is visible
-        backend.set_matrix_get_rate(if window.is_active() {
-            Some(Duration::from_millis(50))
-        } else {
-            None
-        });
-        window.connect_is_active_notify(|window| {
-            window
-                .inner()
-                .backend
-                .set_matrix_get_rate(if window.is_active() {
-                    Some(Duration::from_millis(50))
-                } else {
-                    None
-                });
-        });
+        window.connect_is_active_notify(|window| window.update_matrix_get_rate());
 
         let phony_board_names = app.phony_board_names().to_vec();
         if !phony_board_names.is_empty() {
@@ -271,6 +281,7 @@ impl MainWindow {
         }
 
         window.inner().backend.set(backend);
+        window.update_matrix_get_rate();
         window.inner().is_testing_mode.set(is_testing_mode);
         glib::timeout_add_seconds_local(
             1,
@@ -293,6 +304,10 @@ impl MainWindow {
         MainWindowInner::from_obj(self)
     }
 
+    pub fn backend(&self) -> &Backend {
+        &self.inner().backend
+    }
+
     fn handle_backend_event_stream(&self, mut receiver: backend::Events, is_dummy: bool) {
         let window_weak = self.downgrade();
         glib::MainContext::default().spawn_local(async move {
@@ -305,10 +320,7 @@ impl MainWindow {
     fn handle_backend_event(&self, event: backend::Event, is_dummy: bool) {
         match event {
             // Ignore these events for dummy; only use for real keyboard
-            backend::Event::BoardLoading
-            | backend::Event::BoardLoadingDone
-            | backend::Event::BoardNotUpdated
-                if is_dummy => {}
+            backend::Event::BoardLoading | backend::Event::BoardLoadingDone if is_dummy => {}
             backend::Event::BoardLoading => {
                 info!("loading");
                 let loader = self.display_loader(&fl!("loading"));
@@ -316,12 +328,7 @@ impl MainWindow {
             }
             backend::Event::BoardLoadingDone => {
                 self.inner().board_loading.borrow_mut().take();
-            }
-            backend::Event::BoardNotUpdated => {
-                info!("board not updated");
-                self.inner().board_loading.borrow_mut().take();
-                let loader = self.display_loader(&fl!("firmware-update-required"));
-                *self.inner().board_loading.borrow_mut() = Some(loader);
+                self.check_firmware_updates();
             }
             backend::Event::BoardAdded(board) => {
                 self.add_keyboard(board);
@@ -330,9 +337,9 @@ impl MainWindow {
                 if let backend::BoardEvent::MatrixChanged = &event {
                     self.inner().keyboard_box.queue_draw();
                 }
-                for (keyboard, _) in &*self.inner().keyboards.borrow() {
-                    if keyboard.board().board() == id {
-                        keyboard.handle_backend_event(event);
+                for row in &*self.inner().keyboards.borrow() {
+                    if row.keyboard.board().board() == id {
+                        row.keyboard.handle_backend_event(event);
                         break;
                     }
                 }
@@ -346,6 +353,8 @@ impl MainWindow {
             backend::Event::BootloadedRemoved => {
                 self.remove_flash_menu();
             }
+            backend::Event::Flash(event) => self.handle_flash_event(event),
+            backend::Event::Fwupd(event) => self.handle_fwupd_event(event),
         }
     }
 
@@ -398,19 +407,20 @@ impl MainWindow {
             ..set_attributes(Some(&attr_list));
         };
         let window = self;
-        let button = if !board.is_updated() && *self.inner().is_testing_mode {
-            cascade! {
-              gtk::Button::with_label(&fl!("firmware-update-required"));
-              ..set_halign(gtk::Align::Center);
-            }
-        } else {
-            cascade! {
-                gtk::Button::with_label(&fl!("button-configure"));
-                ..set_halign(gtk::Align::Center);
-                ..connect_clicked(clone!(@weak window, @weak keyboard => move |_| {
-                    window.show_keyboard(&keyboard);
-                }));
-            }
+        let button = cascade! {
+            gtk::Button::with_label(&fl!("button-configure"));
+            ..set_halign(gtk::Align::Center);
+            ..connect_clicked(clone!(@weak window, @weak keyboard => move |_| {
+                window.show_keyboard(&keyboard);
+            }));
+        };
+        let update_button = cascade! {
+            gtk::Button::new();
+            ..set_halign(gtk::Align::Center);
+            ..set_no_show_all(true);
+            ..connect_clicked(clone!(@weak window, @weak keyboard => move |_| {
+                window.update_firmware(keyboard.board());
+            }));
         };
 
         let keyboard_layer = cascade! {
@@ -423,14 +433,16 @@ impl MainWindow {
             ..add(&label);
             ..add(&keyboard_layer);
             ..add(&button);
+            ..add(&update_button);
             ..show_all();
         };
         self.inner().keyboard_box.add(&row);
 
-        if !board.has_keymap() {
+        if !board.capabilities().has(Capability::Keymap) {
             button.hide();
             let label = cascade! {
                 gtk::Label::new(Some(&fl!("firmware-version", version = board.version())));
+                ..set_tooltip_text(board.capabilities().reason(Capability::Keymap));
                 ..set_attributes(Some(&cascade! {
                     pango::AttrList::new();
                     ..insert(pango::AttrColor::new_foreground(65535, 0, 0));
@@ -441,7 +453,13 @@ impl MainWindow {
         }
 
         self.inner().stack.add(&keyboard);
-        self.inner().keyboards.borrow_mut().push((keyboard, row));
+        self.inner().keyboards.borrow_mut().push(KeyboardRow {
+            keyboard,
+            row,
+            configure_button: button,
+            update_button,
+        });
+        self.show_firmware_updates();
 
         self.inner()
             .board_list_stack
@@ -450,8 +468,8 @@ impl MainWindow {
 
     fn remove_keyboard(&self, id: BoardId) {
         let mut boards = self.inner().keyboards.borrow_mut();
-        if let Some(idx) = boards.iter().position(|(kb, _)| kb.board().board() == id) {
-            let (keyboard, row) = boards.remove(idx);
+        if let Some(idx) = boards.iter().position(|x| x.keyboard.board().board() == id) {
+            let KeyboardRow { keyboard, row, .. } = boards.remove(idx);
             if self.inner().stack.visible_child().as_ref() == Some(keyboard.upcast_ref()) {
                 // Set picker keyboard to to `None` before destroying keyboard widget
                 self.inner().picker.set_keyboard(None);
@@ -459,6 +477,8 @@ impl MainWindow {
             }
             self.inner().stack.remove(&keyboard);
             self.inner().keyboard_box.remove(&row);
+            drop(boards);
+            self.update_matrix_get_rate();
 
             if self.num_keyboards() == 0 {
                 self.inner()
@@ -504,6 +524,156 @@ impl MainWindow {
         self.inner().flash_button.set_visible(false);
     }
 
+    pub fn flash_firmware(&self, target: Bootloaded, path: &Path) {
+        if self.inner().flashing.replace(true) {
+            return;
+        }
+        self.inner().flash_button.set_sensitive(false);
+        self.inner()
+            .backend
+            .flash(Box::<DfuProgrammer>::default(), target, path.to_owned());
+    }
+
+    fn handle_flash_event(&self, event: FlashEvent) {
+        let inner = self.inner();
+        // Drop the previous loader first, so only one is shown
+        inner.flash_loading.borrow_mut().take();
+        match event {
+            FlashEvent::Step { step, index, total } => {
+                let step = match step {
+                    FlashStep::Validate => fl!("flash-step-validate"),
+                    FlashStep::Erase => fl!("flash-step-erase"),
+                    FlashStep::Flash => fl!("flash-step-flash"),
+                    FlashStep::Launch => fl!("flash-step-launch"),
+                };
+                let text = fl!(
+                    "flash-step",
+                    step = step,
+                    index = index.to_string(),
+                    total = total.to_string()
+                );
+                *inner.flash_loading.borrow_mut() = Some(self.display_loader(&text));
+            }
+            FlashEvent::Done => {
+                inner.flashing.set(false);
+                inner.flash_button.set_sensitive(true);
+                inner.backend.check_for_bootloader();
+                inner.backend.refresh();
+            }
+            FlashEvent::Failed(err) => {
+                inner.flashing.set(false);
+                inner.flash_button.set_sensitive(true);
+                show_error_dialog(self, &fl!("error-flash"), err);
+            }
+        }
+    }
+
+    /// Ask fwupd for updates in the background, if any keyboard could have one
+    fn check_firmware_updates(&self) {
+        let has_fwupd = self.inner().keyboards.borrow().iter().any(|row| {
+            let board = row.keyboard.board();
+            !board.is_fake() && board.capabilities().has(Capability::Fwupd)
+        });
+        if !has_fwupd {
+            return;
+        }
+        if let Some(fwupd) = fwupd() {
+            self.inner().backend.fwupd_check(fwupd);
+        }
+    }
+
+    /// Show an update button on each keyboard fwupd has an update for
+    fn show_firmware_updates(&self) {
+        let inner = self.inner();
+        let devices = inner.fwupd_devices.borrow();
+        for row in &*inner.keyboards.borrow() {
+            let board = row.keyboard.board();
+            let update_version = devices
+                .iter()
+                .find(|x| x.board_model().as_deref() == Some(board.model()))
+                .and_then(|x| x.update_version.as_ref());
+            let version = match update_version {
+                Some(version) => version,
+                None => {
+                    row.update_button.hide();
+                    row.configure_button.set_sensitive(true);
+                    row.configure_button.set_label(&fl!("button-configure"));
+                    continue;
+                }
+            };
+            row.update_button
+                .set_label(&fl!("firmware-update", version = version.as_str()));
+            row.update_button.show();
+            if *inner.is_testing_mode {
+                row.configure_button.set_sensitive(false);
+                row.configure_button
+                    .set_label(&fl!("firmware-update-required"));
+            }
+        }
+    }
+
+    fn update_firmware(&self, board: &Board) {
+        let inner = self.inner();
+        if inner.flashing.get() {
+            return;
+        }
+        let device = inner
+            .fwupd_devices
+            .borrow()
+            .iter()
+            .find(|x| x.board_model().as_deref() == Some(board.model()))
+            .cloned();
+        if let (Some(device), Some(fwupd)) = (device, fwupd()) {
+            inner.flashing.set(true);
+            for row in &*inner.keyboards.borrow() {
+                row.update_button.set_sensitive(false);
+            }
+            inner.backend.fwupd_update(fwupd, device);
+        }
+    }
+
+    fn handle_fwupd_event(&self, event: FwupdEvent) {
+        let inner = self.inner();
+        match event {
+            FwupdEvent::Devices(Ok(devices)) => {
+                *inner.fwupd_devices.borrow_mut() = devices;
+                self.show_firmware_updates();
+            }
+            // Already logged by backend
+            FwupdEvent::Devices(Err(_)) => {}
+            FwupdEvent::Progress(percentage) => {
+                let text = fl!("firmware-update-progress", percentage = percentage);
+                inner.flash_loading.borrow_mut().take();
+                *inner.flash_loading.borrow_mut() = Some(self.display_loader(&text));
+            }
+            FwupdEvent::Done | FwupdEvent::Failed(_) => {
+                inner.flashing.set(false);
+                inner.flash_loading.borrow_mut().take();
+                for row in &*inner.keyboards.borrow() {
+                    row.update_button.set_sensitive(true);
+                }
+                if let FwupdEvent::Failed(err) = event {
+                    show_error_dialog(self, &fl!("error-fwupd-update"), err);
+                }
+                inner.backend.refresh();
+                self.check_firmware_updates();
+            }
+        }
+    }
+
+    /// Refresh key matrix only when window is visible, or a keyboard needs it in the background
+    pub fn update_matrix_get_rate(&self) {
+        let rate = self
+            .inner()
+            .keyboards
+            .borrow()
+            .iter()
+            .filter_map(|row| row.keyboard.matrix_get_rate())
+            .chain(self.is_active().then(|| Duration::from_millis(50)))
+            .min();
+        self.inner().backend.set_matrix_get_rate(rate);
+    }
+
     fn num_keyboards(&self) -> usize {
         let mut count = 0;
         self.inner().keyboard_box.foreach(|_| count += 1);
@@ -530,7 +700,7 @@ impl MainWindow {
 }
 
 #[cfg(target_os = "linux")]
-fn daemon() -> (Backend, backend::Events) {
+pub fn daemon() -> (Backend, backend::Events) {
     if unsafe { libc::geteuid() == 0 } {
         info!("Already running as root");
         Backend::new()
@@ -542,6 +712,17 @@ fn daemon() -> (Backend, backend::Events) {
 }
 
 #[cfg(not(target_os = "linux"))]
-fn daemon() -> (Backend, backend::Events) {
+pub fn daemon() -> (Backend, backend::Events) {
     Backend::new().expect("Failed to create server")
 }
+
+#[cfg(target_os = "linux")]
+fn fwupd() -> Option<Box<dyn Fwupd>> {
+    Some(Box::<backend::FwupdDbus>::default())
+}
+
+// fwupd only runs on Linux
+#[cfg(not(target_os = "linux"))]
+fn fwupd() -> Option<Box<dyn Fwupd>> {
+    None
+}
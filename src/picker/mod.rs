@@ -128,14 +128,27 @@ impl Picker {
         let layer = kb.layer();
 
         if let Some(layer) = layer {
-            let futures = FuturesUnordered::new();
+            let mut keymap = kb.export_keymap();
             for i in kb.selected().iter() {
-                let i = *i;
-                futures.push(clone!(@strong kb, @strong name => async move {
-                    kb.keymap_set(i, layer, &name).await;
-                }));
+                let key = &kb.board().keys()[*i];
+                if let Some(scancodes) = keymap.map.get_mut(&key.logical_name) {
+                    scancodes[layer] = name.clone();
+                }
             }
-            glib::MainContext::default().spawn_local(async { futures.collect::<()>().await });
+
+            glib::MainContext::default().spawn_local(async move {
+                if !kb.confirm_keymap(&keymap).await {
+                    return;
+                }
+                let futures = FuturesUnordered::new();
+                for i in kb.selected().iter() {
+                    let i = *i;
+                    futures.push(clone!(@strong kb, @strong name => async move {
+                        kb.keymap_set(i, layer, &name).await;
+                    }));
+                }
+                futures.collect::<()>().await
+            });
         }
     }
 }
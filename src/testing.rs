@@ -1,55 +1,23 @@
-use crate::{fl, Keyboard, REFRESH_DISABLED};
-use backend::{Board, DerefCell, NelsonKind, Rgb};
+use crate::{fl, show_error_dialog, Keyboard, MainWindow, REFRESH_DISABLED};
+use backend::{
+    run_test_plan, Benchmark, BenchmarkConfig, Board, Capability, DerefCell, NelsonConfig,
+    NelsonFailure, NelsonKind, NelsonReport, Rgb, StepResult, TestPlan, TestStep, TestStop,
+};
 use cascade::cascade;
-use futures::channel::oneshot;
 use gtk::{
     glib::{self, clone},
     prelude::*,
     subclass::prelude::*,
 };
-use once_cell::sync::{Lazy, OnceCell};
+use once_cell::sync::Lazy;
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
-    sync::{atomic::Ordering, RwLock},
+    fs::File,
+    path::Path,
+    sync::atomic::Ordering,
 };
 
-struct TestResults {
-    bench: RwLock<HashMap<&'static str, Result<f64, String>>>,
-}
-
-impl TestResults {
-    fn global() -> &'static Self {
-        static TEST_RESULTS: OnceCell<TestResults> = OnceCell::new();
-        TEST_RESULTS.get_or_init(Self::new)
-    }
-
-    fn new() -> Self {
-        let test_results = Self {
-            bench: RwLock::new(HashMap::new()),
-        };
-        test_results.reset();
-        test_results
-    }
-
-    fn reset(&self) {
-        let mut bench = self.bench.write().unwrap();
-        bench.clear();
-        for port_desc in &[
-            "USB 2.0: USB-A Left",
-            "USB 2.0: USB-A Right",
-            "USB 2.0: USB-C Left",
-            "USB 2.0: USB-C Right",
-            "USB 3.2 Gen 2: USB-A Left",
-            "USB 3.2 Gen 2: USB-A Right",
-            "USB 3.2 Gen 2: USB-C Left",
-            "USB 3.2 Gen 2: USB-C Right",
-        ] {
-            bench.insert(*port_desc, Err("no benchmarks performed".to_string()));
-        }
-    }
-}
-
 #[derive(Clone, Default, glib::Boxed)]
 #[boxed_type(name = "S76TestingColor")]
 pub struct TestingColors(pub HashMap<(usize, usize), Rgb>);
@@ -59,15 +27,24 @@ pub struct TestingInner {
     board: DerefCell<Board>,
     keyboard: DerefCell<glib::WeakRef<Keyboard>>,
     reset_button: DerefCell<gtk::Button>,
+    export_button: DerefCell<gtk::Button>,
+    nelson_report: RefCell<Option<NelsonReport>>,
     usb_test: DerefCell<gtk::Box>,
     bench_button: DerefCell<gtk::ToggleButton>,
-    bench_labels: DerefCell<HashMap<&'static str, gtk::Label>>,
+    bench_list: DerefCell<gtk::ListBox>,
+    bench_write_check: DerefCell<gtk::CheckButton>,
+    bench_labels: RefCell<HashMap<String, gtk::Label>>,
+    bench: RefCell<Benchmark>,
     num_runs_spin_2: DerefCell<gtk::SpinButton>,
+    sequence_combo_2: DerefCell<gtk::ComboBoxText>,
+    custom_sequence: RefCell<Option<NelsonConfig>>,
     test_buttons: DerefCell<[gtk::Button; 2]>,
     test_labels: DerefCell<[gtk::Label; 3]>,
     selma_start_button: DerefCell<gtk::Button>,
     selma_stop_button: DerefCell<gtk::Button>,
-    selma_stop_sender: RefCell<Option<oneshot::Sender<()>>>,
+    selma_stop: RefCell<Option<TestStop>>,
+    selma_duration_spin: DerefCell<gtk::SpinButton>,
+    selma_label: DerefCell<gtk::Label>,
     selma_running: Cell<bool>,
     colors: RefCell<TestingColors>,
 }
@@ -79,29 +56,29 @@ impl ObjectSubclass for TestingInner {
     type Type = Testing;
 }
 
-impl ObjectImpl for TestingInner {
-    fn constructed(&self) {
-        fn row(widget: &impl IsA<gtk::Widget>) -> gtk::ListBoxRow {
-            cascade! {
-                gtk::ListBoxRow::new();
-                ..set_selectable(false);
-                ..set_activatable(false);
-                ..set_margin(8);
-                ..add(widget);
-            }
-        }
+fn row(widget: &impl IsA<gtk::Widget>) -> gtk::ListBoxRow {
+    cascade! {
+        gtk::ListBoxRow::new();
+        ..set_selectable(false);
+        ..set_activatable(false);
+        ..set_margin(8);
+        ..add(widget);
+    }
+}
 
-        fn label_row(label: &str, widget: &impl IsA<gtk::Widget>) -> gtk::ListBoxRow {
-            row(&cascade! {
-                gtk::Box::new(gtk::Orientation::Horizontal, 8);
-                ..add(&cascade! {
-                    gtk::Label::new(Some(label));
-                    ..set_halign(gtk::Align::Start);
-                });
-                ..pack_end(widget, false, false, 0);
-            })
-        }
+fn label_row(label: &str, widget: &impl IsA<gtk::Widget>) -> gtk::ListBoxRow {
+    row(&cascade! {
+        gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        ..add(&cascade! {
+            gtk::Label::new(Some(label));
+            ..set_halign(gtk::Align::Start);
+        });
+        ..pack_end(widget, false, false, 0);
+    })
+}
 
+impl ObjectImpl for TestingInner {
+    fn constructed(&self) {
         fn color_box(r: f64, g: f64, b: f64) -> gtk::DrawingArea {
             cascade! {
                 gtk::DrawingArea::new();
@@ -126,32 +103,37 @@ impl ObjectImpl for TestingInner {
         }
 
         let reset_button = gtk::Button::with_label("Reset Testing");
+        let export_button = cascade! {
+            gtk::Button::with_label(&fl!("test-export-nelson"));
+            ..set_sensitive(false);
+        };
 
         self.obj().add(&cascade! {
             gtk::ListBox::new();
             ..set_valign(gtk::Align::Start);
             ..style_context().add_class("frame");
-            ..add(&row(&reset_button));
+            ..add(&row(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..set_halign(gtk::Align::Center);
+                ..add(&reset_button);
+                ..add(&export_button);
+            }));
         });
 
+        // Rows for each port are added by `Testing::new`, once the board is known
         let bench_list = gtk::ListBox::new();
 
-        let mut bench_labels = HashMap::new();
-        for (port_desc, _port_result) in TestResults::global().bench.read().unwrap().iter() {
-            let bench_label = gtk::Label::new(None);
-            bench_list.add(&label_row(port_desc, &bench_label));
-            bench_labels.insert(*port_desc, bench_label);
-        }
-
         let bench_button = gtk::ToggleButton::with_label("Run USB test");
+        let bench_write_check = gtk::CheckButton::with_label(&fl!("test-usb-write"));
 
         let usb_test = cascade! {
             gtk::Box::new(gtk::Orientation::Vertical, 12);
             ..add(&gtk::Label::new(Some("USB Port Test")));
             ..add(&cascade! {
-                bench_list;
+                bench_list.clone();
                 ..set_valign(gtk::Align::Start);
                 ..style_context().add_class("frame");
+                ..add(&row(&bench_write_check));
                 ..add(&row(&bench_button));
                 ..set_header_func(Some(Box::new(header_func)));
             });
@@ -160,6 +142,27 @@ impl ObjectImpl for TestingInner {
         let num_runs_spin_2 = gtk::SpinButton::with_range(1.0, 1000.0, 1.0);
         num_runs_spin_2.set_value(100.0);
 
+        let sequence_combo_2 = cascade! {
+            gtk::ComboBoxText::new();
+            ..append(Some("standard"), &fl!("test-sequence-standard"));
+            ..append(Some("fast"), &fl!("test-sequence-fast"));
+            ..append(Some("thorough"), &fl!("test-sequence-thorough"));
+            ..set_active_id(Some("standard"));
+        };
+        let sequence_file_button = cascade! {
+            gtk::FileChooserButton::new(&fl!("test-sequence-load"), gtk::FileChooserAction::Open);
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("json"));
+                ..add_pattern("*.json");
+            });
+            ..connect_file_set(clone!(@weak self as self_ => move |button| {
+                if let Some(path) = button.filename() {
+                    self_.obj().load_custom_sequence(&path);
+                }
+            }));
+        };
+
         let test_buttons = [
             gtk::Button::with_label(&fl!("button-test")),
             gtk::Button::with_label(&fl!("button-test")),
@@ -193,6 +196,11 @@ impl ObjectImpl for TestingInner {
                 ..set_valign(gtk::Align::Start);
                 ..style_context().add_class("frame");
                 ..add(&label_row(&fl!("test-number-of-runs"), &num_runs_spin_2));
+                ..add(&label_row(&fl!("test-sequence"), &cascade! {
+                    gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                    ..add(&sequence_combo_2);
+                    ..add(&sequence_file_button);
+                }));
                 ..add(&row(&test_buttons[1]));
                 ..add(&row(&test_labels[2]));
                 ..add(&label_row(&fl!("test-check-pins"), &color_box(1., 0., 0.)));
@@ -206,6 +214,9 @@ impl ObjectImpl for TestingInner {
             gtk::Button::with_label(&fl!("button-stop"));
             ..set_sensitive(false);
         };
+        let selma_duration_spin = gtk::SpinButton::with_range(1.0, 3600.0, 1.0);
+        selma_duration_spin.set_value(30.0);
+        let selma_label = gtk::Label::new(None);
 
         let selma_test = &cascade! {
             gtk::Box::new(gtk::Orientation::Vertical, 12);
@@ -214,12 +225,14 @@ impl ObjectImpl for TestingInner {
                 gtk::ListBox::new();
                 ..set_valign(gtk::Align::Start);
                 ..style_context().add_class("frame");
+                ..add(&label_row(&fl!("test-duration"), &selma_duration_spin));
                 ..add(&row(&cascade! {
                     gtk::Box::new(gtk::Orientation::Horizontal, 8);
                     ..set_halign(gtk::Align::Center);
                     ..add(&selma_start_button);
                     ..add(&selma_stop_button);
                 }));
+                ..add(&row(&selma_label));
                 ..add(&label_row(&fl!("test-spurious-keypress"), &color_box(1., 0., 0.)));
                 ..set_header_func(Some(Box::new(header_func)));
             });
@@ -243,14 +256,19 @@ impl ObjectImpl for TestingInner {
         });
 
         self.reset_button.set(reset_button);
+        self.export_button.set(export_button);
         self.usb_test.set(usb_test);
         self.bench_button.set(bench_button);
-        self.bench_labels.set(bench_labels);
+        self.bench_list.set(bench_list);
+        self.bench_write_check.set(bench_write_check);
         self.num_runs_spin_2.set(num_runs_spin_2);
+        self.sequence_combo_2.set(sequence_combo_2);
         self.test_buttons.set(test_buttons);
         self.test_labels.set(test_labels);
         self.selma_start_button.set(selma_start_button);
         self.selma_stop_button.set(selma_stop_button);
+        self.selma_duration_spin.set(selma_duration_spin);
+        self.selma_label.set(selma_label);
 
         cascade! {
             self.obj();
@@ -289,11 +307,11 @@ glib::wrapper! {
 
 impl Testing {
     fn update_benchmarks(&self) {
-        for (port_desc, port_result) in TestResults::global().bench.read().unwrap().iter() {
-            if let Some(bench_label) = self.inner().bench_labels.get(port_desc) {
+        for (port_desc, port_result) in self.inner().bench.borrow().port_results.iter() {
+            if let Some(bench_label) = self.inner().bench_labels.borrow().get(port_desc) {
                 match port_result {
                     Ok(ok) => {
-                        bench_label.set_text(&format!("{:.2} MB/s ✅", ok));
+                        bench_label.set_text(&format!("{} ✅", ok));
                     }
                     Err(err) => {
                         bench_label.set_text(&format!("{} ❌", err));
@@ -309,55 +327,45 @@ impl Testing {
         let testing = self.inner();
 
         testing.bench_button.set_label("Running USB test");
+        testing.bench_write_check.set_sensitive(false);
+        let config = BenchmarkConfig {
+            write_test: testing.bench_write_check.is_active(),
+            ..Default::default()
+        };
 
         while testing.bench_button.is_active() {
-            match testing.board.benchmark().await {
-                Ok(benchmark) => {
+            let step = TestStep::Benchmark {
+                config: config.clone(),
+                runs: 1,
+            };
+            let result = match self.run_step(step, &TestStop::default()).await {
+                Some(result) => result,
+                None => break,
+            };
+            match result.benchmark {
+                Some(benchmark) => {
                     for (port_desc, port_result) in benchmark.port_results.iter() {
                         let text = format!("{:.2?}", port_result);
                         info!("{}: {}", port_desc, text);
-                        if let Some(bench_result) = TestResults::global()
-                            .bench
-                            .write()
-                            .unwrap()
-                            .get_mut(port_desc.as_str())
-                        {
-                            match bench_result {
-                                Ok(old) => match port_result {
-                                    Ok(new) => {
-                                        // Replace good results with better results
-                                        if new > old {
-                                            *bench_result = Ok(*new);
-                                        }
-                                    }
-                                    Err(_) => (),
-                                },
-                                Err(_err) => {
-                                    // Replace errors with newest results
-                                    *bench_result = port_result.clone();
-                                }
-                            }
-                        } else {
-                            error!("{} label result not found", port_desc);
-                        }
                     }
+                    testing.bench.borrow_mut().merge(benchmark);
+                    self.update_benchmarks();
                 }
-                Err(err) => {
-                    let message = format!("Benchmark failed to run: {}", err);
-                    error!("{}", message);
+                None => {
+                    let message = result.details.join("\n");
+                    error!("Benchmark failed to run: {}", message);
                     //TODO: have a global label?
-                    for (_, bench_label) in testing.bench_labels.iter() {
+                    for (_, bench_label) in testing.bench_labels.borrow().iter() {
                         bench_label.set_text(&message);
                     }
                 }
             }
 
-            self.update_benchmarks();
-
             glib::timeout_future(std::time::Duration::new(1, 0)).await;
         }
 
         testing.bench_button.set_label("Run USB test");
+        testing.bench_write_check.set_sensitive(true);
     }
 
     fn connect_bench_button(&self) {
@@ -377,87 +385,109 @@ impl Testing {
         self.inner().selma_start_button.set_sensitive(sensitive);
     }
 
-    async fn nelson(&self, test_runs: i32, test_index: usize, nelson_kind: NelsonKind) {
+    fn load_custom_sequence(&self, path: &Path) {
+        let res = File::open(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| NelsonConfig::from_reader(file).map_err(|err| err.to_string()));
+        match res {
+            Ok(config) => {
+                let combo = &self.inner().sequence_combo_2;
+                combo.remove_all();
+                combo.append(Some("standard"), &fl!("test-sequence-standard"));
+                combo.append(Some("fast"), &fl!("test-sequence-fast"));
+                combo.append(Some("thorough"), &fl!("test-sequence-thorough"));
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                combo.append(Some("custom"), &name);
+                combo.set_active_id(Some("custom"));
+                self.inner().custom_sequence.replace(Some(config));
+            }
+            Err(err) => {
+                let window = self
+                    .toplevel()
+                    .and_then(|x| x.downcast::<gtk::Window>().ok());
+                if let Some(window) = window {
+                    show_error_dialog(&window, &fl!("error-load-sequence"), err);
+                }
+            }
+        }
+    }
+
+    fn selected_sequence(&self) -> NelsonConfig {
+        match self.inner().sequence_combo_2.active_id().as_deref() {
+            Some("fast") => NelsonConfig::fast(),
+            Some("thorough") => NelsonConfig::thorough(),
+            Some("custom") => self.inner().custom_sequence.borrow().clone().unwrap(),
+            _ => NelsonKind::Normal.into(),
+        }
+    }
+
+    /// Run `step` as a test plan of its own, as the headless test runner would
+    async fn run_step(&self, step: TestStep, stop: &TestStop) -> Option<StepResult> {
+        let window = self.toplevel()?.downcast::<MainWindow>().ok()?;
+        let plan = TestPlan { steps: vec![step] };
+        let board = &self.inner().board;
+        let mut result = run_test_plan(window.backend(), board, &plan, "", stop).await;
+        result.steps.pop()
+    }
+
+    async fn nelson(&self, test_runs: i32, test_index: usize, config: NelsonConfig) {
         let testing = self.inner();
 
         info!("Disabling test buttons");
         self.test_buttons_sensitive(false);
 
         let test_label = &testing.test_labels[test_index];
+        test_label.set_text(&format!("Running {} tests", test_runs));
 
-        info!("Disable keyboard input events");
-        self.set_no_input(true).await;
-
-        for test_run in 1..=test_runs {
-            let message = format!("Test {}/{} running", test_run, test_runs);
-            info!("{}", message);
-            test_label.set_text(&message);
-
-            let nelson = match testing.board.nelson(nelson_kind).await {
-                Ok(ok) => ok,
-                Err(err) => {
-                    let message = format!("Test {}/{} failed to run: {}", test_run, test_runs, err);
-                    error!("{}", message);
-                    test_label.set_text(&message);
-                    break;
-                }
-            };
-
-            for row in 0..nelson.max_rows() {
-                for col in 0..nelson.max_cols() {
-                    let r = if nelson.missing.get(row, col).unwrap_or(false) {
-                        255
-                    } else {
-                        0
-                    };
-                    let g = if nelson.sticking.get(row, col).unwrap_or(false) {
-                        255
-                    } else {
-                        0
-                    };
-                    let b = if nelson.bouncing.get(row, col).unwrap_or(false) {
-                        255
-                    } else {
-                        0
-                    };
-                    if r != 0 || g != 0 || b != 0 {
-                        testing
-                            .colors
-                            .borrow_mut()
-                            .0
-                            .insert((row, col), Rgb::new(r, g, b));
-                    } else {
-                        testing.colors.borrow_mut().0.remove(&(row, col));
-                    }
-                }
+        let step = TestStep::Nelson {
+            config,
+            runs: test_runs as u32,
+        };
+        if let Some(result) = self.run_step(step, &TestStop::default()).await {
+            if let Some(report) = &result.nelson {
+                self.show_nelson_findings(report);
             }
-
-            self.notify("colors");
-
-            if nelson.success(testing.board.layout().layout()) {
-                let message = format!("Test {}/{} successful", test_run, test_runs);
+            let message = result.details.join("\n");
+            if result.success {
                 info!("{}", message);
-                test_label.set_text(&message);
             } else {
-                let message = format!("Test {}/{} failed", test_run, test_runs);
                 error!("{}", message);
-                test_label.set_text(&message);
-                break;
             }
-        }
+            test_label.set_text(&message);
 
-        info!("Re-enable keyboard input events");
-        self.set_no_input(false).await;
+            testing.nelson_report.replace(result.nelson);
+            testing.export_button.set_sensitive(true);
+        }
 
         info!("Enabling test buttons");
         self.test_buttons_sensitive(true);
     }
 
+    /// Color keys by their failures in the last run of `report`
+    fn show_nelson_findings(&self, report: &NelsonReport) {
+        let mut colors = self.inner().colors.borrow_mut();
+        colors.0.clear();
+        for finding in report.runs.last().iter().flat_map(|x| &x.findings) {
+            let (row, col) = finding.electrical;
+            let color = colors
+                .0
+                .entry((row as usize, col as usize))
+                .or_insert(Rgb::new(0, 0, 0));
+            match finding.failure {
+                NelsonFailure::Missing => color.r = 255,
+                NelsonFailure::Sticking => color.g = 255,
+                NelsonFailure::Bouncing => color.b = 255,
+            }
+        }
+        drop(colors);
+        self.notify("colors");
+    }
+
     fn connect_test_button_1(&self) {
         self.inner().test_buttons[0].connect_clicked(clone!(@strong self as self_ => move |_| {
             glib::MainContext::default().spawn_local(clone!(@strong self_ => async move {
                 REFRESH_DISABLED.store(true, Ordering::Relaxed);
-                self_.nelson(1, 0, NelsonKind::Normal).await;
+                self_.nelson(1, 0, NelsonKind::Normal.into()).await;
                 REFRESH_DISABLED.store(false, Ordering::Relaxed);
             }));
         }));
@@ -470,7 +500,7 @@ impl Testing {
                 self_.nelson(
                     self_.inner().num_runs_spin_2.value_as_int(),
                     2,
-                    NelsonKind::Normal,
+                    self_.selected_sequence(),
                 ).await;
                 REFRESH_DISABLED.store(false, Ordering::Relaxed);
             }));
@@ -500,28 +530,33 @@ impl Testing {
 
         info!("Disabling test buttons");
         self.test_buttons_sensitive(false);
-        testing.selma_stop_button.set_sensitive(true);
-
-        info!("Disable keyboard input events");
-        self.set_no_input(true).await;
 
+        let duration_secs = testing.selma_duration_spin.value_as_int() as u64;
+        testing
+            .selma_label
+            .set_text(&format!("Running for {} seconds", duration_secs));
         testing.colors.borrow_mut().0.clear();
         self.selma_update_colors();
-        self.inner().selma_running.set(true);
+        testing.selma_running.set(true);
 
-        // Wait for stop button to be pressed
-        let (sender, reciever) = oneshot::channel();
-        *testing.selma_stop_sender.borrow_mut() = Some(sender);
-        let _ = reciever.await;
+        let stop = TestStop::default();
+        testing.selma_stop.replace(Some(stop.clone()));
+        testing.selma_stop_button.set_sensitive(true);
 
-        self.inner().selma_running.set(false);
+        let step = TestStep::Selma { duration_secs };
+        if let Some(mut result) = self.run_step(step, &stop).await {
+            if result.success {
+                result.details.push("No spurious keypresses".to_string());
+            }
+            testing.selma_label.set_text(&result.details.join("\n"));
+        }
 
-        info!("Re-enable keyboard input events");
-        self.set_no_input(false).await;
+        testing.selma_running.set(false);
+        testing.selma_stop.replace(None);
+        testing.selma_stop_button.set_sensitive(false);
 
         info!("Enabling test buttons");
         self.test_buttons_sensitive(true);
-        testing.selma_stop_button.set_sensitive(false);
     }
 
     fn connect_selma_buttons(&self) {
@@ -536,20 +571,71 @@ impl Testing {
         self.inner()
             .selma_stop_button
             .connect_clicked(clone!(@strong self as self_ => move |_| {
-                glib::MainContext::default().spawn_local(clone!(@strong self_ => async move {
-                    if let Some(sender) = self_.inner().selma_stop_sender.borrow_mut().take() {
-                        let _ = sender.send(());
-                    }
-                }));
+                if let Some(stop) = self_.inner().selma_stop.borrow().as_ref() {
+                    stop.stop();
+                }
             }));
     }
 
+    fn export_nelson_report(&self) {
+        let chooser = cascade! {
+            gtk::FileChooserNative::new(Some(&fl!("test-export-nelson")), None::<&gtk::Window>, gtk::FileChooserAction::Save, Some(&fl!("button-export")), Some(&fl!("button-cancel")));
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("JUnit XML"));
+                ..add_pattern("*.xml");
+            });
+            ..add_filter(cascade! {
+                gtk::FileFilter::new();
+                ..set_name(Some("json"));
+                ..add_pattern("*.json");
+            });
+            ..set_current_name("nelson.xml");
+            ..set_do_overwrite_confirmation(true);
+        };
+
+        if chooser.run() == gtk::ResponseType::Accept {
+            let path = chooser.filename().unwrap();
+            let report = self.inner().nelson_report.borrow();
+            let report = match report.as_ref() {
+                Some(report) => report,
+                None => return,
+            };
+
+            let res = File::create(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|file| {
+                    if path.extension().map_or(false, |ext| ext == "json") {
+                        report
+                            .to_json_writer_pretty(file)
+                            .map_err(|err| err.to_string())
+                    } else {
+                        report.to_junit_writer(file).map_err(|err| err.to_string())
+                    }
+                });
+            if let Err(err) = res {
+                let window = self
+                    .toplevel()
+                    .and_then(|x| x.downcast::<gtk::Window>().ok());
+                if let Some(window) = window {
+                    show_error_dialog(&window, &fl!("error-export-nelson"), err);
+                }
+            }
+        }
+    }
+
     fn connect_reset_button(&self) {
         let obj_btn = self.clone();
         self.inner().reset_button.connect_clicked(move |_button| {
-            TestResults::global().reset();
+            obj_btn.reset_benchmarks();
             obj_btn.update_benchmarks();
         });
+
+        self.inner()
+            .export_button
+            .connect_clicked(clone!(@weak self as self_ => move |_| {
+                self_.export_nelson_report();
+            }));
     }
 
     pub fn new(board: &Board, keyboard: &Keyboard) -> Self {
@@ -561,13 +647,49 @@ impl Testing {
         obj.connect_test_button_2();
         obj.connect_selma_buttons();
         obj.connect_reset_button();
+        obj.add_bench_rows();
+        obj.reset_benchmarks();
         obj.update_benchmarks();
-        if board.is_lite() {
+        if !board.capabilities().has(Capability::UsbHub) {
             obj.inner().usb_test.set_sensitive(false);
         }
         obj
     }
 
+    /// Descriptions of the ports tested by the USB test, from the board's hub layout
+    fn port_descs(&self) -> Vec<String> {
+        self.inner()
+            .board
+            .usb_hub()
+            .map(|x| x.port_descs())
+            .unwrap_or_default()
+    }
+
+    fn reset_benchmarks(&self) {
+        let mut bench = self.inner().bench.borrow_mut();
+        bench.port_results.clear();
+        for port_desc in self.port_descs() {
+            bench
+                .port_results
+                .insert(port_desc, Err("no benchmarks performed".to_string()));
+        }
+    }
+
+    fn add_bench_rows(&self) {
+        let inner = self.inner();
+        let port_descs = self.port_descs();
+
+        let mut bench_labels = inner.bench_labels.borrow_mut();
+        for (i, port_desc) in port_descs.into_iter().enumerate() {
+            let bench_label = gtk::Label::new(None);
+            inner
+                .bench_list
+                .insert(&label_row(&port_desc, &bench_label), i as i32);
+            bench_labels.insert(port_desc, bench_label);
+        }
+        inner.bench_list.show_all();
+    }
+
     fn inner(&self) -> &TestingInner {
         TestingInner::from_obj(self)
     }
@@ -576,10 +698,4 @@ impl Testing {
     fn keyboard(&self) -> Keyboard {
         self.inner().keyboard.upgrade().unwrap()
     }
-
-    async fn set_no_input(&self, no_input: bool) {
-        if let Err(err) = self.inner().board.set_no_input(no_input).await {
-            error!("Error setting no input mode: {}", err);
-        }
-    }
 }
@@ -0,0 +1,177 @@
+use std::{
+    convert::TryFrom,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use backend::{
+    import_qmk_info, layout_search_path, layouts, user_layouts, KeyMap, Layout, QmkKeymap,
+};
+
+const USAGE: &str = "Usage: layouts validate [--firmware VERSION] [BOARD...]
+       layouts import-qmk [--kle PHYSICAL_JSON] [--output DIR] BOARD INFO_JSON KEYMAP_JSON
+       layouts export-qmk [--firmware VERSION] KEYMAP_JSON DIR
+
+validate: Check the data files of each layout, or every built-in and user
+layout if no BOARD is given. Scancodes are checked against the keymap used by
+firmware VERSION, which defaults to the newest firmware.
+
+import-qmk: Generate the layout of BOARD, like acme/macropad, from a QMK
+keyboard's info.json and a keymap.json, writing it to DIR (by default the
+first directory the Configurator searches for layouts: the first entry of
+SYSTEM76_KEYBOARD_LAYOUT_PATH, or `layouts` in the data directory). Key positions are taken from info.json, or from a KLE file listing
+the same keys in the same order.
+
+export-qmk: Convert a keymap exported by the Configurator into a QMK
+keymap.json and keymap.c in DIR, using the layout of its board for firmware
+VERSION.";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("Failed to read '{}': {}", path, err);
+        process::exit(1);
+    })
+}
+
+/// Print problems with each layout, returning `false` if any were found
+fn validate(boards: &[String], version: &str) -> bool {
+    let mut ok = true;
+    for board in boards {
+        match Layout::from_board(board, version) {
+            Ok(layout) => {
+                let lints = layout.lint();
+                if lints.is_empty() {
+                    println!("{}: ok", board);
+                }
+                for lint in lints {
+                    println!("{}: {}", board, lint);
+                    ok = false;
+                }
+            }
+            Err(err) => {
+                println!("{}: {}", board, err);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Import a QMK keyboard, then check the result
+fn import_qmk(mut args: impl Iterator<Item = String>) {
+    let mut kle = None;
+    let mut output = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kle" => kle = Some(args.next().unwrap_or_else(|| usage())),
+            "--output" => output = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ => positional.push(arg),
+        }
+    }
+    let (board, info, keymap) = match positional.as_slice() {
+        [board, info, keymap] => (board, read(info), read(keymap)),
+        _ => usage(),
+    };
+    let kle = kle.as_deref().map(read);
+    let output = match output.or_else(|| layout_search_path().into_iter().next()) {
+        Some(output) => output,
+        None => {
+            eprintln!("No data directory for user layouts, pass --output");
+            process::exit(1);
+        }
+    };
+
+    let res = import_qmk_info(board, &info, &keymap, kle.as_deref())
+        .and_then(|files| files.write_dir(&output));
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    println!("Wrote {} to {}", board, output.display());
+
+    match Layout::from_dir(board, &output, "dummy") {
+        Ok(layout) => {
+            for lint in layout.lint() {
+                println!("{}: {}", board, lint);
+            }
+        }
+        Err(err) => {
+            println!("{}: {}", board, err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Write a Configurator keymap as QMK `keymap.json` and `keymap.c`
+fn export_qmk(mut args: impl Iterator<Item = String>) {
+    let mut version = "dummy".to_string();
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--firmware" => version = args.next().unwrap_or_else(|| usage()),
+            _ => positional.push(arg),
+        }
+    }
+    let (keymap, dir) = match positional.as_slice() {
+        [keymap, dir] => (read(keymap), Path::new(dir)),
+        _ => usage(),
+    };
+
+    let res = KeyMap::try_from(keymap.as_str())
+        .map_err(|err| format!("Failed to parse keymap: {}", err))
+        .and_then(|keymap| {
+            let layout = Layout::from_board(&keymap.model, &version)?;
+            let qmk = QmkKeymap::from_keymap(&keymap, &layout)?;
+            fs::create_dir_all(dir)
+                .and_then(|_| fs::write(dir.join("keymap.json"), qmk.to_json_pretty()))
+                .and_then(|_| fs::write(dir.join("keymap.c"), qmk.to_keymap_c(&layout)))
+                .map_err(|err| format!("Failed to write to '{}': {}", dir.display(), err))
+        });
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("validate") => {}
+        Some("import-qmk") => return import_qmk(args),
+        Some("export-qmk") => return export_qmk(args),
+        Some("-h" | "--help") => return println!("{}", USAGE),
+        _ => usage(),
+    }
+
+    let mut version = "dummy".to_string();
+    let mut boards = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--firmware" => version = args.next().unwrap_or_else(|| usage()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => boards.push(arg),
+        }
+    }
+    if boards.is_empty() {
+        boards = layouts().iter().map(|x| x.to_string()).collect();
+        for board in user_layouts() {
+            if !boards.contains(&board) {
+                boards.push(board);
+            }
+        }
+    }
+
+    if !validate(&boards, &version) {
+        process::exit(1);
+    }
+}